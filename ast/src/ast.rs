@@ -25,12 +25,13 @@ pub enum BinaryOperation {
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum UnaryOperation {
     Negate,
+    Plus,
 
     Not,
 }
 
 #[repr(u8)]
-#[derive(Hash, Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Hash, Copy, Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
 pub enum PrimitiveType {
     Nil,
     Bool,
@@ -51,6 +52,8 @@ pub enum TypeSignature {
     Primitive(PrimitiveType),
     Function(FunctionSignature),
     Custom(String),
+    // element type
+    Array(Box<TypeSignature>),
 }
 
 impl<'a> TypeSignature {
@@ -222,6 +225,7 @@ impl std::fmt::Debug for TypeSignature {
                 }
             }
             TypeSignature::Custom(c) => write!(f, "{}", c),
+            TypeSignature::Array(elem) => write!(f, "[{:?}]", elem),
         }
     }
 }
@@ -383,6 +387,15 @@ pub enum Ast {
     // while cond, while expr
     While(Box<AstNode>, Box<AstNode>),
 
+    // init expr, cond, step expr, body expr
+    For(Box<AstNode>, Box<AstNode>, Box<AstNode>, Box<AstNode>),
+
+    // exits the innermost loop
+    Break,
+
+    // jumps to the condition of the innermost loop
+    Continue,
+
     // name, variable signature, optional value expr
     VarDecl(String, VariableSignature, Option<Box<AstNode>>),
 
@@ -400,6 +413,21 @@ pub enum Ast {
 
     // expression, type to cast to
     As(Box<AstNode>, TypeSignature),
+
+    // element exprs
+    Array(Vec<AstNode>),
+
+    // expression that evaluates to an array, index expr
+    Index(Box<AstNode>, Box<AstNode>),
+
+    // struct name, field names and types
+    StructDef(String, Vec<(String, TypeSignature)>),
+
+    // struct name, field names and value exprs
+    StructInit(String, Vec<(String, AstNode)>),
+
+    // expression that evaluates to a struct, field name
+    FieldAccess(Box<AstNode>, String),
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -409,6 +437,119 @@ pub struct AstNode {
     pub type_sig: Option<TypeSignature>,
 }
 
+impl AstNode {
+    pub fn children(&self) -> Vec<&AstNode> {
+        match &self.node {
+            Ast::Identifier(_)
+            | Ast::Integer(_)
+            | Ast::Float(_)
+            | Ast::String(_)
+            | Ast::Bool(_)
+            | Ast::Break
+            | Ast::Continue
+            | Ast::FnExtern(_, _)
+            | Ast::StructDef(_, _) => Vec::new(),
+            Ast::Statement(expr)
+            | Ast::Unary(_, expr)
+            | Ast::Return(expr)
+            | Ast::As(expr, _)
+            | Ast::FieldAccess(expr, _) => vec![&**expr],
+            Ast::FnDef(_, _, expr) => vec![&**expr],
+            Ast::Binary(_, l, r) | Ast::While(l, r) | Ast::Index(l, r) => vec![&**l, &**r],
+            Ast::For(init, cond, step, expr) => vec![&**init, &**cond, &**step, &**expr],
+            Ast::Block(exprs) | Ast::Array(exprs) => exprs.iter().collect(),
+            Ast::IfElse(ifcond, ifexpr, elseifs, elseexpr) => {
+                let mut children = vec![&**ifcond, &**ifexpr];
+                for (cond, expr) in elseifs {
+                    children.push(&**cond);
+                    children.push(&**expr);
+                }
+                if let Some(eexpr) = elseexpr {
+                    children.push(&**eexpr);
+                }
+                children
+            }
+            Ast::VarDecl(_, _, expr) => expr.iter().map(|e| &**e).collect(),
+            Ast::Import(module) => module.expressions.iter().collect(),
+            Ast::FnCall(callee, args) => {
+                let mut children = vec![&**callee];
+                children.extend(args.iter());
+                children
+            }
+            Ast::StructInit(_, fields) => fields.iter().map(|(_, value)| value).collect(),
+        }
+    }
+
+    pub fn children_mut(&mut self) -> Vec<&mut AstNode> {
+        match &mut self.node {
+            Ast::Identifier(_)
+            | Ast::Integer(_)
+            | Ast::Float(_)
+            | Ast::String(_)
+            | Ast::Bool(_)
+            | Ast::Break
+            | Ast::Continue
+            | Ast::FnExtern(_, _)
+            | Ast::StructDef(_, _) => Vec::new(),
+            Ast::Statement(expr)
+            | Ast::Unary(_, expr)
+            | Ast::Return(expr)
+            | Ast::As(expr, _)
+            | Ast::FieldAccess(expr, _) => vec![&mut **expr],
+            Ast::FnDef(_, _, expr) => vec![&mut **expr],
+            Ast::Binary(_, l, r) | Ast::While(l, r) | Ast::Index(l, r) => {
+                vec![&mut **l, &mut **r]
+            }
+            Ast::For(init, cond, step, expr) => {
+                vec![&mut **init, &mut **cond, &mut **step, &mut **expr]
+            }
+            Ast::Block(exprs) | Ast::Array(exprs) => exprs.iter_mut().collect(),
+            Ast::IfElse(ifcond, ifexpr, elseifs, elseexpr) => {
+                let mut children = vec![&mut **ifcond, &mut **ifexpr];
+                for (cond, expr) in elseifs {
+                    children.push(&mut **cond);
+                    children.push(&mut **expr);
+                }
+                if let Some(eexpr) = elseexpr {
+                    children.push(&mut **eexpr);
+                }
+                children
+            }
+            Ast::VarDecl(_, _, expr) => expr.iter_mut().map(|e| &mut **e).collect(),
+            Ast::Import(module) => module.expressions.iter_mut().collect(),
+            Ast::FnCall(callee, args) => {
+                let mut children = vec![&mut **callee];
+                children.extend(args.iter_mut());
+                children
+            }
+            Ast::StructInit(_, fields) => fields.iter_mut().map(|(_, value)| value).collect(),
+        }
+    }
+
+    // walks the tree without recursion so it is safe to call on trees too deep to compile
+    pub fn depth(&self) -> usize {
+        let mut max_depth = 0;
+        let mut stack = vec![(self, 1)];
+        while let Some((node, depth)) = stack.pop() {
+            max_depth = max_depth.max(depth);
+            for child in node.children() {
+                stack.push((child, depth + 1));
+            }
+        }
+        max_depth
+    }
+
+    pub fn node_count(&self) -> usize {
+        let mut count = 0;
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            count += 1;
+            stack.extend(node.children());
+        }
+        count
+    }
+}
+
 impl std::hash::Hash for AstNode {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         self.pos.hash(state);
@@ -8,5 +8,10 @@ pub use ast::{
     UnaryOperation, VariableSignature,
 };
 pub mod semantic;
+pub mod visitor;
+pub use visitor::{Visitor, VisitorMut};
+
+mod printer;
+mod unused;
 
 mod new_ast;
\ No newline at end of file
@@ -0,0 +1,396 @@
+use super::ast::{Ast, AstNode, BinaryOperation, Module, TypeSignature, UnaryOperation};
+
+const INDENT: &str = "    ";
+
+// binding strength of each kind of expression, mirrors the precedences used by the parser
+const PREC_NONE: u8 = 0;
+const PREC_ASSIGNMENT: u8 = 1;
+const PREC_OR: u8 = 2;
+const PREC_AND: u8 = 3;
+const PREC_EQUALITY: u8 = 4;
+const PREC_COMPARISON: u8 = 5;
+const PREC_TERM: u8 = 6;
+const PREC_FACTOR: u8 = 7;
+const PREC_UNARY: u8 = 8;
+const PREC_CALL: u8 = 9;
+const PREC_PRIMARY: u8 = 10;
+
+impl Module {
+    /// Renders the module back into source text, one top level expression per line
+    pub fn to_source(&self) -> String {
+        self.expressions
+            .iter()
+            .map(|e| e.node.to_source())
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+impl Ast {
+    /// Renders the node back into source text that parses to an equivalent tree
+    pub fn to_source(&self) -> String {
+        let mut out = String::new();
+        write_node(&mut out, self, 0);
+        out
+    }
+}
+
+fn binary_precedence(op: BinaryOperation) -> u8 {
+    match op {
+        BinaryOperation::Add | BinaryOperation::Subtract => PREC_TERM,
+        BinaryOperation::Multiply | BinaryOperation::Divide => PREC_FACTOR,
+        // the parser gives != the precedence of the comparison operators rather than ==
+        BinaryOperation::Less
+        | BinaryOperation::LessEqual
+        | BinaryOperation::Greater
+        | BinaryOperation::GreaterEqual
+        | BinaryOperation::NotEqual => PREC_COMPARISON,
+        BinaryOperation::Equal => PREC_EQUALITY,
+        BinaryOperation::And => PREC_AND,
+        BinaryOperation::Or => PREC_OR,
+        BinaryOperation::Assign => PREC_ASSIGNMENT,
+    }
+}
+
+fn binary_operator(op: BinaryOperation) -> &'static str {
+    match op {
+        BinaryOperation::Add => "+",
+        BinaryOperation::Subtract => "-",
+        BinaryOperation::Multiply => "*",
+        BinaryOperation::Divide => "/",
+        BinaryOperation::Less => "<",
+        BinaryOperation::LessEqual => "<=",
+        BinaryOperation::Greater => ">",
+        BinaryOperation::GreaterEqual => ">=",
+        BinaryOperation::Equal => "==",
+        BinaryOperation::NotEqual => "!=",
+        BinaryOperation::And => "and",
+        BinaryOperation::Or => "or",
+        BinaryOperation::Assign => "=",
+    }
+}
+
+fn precedence(ast: &Ast) -> u8 {
+    match ast {
+        Ast::Identifier(_)
+        | Ast::Integer(_)
+        | Ast::Float(_)
+        | Ast::String(_)
+        | Ast::Bool(_)
+        | Ast::Block(_)
+        | Ast::Array(_)
+        | Ast::StructInit(..)
+        | Ast::Break
+        | Ast::Continue => PREC_PRIMARY,
+        Ast::FnCall(..) | Ast::Index(..) | Ast::FieldAccess(..) => PREC_CALL,
+        Ast::Unary(..) => PREC_UNARY,
+        Ast::As(..) => PREC_FACTOR,
+        Ast::Binary(op, _, _) => binary_precedence(*op),
+        // keyword expressions read as much as they can to their right so they always need grouping
+        _ => PREC_NONE,
+    }
+}
+
+// operands of an infix operator are parsed at the operator's precedence and the parser
+// folds operators of equal precedence to the right, so a left operand has to bind tighter
+fn write_operand(out: &mut String, ast: &Ast, indent: usize, min_prec: u8) {
+    if precedence(ast) < min_prec {
+        out.push('(');
+        write_node(out, ast, indent);
+        out.push(')');
+    } else {
+        write_node(out, ast, indent);
+    }
+}
+
+fn write_type(out: &mut String, type_sig: &TypeSignature) {
+    match type_sig {
+        TypeSignature::Function(sig) => {
+            out.push('(');
+            for (idx, param) in sig.params.iter().enumerate() {
+                if idx > 0 {
+                    out.push_str(", ");
+                }
+                if param.mutable {
+                    out.push_str("mut ");
+                }
+                if let Some(param_type) = &param.type_sig {
+                    write_type(out, param_type);
+                }
+            }
+            out.push_str(") -> ");
+            match &sig.return_type {
+                Some(return_type) => write_type(out, return_type),
+                None => out.push_str("Nil"),
+            }
+        }
+        TypeSignature::Array(elem) => {
+            out.push('[');
+            write_type(out, elem);
+            out.push(']');
+        }
+        _ => out.push_str(&format!("{:?}", type_sig)),
+    }
+}
+
+fn write_indent(out: &mut String, indent: usize) {
+    for _ in 0..indent {
+        out.push_str(INDENT);
+    }
+}
+
+fn is_block(node: &AstNode) -> bool {
+    matches!(node.node, Ast::Block(_))
+}
+
+fn write_node(out: &mut String, ast: &Ast, indent: usize) {
+    match ast {
+        Ast::Identifier(name) => out.push_str(name),
+        Ast::Integer(n) => out.push_str(&n.to_string()),
+        // debug formatting keeps the decimal point so the literal is still read as a float
+        Ast::Float(n) => out.push_str(&format!("{:?}", n)),
+        Ast::String(s) => {
+            out.push('"');
+            for c in s.chars() {
+                match c {
+                    '\n' => out.push_str("\\n"),
+                    '\t' => out.push_str("\\t"),
+                    '\r' => out.push_str("\\r"),
+                    '\0' => out.push_str("\\0"),
+                    '\\' => out.push_str("\\\\"),
+                    '"' => out.push_str("\\\""),
+                    c => out.push(c),
+                }
+            }
+            out.push('"');
+        }
+        Ast::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Ast::Statement(expr) => {
+            write_node(out, &expr.node, indent);
+            out.push(';');
+        }
+        Ast::Binary(op, l, r) => {
+            let prec = binary_precedence(*op);
+            write_operand(out, &l.node, indent, prec + 1);
+            out.push(' ');
+            out.push_str(binary_operator(*op));
+            out.push(' ');
+            write_operand(out, &r.node, indent, prec);
+        }
+        Ast::Unary(op, expr) => {
+            out.push_str(match op {
+                UnaryOperation::Negate => "-",
+                UnaryOperation::Plus => "+",
+                UnaryOperation::Not => "!",
+            });
+            write_operand(out, &expr.node, indent, PREC_UNARY);
+        }
+        Ast::Return(expr) => {
+            out.push_str("return ");
+            write_node(out, &expr.node, indent);
+        }
+        Ast::Block(exprs) => {
+            if exprs.is_empty() {
+                out.push_str("{}");
+                return;
+            }
+            out.push_str("{\n");
+            for e in exprs {
+                write_indent(out, indent + 1);
+                write_node(out, &e.node, indent + 1);
+                out.push('\n');
+            }
+            write_indent(out, indent);
+            out.push('}');
+        }
+        Ast::IfElse(ifcond, ifexpr, elseifs, elseexpr) => {
+            let blocks = is_block(ifexpr)
+                && elseifs.iter().all(|(_, expr)| is_block(expr))
+                && elseexpr.iter().all(|e| is_block(e));
+            if !blocks && elseifs.is_empty() && elseexpr.is_some() {
+                // branches that aren't blocks came from the conditional operator
+                write_operand(out, &ifcond.node, indent, PREC_ASSIGNMENT + 1);
+                out.push_str(" ? ");
+                write_operand(out, &ifexpr.node, indent, PREC_ASSIGNMENT);
+                out.push_str(" : ");
+                write_operand(
+                    out,
+                    &elseexpr.as_ref().unwrap().node,
+                    indent,
+                    PREC_ASSIGNMENT,
+                );
+                return;
+            }
+
+            out.push_str("if ");
+            write_node(out, &ifcond.node, indent);
+            out.push(' ');
+            write_node(out, &ifexpr.node, indent);
+            for (cond, expr) in elseifs {
+                out.push_str(" else if ");
+                write_node(out, &cond.node, indent);
+                out.push(' ');
+                write_node(out, &expr.node, indent);
+            }
+            if let Some(eexpr) = elseexpr {
+                out.push_str(" else ");
+                write_node(out, &eexpr.node, indent);
+            }
+        }
+        Ast::While(cond, expr) => {
+            out.push_str("while ");
+            write_node(out, &cond.node, indent);
+            out.push(' ');
+            write_node(out, &expr.node, indent);
+        }
+        Ast::For(init, cond, step, expr) => {
+            out.push_str("for ");
+            write_node(out, &init.node, indent);
+            out.push_str("; ");
+            write_node(out, &cond.node, indent);
+            out.push_str("; ");
+            write_node(out, &step.node, indent);
+            out.push(' ');
+            write_node(out, &expr.node, indent);
+        }
+        Ast::Break => out.push_str("break"),
+        Ast::Continue => out.push_str("continue"),
+        Ast::VarDecl(name, var_sig, set_expr) => {
+            out.push_str("let ");
+            if var_sig.mutable {
+                out.push_str("mut ");
+            }
+            out.push_str(name);
+            if let Some(type_sig) = &var_sig.type_sig {
+                out.push_str(": ");
+                write_type(out, type_sig);
+            }
+            if let Some(se) = set_expr {
+                out.push_str(" = ");
+                write_node(out, &se.node, indent);
+            }
+        }
+        Ast::Import(module) => {
+            out.push_str("import \"");
+            if let Some(file) = &module.file {
+                out.push_str(file);
+            }
+            out.push('"');
+        }
+        Ast::FnDef(sig, param_names, body) => {
+            out.push('(');
+            for (idx, (param, name)) in sig.params.iter().zip(param_names.iter()).enumerate() {
+                if idx > 0 {
+                    out.push_str(", ");
+                }
+                out.push_str(name);
+                if let Some(type_sig) = &param.type_sig {
+                    out.push_str(": ");
+                    if param.mutable {
+                        out.push_str("mut ");
+                    }
+                    write_type(out, type_sig);
+                }
+            }
+            out.push(')');
+            if let Some(return_type) = &sig.return_type {
+                out.push_str(" -> ");
+                write_type(out, return_type);
+            }
+            out.push(' ');
+            write_node(out, &body.node, indent);
+        }
+        Ast::FnExtern(sig, name) => {
+            // extern declarations don't keep their parameter names so placeholders are used
+            out.push_str("extern ");
+            out.push_str(name);
+            out.push('(');
+            for (idx, param) in sig.params.iter().enumerate() {
+                if idx > 0 {
+                    out.push_str(", ");
+                }
+                out.push_str(&format!("p{}", idx));
+                if let Some(type_sig) = &param.type_sig {
+                    out.push_str(": ");
+                    if param.mutable {
+                        out.push_str("mut ");
+                    }
+                    write_type(out, type_sig);
+                }
+            }
+            out.push_str(") -> ");
+            match &sig.return_type {
+                Some(return_type) => write_type(out, return_type),
+                None => out.push_str("Nil"),
+            }
+        }
+        Ast::FnCall(callee, args) => {
+            write_operand(out, &callee.node, indent, PREC_CALL);
+            out.push('(');
+            for (idx, arg) in args.iter().enumerate() {
+                if idx > 0 {
+                    out.push_str(", ");
+                }
+                write_node(out, &arg.node, indent);
+            }
+            out.push(')');
+        }
+        Ast::Array(elems) => {
+            out.push('[');
+            for (idx, elem) in elems.iter().enumerate() {
+                if idx > 0 {
+                    out.push_str(", ");
+                }
+                write_node(out, &elem.node, indent);
+            }
+            out.push(']');
+        }
+        Ast::Index(array, index) => {
+            write_operand(out, &array.node, indent, PREC_CALL);
+            out.push('[');
+            write_node(out, &index.node, indent);
+            out.push(']');
+        }
+        Ast::StructDef(name, fields) => {
+            out.push_str("struct ");
+            out.push_str(name);
+            if fields.is_empty() {
+                out.push_str(" {}");
+                return;
+            }
+            out.push_str(" {\n");
+            for (field, type_sig) in fields {
+                write_indent(out, indent + 1);
+                out.push_str(field);
+                out.push_str(": ");
+                write_type(out, type_sig);
+                out.push_str(",\n");
+            }
+            write_indent(out, indent);
+            out.push('}');
+        }
+        Ast::StructInit(name, fields) => {
+            out.push_str(name);
+            out.push_str(" { ");
+            for (idx, (field, value)) in fields.iter().enumerate() {
+                if idx > 0 {
+                    out.push_str(", ");
+                }
+                out.push_str(field);
+                out.push_str(": ");
+                write_node(out, &value.node, indent);
+            }
+            out.push_str(" }");
+        }
+        Ast::FieldAccess(expr, field) => {
+            write_operand(out, &expr.node, indent, PREC_CALL);
+            out.push('.');
+            out.push_str(field);
+        }
+        Ast::As(expr, type_sig) => {
+            write_operand(out, &expr.node, indent, PREC_FACTOR + 1);
+            out.push_str(" as ");
+            write_type(out, type_sig);
+        }
+    }
+}
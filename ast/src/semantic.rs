@@ -1,6 +1,8 @@
+use super::unused::unused_variables;
 use super::{ast, Notice, NoticeLevel};
+use core::GravitonError;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 const DEFAULT_NUM_PRIMITIVE_SIGNATURE: ast::PrimitiveType = ast::PrimitiveType::I32;
 const DEFAULT_NUM_TYPE_SIGNATURE: ast::TypeSignature =
@@ -15,6 +17,8 @@ const FLOAT_TYPE_SIGNATURE: ast::TypeSignature =
 #[derive(Debug, Clone, Default)]
 pub struct SemanticStdLib {
     variables: HashMap<String, (bool, ast::TypeSignature)>,
+    structs: HashMap<String, Vec<(String, ast::TypeSignature)>>,
+    uninferred: HashSet<String>,
 }
 
 impl SemanticStdLib {
@@ -22,20 +26,36 @@ impl SemanticStdLib {
         self.variables
             .insert(name, (false, ast::TypeSignature::Function(sig)));
     }
+
+    // variables the host defines before running, like `StackVm::define_global`, can't be assigned
+    pub fn add_var(&mut self, name: String, type_sig: ast::TypeSignature) {
+        self.variables.insert(name, (false, type_sig));
+    }
 }
 
 #[derive(Debug, Clone)]
 struct Scope {
     variables: HashMap<String, (bool, ast::TypeSignature)>,
+    // mutable variables declared without a type or value, they read as Nil until the
+    // first assignment sets their type
+    uninferred: HashSet<String>,
 }
 
 pub struct SemanticAnalyzer {
     scopes: Vec<Scope>,
     notices: Vec<Notice>,
     in_function_block: bool,
+    fn_return_type: Option<ast::TypeSignature>,
+    loop_depth: usize,
     file: Option<String>,
     supress_errors: bool,
     current_fn: (String, ast::TypeSignature),
+    // names of the variables whose initializers are being analyzed, innermost last
+    initializing: Vec<String>,
+    // struct names and their fields, structs are always global
+    structs: HashMap<String, Vec<(String, ast::TypeSignature)>>,
+    // source analyzed a piece at a time, like repl lines, may evaluate to any type
+    incremental: bool,
 }
 
 impl<'a> SemanticAnalyzer {
@@ -49,6 +69,20 @@ impl<'a> SemanticAnalyzer {
         None
     }
 
+    // sets the type of a variable declared without one, false if its type was already known
+    fn infer_var_type(&mut self, var: &str, type_: &ast::TypeSignature) -> bool {
+        for s in self.scopes.iter_mut().rev() {
+            if let Some(v) = s.variables.get_mut(var) {
+                if s.uninferred.remove(var) {
+                    v.1 = type_.clone();
+                    return true;
+                }
+                return false;
+            }
+        }
+        false
+    }
+
     fn make_notice(&mut self, pos: super::Position, msg: String) -> Notice {
         let e = Notice {
             level: NoticeLevel::Notice,
@@ -80,6 +114,7 @@ impl<'a> SemanticAnalyzer {
     fn new_scope(&mut self) {
         self.scopes.push(Scope {
             variables: HashMap::new(),
+            uninferred: HashSet::new(),
         });
     }
 
@@ -95,6 +130,20 @@ impl<'a> SemanticAnalyzer {
         self.scopes.last_mut().unwrap()
     }
 
+    fn check_fn_return_type(&mut self, pos: super::Position, return_type: &ast::TypeSignature) {
+        if let Some(expected) = self.fn_return_type.clone() {
+            if *return_type != expected {
+                self.make_err(
+                    pos,
+                    format!(
+                        "Return type does not match the function's return type; expected {:?} but got {:?}",
+                        expected, return_type
+                    ),
+                );
+            }
+        }
+    }
+
     fn check_if_type_is_defined(
         &mut self,
         type_: &ast::TypeSignature,
@@ -102,29 +151,48 @@ impl<'a> SemanticAnalyzer {
         match type_ {
             ast::TypeSignature::Primitive(_) => Some(type_.clone()),
             ast::TypeSignature::Function(_) => Some(type_.clone()),
-            ast::TypeSignature::Custom(s) if s == "String" => Some(type_.clone()),
+            ast::TypeSignature::Custom(s) if s == "String" || self.structs.contains_key(s) => {
+                Some(type_.clone())
+            }
+            ast::TypeSignature::Array(elem) => {
+                self.check_if_type_is_defined(elem).map(|_| type_.clone())
+            }
             _ => None,
         }
     }
 
-    pub fn analyze(
-        module: &mut ast::Module,
+    fn new(
         filename: Option<String>,
-        stdlib: Option<SemanticStdLib>,
-    ) -> Result<Vec<Notice>, Vec<Notice>> {
-        let mut sa = SemanticAnalyzer {
+        variables: HashMap<String, (bool, ast::TypeSignature)>,
+    ) -> Self {
+        SemanticAnalyzer {
             scopes: vec![Scope {
-                variables: HashMap::new(),
+                variables,
+                uninferred: HashSet::new(),
             }],
             notices: Vec::new(),
             in_function_block: false,
+            fn_return_type: None,
+            loop_depth: 0,
             file: filename,
             supress_errors: false,
             current_fn: (String::new(), NIL_TYPE_SIGNATURE.clone()),
-        };
+            initializing: Vec::new(),
+            structs: HashMap::new(),
+            incremental: false,
+        }
+    }
+
+    pub fn analyze(
+        module: &mut ast::Module,
+        filename: Option<String>,
+        stdlib: Option<SemanticStdLib>,
+    ) -> Result<Vec<Notice>, Vec<Notice>> {
+        let mut sa = SemanticAnalyzer::new(filename, HashMap::new());
 
         if let Some(lib) = stdlib {
             sa.last_scope().variables.extend(lib.variables);
+            sa.structs.extend(lib.structs);
         }
 
         analyze_module(&mut sa, module);
@@ -132,9 +200,60 @@ impl<'a> SemanticAnalyzer {
         if core::contains_errors(&sa.notices) {
             Err(sa.notices)
         } else {
+            sa.notices
+                .extend(unused_variables(&module.expressions, true, sa.file.clone()));
             Ok(sa.notices)
         }
     }
+
+    // analyzes one piece of a program that is run a piece at a time, like a line in a repl,
+    // globals it defines are added to `globals` so the following pieces can use them
+    pub fn analyze_incremental(
+        module: &mut ast::Module,
+        globals: &mut SemanticStdLib,
+    ) -> Result<Vec<Notice>, Vec<Notice>> {
+        let mut sa = SemanticAnalyzer::new(None, globals.variables.clone());
+        sa.incremental = true;
+        sa.structs = globals.structs.clone();
+        sa.scopes[0].uninferred = globals.uninferred.clone();
+
+        analyze_module(&mut sa, module);
+
+        if core::contains_errors(&sa.notices) {
+            Err(sa.notices)
+        } else {
+            let global_scope = sa.scopes.swap_remove(0);
+            globals.variables = global_scope.variables;
+            globals.uninferred = global_scope.uninferred;
+            globals.structs = sa.structs;
+            // globals can still be used by the pieces that come after this one
+            sa.notices
+                .extend(unused_variables(&module.expressions, false, None));
+            Ok(sa.notices)
+        }
+    }
+}
+
+/// Checks the types of a single node, reporting every mismatch rather than stopping at the first.
+/// Nothing is in scope beforehand so builtins like `print` aren't known, use
+/// `SemanticAnalyzer::analyze` with a `SemanticStdLib` to check a whole module against them.
+pub fn typecheck(ast: &ast::AstNode) -> Result<(), Vec<GravitonError>> {
+    let mut sa = SemanticAnalyzer::new(None, HashMap::new());
+    // analyzing fills in the type of each node so it's done on a copy
+    let mut node = ast.clone();
+    analyze(&mut sa, &mut node);
+
+    let errors: Vec<GravitonError> = sa
+        .notices
+        .iter()
+        .filter(|n| matches!(n.level, NoticeLevel::Error | NoticeLevel::Critical))
+        .map(GravitonError::from)
+        .collect();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
 }
 
 fn analyze_module(sa: &mut SemanticAnalyzer, module: &mut ast::Module) -> ast::TypeSignature {
@@ -164,7 +283,7 @@ fn analyze_module(sa: &mut SemanticAnalyzer, module: &mut ast::Module) -> ast::T
             idx += 1;
         }
         if let Some(r) = return_type {
-            if r != DEFAULT_NUM_TYPE_SIGNATURE && r != NIL_TYPE_SIGNATURE {
+            if !sa.incremental && r != DEFAULT_NUM_TYPE_SIGNATURE && r != NIL_TYPE_SIGNATURE {
                 sa.make_err(
                     super::Position { line: 0, col: 0 },
                     format!("Modules may only return I32 or Nil; found {:?}", r),
@@ -199,6 +318,11 @@ fn analyze(sa: &mut SemanticAnalyzer, ast: &mut ast::AstNode) -> ast::TypeSignat
                     } else {
                         return sa.current_fn.1.clone();
                     }
+                } else if sa.initializing.contains(s) {
+                    sa.make_err(
+                        ast.pos,
+                        format!("cannot use '{}' in its own initializer", s),
+                    );
                 } else {
                     sa.make_err(ast.pos, format!("Variable {} not found in scope", s));
                 }
@@ -220,9 +344,39 @@ fn analyze(sa: &mut SemanticAnalyzer, ast: &mut ast::AstNode) -> ast::TypeSignat
             ast::BinaryOperation::Less
             | ast::BinaryOperation::LessEqual
             | ast::BinaryOperation::Greater
-            | ast::BinaryOperation::GreaterEqual
-            | ast::BinaryOperation::Equal
-            | ast::BinaryOperation::NotEqual => {
+            | ast::BinaryOperation::GreaterEqual => {
+                let ltype = analyze(sa, l);
+                let rtype = analyze(sa, r);
+                if !ltype.is_number() {
+                    sa.make_err(
+                        l.pos,
+                        format!(
+                            "Left comparison operand must be a number to be ordered; found {:?}",
+                            ltype
+                        ),
+                    );
+                }
+                if !rtype.is_number() {
+                    sa.make_err(
+                        r.pos,
+                        format!(
+                            "Right comparison operand must be a number to be ordered; found {:?}",
+                            rtype
+                        ),
+                    );
+                }
+                if ltype != rtype {
+                    sa.make_err(
+                        ast.pos,
+                        format!(
+                            "Binary operands are not the same type; {:?} != {:?}",
+                            ltype, rtype
+                        ),
+                    );
+                }
+                BOOL_TYPE_SIGNATURE.clone()
+            }
+            ast::BinaryOperation::Equal | ast::BinaryOperation::NotEqual => {
                 let ltype = analyze(sa, &mut **l);
                 let rtype = analyze(sa, &mut **r);
                 if ltype != rtype {
@@ -280,8 +434,16 @@ fn analyze(sa: &mut SemanticAnalyzer, ast: &mut ast::AstNode) -> ast::TypeSignat
                 BOOL_TYPE_SIGNATURE.clone()
             }
             ast::BinaryOperation::Assign => {
-                let return_type = analyze(sa, &mut **l);
-                if return_type != analyze(sa, &mut **r) {
+                let mut return_type = analyze(sa, l);
+                let rtype = analyze(sa, r);
+                let inferred = match &l.node {
+                    ast::Ast::Identifier(s) => sa.infer_var_type(s, &rtype),
+                    _ => false,
+                };
+                if inferred {
+                    l.type_sig = Some(rtype.clone());
+                    return_type = rtype;
+                } else if return_type != rtype {
                     sa.make_err(r.pos, "Binary operands are not the same type".to_string());
                 }
                 if let ast::Ast::Identifier(s) = &l.node {
@@ -293,6 +455,9 @@ fn analyze(sa: &mut SemanticAnalyzer, ast: &mut ast::AstNode) -> ast::TypeSignat
                         sa.make_err(l.pos, format!("Variable {} not found in scope", s));
                     }
                     return_type
+                } else if let ast::Ast::Index(..) | ast::Ast::FieldAccess(..) = &l.node {
+                    // writing an element or field doesn't rebind the variable so it doesn't need to be mutable
+                    return_type
                 } else {
                     sa.make_err(l.pos, "Binary assign not assigning variable".to_string());
                     NIL_TYPE_SIGNATURE.clone()
@@ -351,6 +516,15 @@ fn analyze(sa: &mut SemanticAnalyzer, ast: &mut ast::AstNode) -> ast::TypeSignat
                     }
                     expr_type
                 }
+                ast::UnaryOperation::Plus => {
+                    if !expr_type.is_number() {
+                        sa.make_err(
+                            expr.pos,
+                            "Unary plus only supports primitive number types".to_string(),
+                        );
+                    }
+                    expr_type
+                }
             }
         }
         ast::Ast::Return(_) => {
@@ -379,8 +553,9 @@ fn analyze(sa: &mut SemanticAnalyzer, ast: &mut ast::AstNode) -> ast::TypeSignat
                                     );
                                 }
                             } else {
-                                return_type = Some(ret);
+                                return_type = Some(ret.clone());
                             }
+                            sa.check_fn_return_type(rexpr.pos, &ret);
                             if !sa.in_function_block {
                                 sa.make_err(
                                     rexpr.pos,
@@ -405,8 +580,9 @@ fn analyze(sa: &mut SemanticAnalyzer, ast: &mut ast::AstNode) -> ast::TypeSignat
                                     );
                                 }
                             } else {
-                                return_type = Some(ret);
+                                return_type = Some(ret.clone());
                             }
+                            sa.check_fn_return_type(rexpr.pos, &ret);
                             if !sa.in_function_block {
                                 sa.make_err(
                                     rexpr.pos,
@@ -485,12 +661,62 @@ fn analyze(sa: &mut SemanticAnalyzer, ast: &mut ast::AstNode) -> ast::TypeSignat
                     format!("While condition must evaluate to Bool; got {:?}", cond_type),
                 );
             }
+            sa.loop_depth += 1;
             let return_type = analyze(sa, &mut **expr);
+            sa.loop_depth -= 1;
             sa.pop_scope(ast.pos);
             return_type
         }
+        ast::Ast::For(ref mut init, ref mut cond, ref mut step, ref mut expr) => {
+            // the scope keeps variables declared by the initializer local to the loop
+            sa.new_scope();
+            analyze(sa, init);
+            let cond_type = analyze(sa, cond);
+            if cond_type != BOOL_TYPE_SIGNATURE {
+                sa.make_err(
+                    cond.pos,
+                    format!("For condition must evaluate to Bool; got {:?}", cond_type),
+                );
+            }
+            analyze(sa, step);
+            sa.loop_depth += 1;
+            analyze(sa, expr);
+            sa.loop_depth -= 1;
+            sa.pop_scope(ast.pos);
+            NIL_TYPE_SIGNATURE.clone()
+        }
+        ast::Ast::Break => {
+            if sa.loop_depth == 0 {
+                sa.make_err(ast.pos, "Break must be within a loop".to_string());
+            }
+            NIL_TYPE_SIGNATURE.clone()
+        }
+        ast::Ast::Continue => {
+            if sa.loop_depth == 0 {
+                sa.make_err(ast.pos, "Continue must be within a loop".to_string());
+            }
+            NIL_TYPE_SIGNATURE.clone()
+        }
         ast::Ast::VarDecl(ref name, ref mut sig, ref mut expr) => {
-            if sa.check_if_var_in_scopes(&name).is_none() {
+            // only the current scope is checked, declaring in an inner scope shadows the outer variable
+            if !sa.last_scope().variables.contains_key(name) {
+                if expr.is_none() && !sig.mutable {
+                    sa.make_err(
+                        ast.pos,
+                        format!(
+                            "Immutable variable {} must be given a value, it can never be assigned",
+                            name
+                        ),
+                    );
+                }
+                // functions may refer to themselves, anything else can't be read before it's set
+                let initializing = match expr {
+                    Some(e) => !matches!(e.node, ast::Ast::FnDef(..)),
+                    None => false,
+                };
+                if initializing {
+                    sa.initializing.push(name.clone());
+                }
                 let mut return_type = NIL_TYPE_SIGNATURE.clone();
                 if let Some(type_sig) = &sig.type_sig {
                     if let Some(e) = expr {
@@ -519,14 +745,17 @@ fn analyze(sa: &mut SemanticAnalyzer, ast: &mut ast::AstNode) -> ast::TypeSignat
                         .variables
                         .insert(name.clone(), (sig.mutable, expr_type));
                 } else {
-                    sa.make_err(
-                        ast.pos,
-                        "Cannot infer type without an assign expression".to_string(),
-                    );
+                    // the vm stores Nil for a variable without a value
+                    if sig.mutable {
+                        sa.last_scope().uninferred.insert(name.clone());
+                    }
                     sa.last_scope()
                         .variables
                         .insert(name.clone(), (sig.mutable, NIL_TYPE_SIGNATURE.clone()));
                 }
+                if initializing {
+                    sa.initializing.pop();
+                }
                 sa.current_fn = (String::new(), NIL_TYPE_SIGNATURE.clone());
                 return_type
             } else {
@@ -544,6 +773,8 @@ fn analyze(sa: &mut SemanticAnalyzer, ast: &mut ast::AstNode) -> ast::TypeSignat
         }
         ast::Ast::FnDef(ref mut sig, ref param_names, ref mut expr) => {
             sa.new_scope();
+            // loops outside of the function cannot be broken out of from within it
+            let outer_loop_depth = std::mem::replace(&mut sa.loop_depth, 0);
             for (var, name) in sig.params.iter().zip(param_names.iter()) {
                 if let Some(type_sig) = &var.type_sig {
                     if sa.check_if_type_is_defined(type_sig).is_none() {
@@ -569,12 +800,20 @@ fn analyze(sa: &mut SemanticAnalyzer, ast: &mut ast::AstNode) -> ast::TypeSignat
                 if sa.check_if_type_is_defined(type_sig).is_none() {
                     sa.make_err(ast.pos, format!("Type {:?} is not defined", type_sig));
                 }
+                let outer_in_function_block = sa.in_function_block;
                 if let ast::Ast::Block(_) = &expr.node {
                     sa.in_function_block = true;
                 }
+                let outer_fn_return_type = sa.fn_return_type.replace((**type_sig).clone());
                 let expr_type = analyze(sa, &mut **expr);
-                sa.in_function_block = false;
-                if **type_sig != expr_type {
+                sa.fn_return_type = outer_fn_return_type;
+                sa.in_function_block = outer_in_function_block;
+                if expr_type.is_nil() && !type_sig.is_nil() {
+                    sa.make_err(
+                        expr.pos,
+                        format!("Function must return a value of type {:?}", **type_sig),
+                    );
+                } else if **type_sig != expr_type {
                     sa.make_err(
                         expr.pos,
                         format!(
@@ -584,8 +823,11 @@ fn analyze(sa: &mut SemanticAnalyzer, ast: &mut ast::AstNode) -> ast::TypeSignat
                     );
                 }
             } else {
+                let outer_fn_return_type = sa.fn_return_type.take();
                 sig.return_type = Some(Box::new(analyze(sa, &mut **expr)));
+                sa.fn_return_type = outer_fn_return_type;
             }
+            sa.loop_depth = outer_loop_depth;
             sa.pop_scope(ast.pos);
             ast::TypeSignature::Function(sig.clone())
         }
@@ -663,6 +905,150 @@ fn analyze(sa: &mut SemanticAnalyzer, ast: &mut ast::AstNode) -> ast::TypeSignat
                 }
             }
         }
+        ast::Ast::Array(ref mut elems) => {
+            let mut elems = elems.iter_mut();
+            match elems.next() {
+                Some(first) => {
+                    let elem_type = analyze(sa, first);
+                    for elem in elems {
+                        let other_type = analyze(sa, elem);
+                        if other_type != elem_type {
+                            sa.make_err(
+                                elem.pos,
+                                format!(
+                                    "Array elements are not the same type; expected {:?} but got {:?}",
+                                    elem_type, other_type
+                                ),
+                            );
+                        }
+                    }
+                    ast::TypeSignature::Array(Box::new(elem_type))
+                }
+                None => {
+                    sa.make_err(
+                        ast.pos,
+                        "Cannot infer the element type of an empty array".to_string(),
+                    );
+                    ast::TypeSignature::Array(Box::new(NIL_TYPE_SIGNATURE.clone()))
+                }
+            }
+        }
+        ast::Ast::Index(ref mut array, ref mut index) => {
+            let array_type = analyze(sa, array);
+            let index_type = analyze(sa, index);
+            if !index_type.is_integer() {
+                sa.make_err(
+                    index.pos,
+                    format!("Array index must be an integer; found {:?}", index_type),
+                );
+            }
+            if let ast::TypeSignature::Array(elem_type) = array_type {
+                *elem_type
+            } else {
+                sa.make_err(
+                    array.pos,
+                    format!("Cannot index into a value of type {:?}", array_type),
+                );
+                NIL_TYPE_SIGNATURE.clone()
+            }
+        }
+        ast::Ast::StructDef(ref name, ref fields) => {
+            if sa.structs.contains_key(name) {
+                sa.make_err(ast.pos, format!("Struct {} already defined", name));
+            } else {
+                let mut seen: Vec<&String> = Vec::new();
+                for (field, type_sig) in fields {
+                    if seen.contains(&field) {
+                        sa.make_err(
+                            ast.pos,
+                            format!(
+                                "Field {} of struct {} is defined more than once",
+                                field, name
+                            ),
+                        );
+                    }
+                    seen.push(field);
+                    if sa.check_if_type_is_defined(type_sig).is_none() {
+                        sa.make_err(ast.pos, format!("Type {:?} is not defined", type_sig));
+                    }
+                }
+                sa.structs.insert(name.clone(), fields.clone());
+            }
+            NIL_TYPE_SIGNATURE.clone()
+        }
+        ast::Ast::StructInit(ref name, ref mut fields) => match sa.structs.get(name).cloned() {
+            Some(def) => {
+                let mut seen: Vec<String> = Vec::new();
+                for (field, value) in fields.iter_mut() {
+                    let value_type = analyze(sa, value);
+                    if seen.contains(field) {
+                        sa.make_err(value.pos, format!("Field {} is set more than once", field));
+                        continue;
+                    }
+                    seen.push(field.clone());
+                    match def.iter().find(|(f, _)| f == field) {
+                        Some((_, field_type)) if *field_type != value_type => {
+                            sa.make_err(
+                                value.pos,
+                                format!(
+                                    "Field {} expected type {:?} but got type {:?}",
+                                    field, field_type, value_type
+                                ),
+                            );
+                        }
+                        Some(_) => {}
+                        None => {
+                            sa.make_err(
+                                value.pos,
+                                format!("Struct {} has no field {}", name, field),
+                            );
+                        }
+                    }
+                }
+                for (field, _) in def.iter() {
+                    if !seen.contains(field) {
+                        sa.make_err(
+                            ast.pos,
+                            format!("Missing field {} in initializer of struct {}", field, name),
+                        );
+                    }
+                }
+                ast::TypeSignature::Custom(name.clone())
+            }
+            None => {
+                for (_, value) in fields.iter_mut() {
+                    analyze(sa, value);
+                }
+                sa.make_err(ast.pos, format!("Struct {} is not defined", name));
+                NIL_TYPE_SIGNATURE.clone()
+            }
+        },
+        ast::Ast::FieldAccess(ref mut expr, ref field) => {
+            let expr_type = analyze(sa, expr);
+            let def = match &expr_type {
+                ast::TypeSignature::Custom(name) => sa.structs.get(name).cloned(),
+                _ => None,
+            };
+            match def {
+                Some(def) => match def.into_iter().find(|(f, _)| f == field) {
+                    Some((_, field_type)) => field_type,
+                    None => {
+                        sa.make_err(
+                            ast.pos,
+                            format!("Struct {:?} has no field {}", expr_type, field),
+                        );
+                        NIL_TYPE_SIGNATURE.clone()
+                    }
+                },
+                None => {
+                    sa.make_err(
+                        expr.pos,
+                        format!("Cannot access field {} of type {:?}", field, expr_type),
+                    );
+                    NIL_TYPE_SIGNATURE.clone()
+                }
+            }
+        }
     };
     ast.type_sig = Some(node_type.clone());
     node_type
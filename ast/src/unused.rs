@@ -0,0 +1,171 @@
+use super::ast::{Ast, AstNode, BinaryOperation};
+use super::visitor::{self, Visitor};
+use super::{Notice, NoticeLevel, Position};
+
+use std::collections::HashMap;
+
+struct Declaration {
+    pos: Position,
+    used: bool,
+    // parameters, names starting with `_` and anything declared by an import are never reported
+    reportable: bool,
+}
+
+struct UnusedVariables {
+    scopes: Vec<HashMap<String, Declaration>>,
+    import_depth: usize,
+    file: Option<String>,
+    notices: Vec<Notice>,
+}
+
+/// Warns about variables that are declared but never read. Globals are skipped when
+/// `check_globals` is false since code analyzed a piece at a time may still use them later.
+pub fn unused_variables(
+    exprs: &[AstNode],
+    check_globals: bool,
+    file: Option<String>,
+) -> Vec<Notice> {
+    let mut pass = UnusedVariables {
+        scopes: vec![HashMap::new()],
+        import_depth: 0,
+        file,
+        notices: Vec::new(),
+    };
+    for expr in exprs {
+        pass.visit_node(expr);
+    }
+    if check_globals {
+        pass.pop_scope();
+    }
+    pass.notices
+}
+
+impl UnusedVariables {
+    fn declare(&mut self, name: &str, pos: Position, reportable: bool) {
+        // a shadowed variable isn't reported, the new declaration may be replacing it on purpose
+        for scope in self.scopes.iter_mut() {
+            if let Some(decl) = scope.get_mut(name) {
+                decl.used = true;
+            }
+        }
+        let reportable = reportable && self.import_depth == 0 && !name.starts_with('_');
+        self.scopes.last_mut().unwrap().insert(
+            name.to_string(),
+            Declaration {
+                pos,
+                used: false,
+                reportable,
+            },
+        );
+    }
+
+    fn mark_used(&mut self, name: &str) {
+        for scope in self.scopes.iter_mut().rev() {
+            if let Some(decl) = scope.get_mut(name) {
+                decl.used = true;
+                return;
+            }
+        }
+    }
+
+    fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn pop_scope(&mut self) {
+        let scope = match self.scopes.pop() {
+            Some(scope) => scope,
+            None => return,
+        };
+        let mut unused: Vec<(String, Position)> = scope
+            .into_iter()
+            .filter(|(_, decl)| decl.reportable && !decl.used)
+            .map(|(name, decl)| (name, decl.pos))
+            .collect();
+        // scopes are hash maps so they're sorted to report in source order
+        unused.sort_by_key(|(_, pos)| *pos);
+        for (name, pos) in unused {
+            self.notices.push(Notice {
+                from: "Semantic".to_string(),
+                msg: format!("unused variable '{}'", name),
+                pos,
+                file: self.file.clone(),
+                level: NoticeLevel::Warning,
+            });
+        }
+    }
+
+    fn scoped(&mut self, node: &AstNode) {
+        self.push_scope();
+        visitor::walk(self, node);
+        self.pop_scope();
+    }
+}
+
+impl Visitor for UnusedVariables {
+    fn visit_identifier(&mut self, node: &AstNode) {
+        if let Ast::Identifier(name) = &node.node {
+            self.mark_used(name);
+        }
+    }
+
+    // assigning to a variable doesn't read it, only the assigned value is visited
+    fn visit_binary(&mut self, node: &AstNode) {
+        match &node.node {
+            Ast::Binary(BinaryOperation::Assign, l, r) if matches!(l.node, Ast::Identifier(_)) => {
+                self.visit_node(r)
+            }
+            _ => visitor::walk(self, node),
+        }
+    }
+
+    fn visit_var_decl(&mut self, node: &AstNode) {
+        if let Ast::VarDecl(name, _, value) = &node.node {
+            match value {
+                // functions are declared first so they can call themselves
+                Some(value) if matches!(value.node, Ast::FnDef(..)) => {
+                    self.declare(name, node.pos, true);
+                    self.visit_node(value);
+                }
+                Some(value) => {
+                    self.visit_node(value);
+                    self.declare(name, node.pos, true);
+                }
+                None => self.declare(name, node.pos, true),
+            }
+        }
+    }
+
+    fn visit_fn_def(&mut self, node: &AstNode) {
+        if let Ast::FnDef(_, params, body) = &node.node {
+            self.push_scope();
+            for param in params {
+                self.declare(param, node.pos, false);
+            }
+            self.visit_node(body);
+            self.pop_scope();
+        }
+    }
+
+    fn visit_block(&mut self, node: &AstNode) {
+        self.scoped(node)
+    }
+
+    fn visit_if_else(&mut self, node: &AstNode) {
+        self.scoped(node)
+    }
+
+    fn visit_while(&mut self, node: &AstNode) {
+        self.scoped(node)
+    }
+
+    fn visit_for(&mut self, node: &AstNode) {
+        self.scoped(node)
+    }
+
+    fn visit_import(&mut self, node: &AstNode) {
+        self.import_depth += 1;
+        visitor::walk(self, node);
+        self.import_depth -= 1;
+    }
+}
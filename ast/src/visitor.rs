@@ -0,0 +1,349 @@
+use super::ast::{Ast, AstNode};
+
+// passes implement the methods for the nodes they care about, every other node just has its children visited
+// an overriding method calls `walk` itself if it still wants the node's children visited
+pub trait Visitor {
+    // called for every node, dispatches to the method for the node's variant
+    fn visit_node(&mut self, node: &AstNode) {
+        match &node.node {
+            Ast::Identifier(_) => self.visit_identifier(node),
+            Ast::Integer(_) => self.visit_integer(node),
+            Ast::Float(_) => self.visit_float(node),
+            Ast::String(_) => self.visit_string(node),
+            Ast::Bool(_) => self.visit_bool(node),
+            Ast::Statement(_) => self.visit_statement(node),
+            Ast::Binary(..) => self.visit_binary(node),
+            Ast::Unary(..) => self.visit_unary(node),
+            Ast::Return(_) => self.visit_return(node),
+            Ast::Block(_) => self.visit_block(node),
+            Ast::IfElse(..) => self.visit_if_else(node),
+            Ast::While(..) => self.visit_while(node),
+            Ast::For(..) => self.visit_for(node),
+            Ast::Break => self.visit_break(node),
+            Ast::Continue => self.visit_continue(node),
+            Ast::VarDecl(..) => self.visit_var_decl(node),
+            Ast::Import(_) => self.visit_import(node),
+            Ast::FnDef(..) => self.visit_fn_def(node),
+            Ast::FnExtern(..) => self.visit_fn_extern(node),
+            Ast::FnCall(..) => self.visit_fn_call(node),
+            Ast::As(..) => self.visit_as(node),
+            Ast::Array(_) => self.visit_array(node),
+            Ast::Index(..) => self.visit_index(node),
+            Ast::StructDef(..) => self.visit_struct_def(node),
+            Ast::StructInit(..) => self.visit_struct_init(node),
+            Ast::FieldAccess(..) => self.visit_field_access(node),
+        }
+    }
+
+    fn visit_identifier(&mut self, node: &AstNode) {
+        walk(self, node)
+    }
+    fn visit_integer(&mut self, node: &AstNode) {
+        walk(self, node)
+    }
+    fn visit_float(&mut self, node: &AstNode) {
+        walk(self, node)
+    }
+    fn visit_string(&mut self, node: &AstNode) {
+        walk(self, node)
+    }
+    fn visit_bool(&mut self, node: &AstNode) {
+        walk(self, node)
+    }
+    fn visit_statement(&mut self, node: &AstNode) {
+        walk(self, node)
+    }
+    fn visit_binary(&mut self, node: &AstNode) {
+        walk(self, node)
+    }
+    fn visit_unary(&mut self, node: &AstNode) {
+        walk(self, node)
+    }
+    fn visit_return(&mut self, node: &AstNode) {
+        walk(self, node)
+    }
+    fn visit_block(&mut self, node: &AstNode) {
+        walk(self, node)
+    }
+    fn visit_if_else(&mut self, node: &AstNode) {
+        walk(self, node)
+    }
+    fn visit_while(&mut self, node: &AstNode) {
+        walk(self, node)
+    }
+    fn visit_for(&mut self, node: &AstNode) {
+        walk(self, node)
+    }
+    fn visit_break(&mut self, node: &AstNode) {
+        walk(self, node)
+    }
+    fn visit_continue(&mut self, node: &AstNode) {
+        walk(self, node)
+    }
+    fn visit_var_decl(&mut self, node: &AstNode) {
+        walk(self, node)
+    }
+    fn visit_import(&mut self, node: &AstNode) {
+        walk(self, node)
+    }
+    fn visit_fn_def(&mut self, node: &AstNode) {
+        walk(self, node)
+    }
+    fn visit_fn_extern(&mut self, node: &AstNode) {
+        walk(self, node)
+    }
+    fn visit_fn_call(&mut self, node: &AstNode) {
+        walk(self, node)
+    }
+    fn visit_as(&mut self, node: &AstNode) {
+        walk(self, node)
+    }
+    fn visit_array(&mut self, node: &AstNode) {
+        walk(self, node)
+    }
+    fn visit_index(&mut self, node: &AstNode) {
+        walk(self, node)
+    }
+    fn visit_struct_def(&mut self, node: &AstNode) {
+        walk(self, node)
+    }
+    fn visit_struct_init(&mut self, node: &AstNode) {
+        walk(self, node)
+    }
+    fn visit_field_access(&mut self, node: &AstNode) {
+        walk(self, node)
+    }
+}
+
+/// Visits each of the node's children in source order
+pub fn walk<V: Visitor + ?Sized>(visitor: &mut V, node: &AstNode) {
+    for child in node.children() {
+        visitor.visit_node(child);
+    }
+}
+
+// same as `Visitor` but the pass is allowed to rewrite the nodes it visits
+pub trait VisitorMut {
+    // called for every node, dispatches to the method for the node's variant
+    fn visit_node_mut(&mut self, node: &mut AstNode) {
+        match &node.node {
+            Ast::Identifier(_) => self.visit_identifier_mut(node),
+            Ast::Integer(_) => self.visit_integer_mut(node),
+            Ast::Float(_) => self.visit_float_mut(node),
+            Ast::String(_) => self.visit_string_mut(node),
+            Ast::Bool(_) => self.visit_bool_mut(node),
+            Ast::Statement(_) => self.visit_statement_mut(node),
+            Ast::Binary(..) => self.visit_binary_mut(node),
+            Ast::Unary(..) => self.visit_unary_mut(node),
+            Ast::Return(_) => self.visit_return_mut(node),
+            Ast::Block(_) => self.visit_block_mut(node),
+            Ast::IfElse(..) => self.visit_if_else_mut(node),
+            Ast::While(..) => self.visit_while_mut(node),
+            Ast::For(..) => self.visit_for_mut(node),
+            Ast::Break => self.visit_break_mut(node),
+            Ast::Continue => self.visit_continue_mut(node),
+            Ast::VarDecl(..) => self.visit_var_decl_mut(node),
+            Ast::Import(_) => self.visit_import_mut(node),
+            Ast::FnDef(..) => self.visit_fn_def_mut(node),
+            Ast::FnExtern(..) => self.visit_fn_extern_mut(node),
+            Ast::FnCall(..) => self.visit_fn_call_mut(node),
+            Ast::As(..) => self.visit_as_mut(node),
+            Ast::Array(_) => self.visit_array_mut(node),
+            Ast::Index(..) => self.visit_index_mut(node),
+            Ast::StructDef(..) => self.visit_struct_def_mut(node),
+            Ast::StructInit(..) => self.visit_struct_init_mut(node),
+            Ast::FieldAccess(..) => self.visit_field_access_mut(node),
+        }
+    }
+
+    fn visit_identifier_mut(&mut self, node: &mut AstNode) {
+        walk_mut(self, node)
+    }
+    fn visit_integer_mut(&mut self, node: &mut AstNode) {
+        walk_mut(self, node)
+    }
+    fn visit_float_mut(&mut self, node: &mut AstNode) {
+        walk_mut(self, node)
+    }
+    fn visit_string_mut(&mut self, node: &mut AstNode) {
+        walk_mut(self, node)
+    }
+    fn visit_bool_mut(&mut self, node: &mut AstNode) {
+        walk_mut(self, node)
+    }
+    fn visit_statement_mut(&mut self, node: &mut AstNode) {
+        walk_mut(self, node)
+    }
+    fn visit_binary_mut(&mut self, node: &mut AstNode) {
+        walk_mut(self, node)
+    }
+    fn visit_unary_mut(&mut self, node: &mut AstNode) {
+        walk_mut(self, node)
+    }
+    fn visit_return_mut(&mut self, node: &mut AstNode) {
+        walk_mut(self, node)
+    }
+    fn visit_block_mut(&mut self, node: &mut AstNode) {
+        walk_mut(self, node)
+    }
+    fn visit_if_else_mut(&mut self, node: &mut AstNode) {
+        walk_mut(self, node)
+    }
+    fn visit_while_mut(&mut self, node: &mut AstNode) {
+        walk_mut(self, node)
+    }
+    fn visit_for_mut(&mut self, node: &mut AstNode) {
+        walk_mut(self, node)
+    }
+    fn visit_break_mut(&mut self, node: &mut AstNode) {
+        walk_mut(self, node)
+    }
+    fn visit_continue_mut(&mut self, node: &mut AstNode) {
+        walk_mut(self, node)
+    }
+    fn visit_var_decl_mut(&mut self, node: &mut AstNode) {
+        walk_mut(self, node)
+    }
+    fn visit_import_mut(&mut self, node: &mut AstNode) {
+        walk_mut(self, node)
+    }
+    fn visit_fn_def_mut(&mut self, node: &mut AstNode) {
+        walk_mut(self, node)
+    }
+    fn visit_fn_extern_mut(&mut self, node: &mut AstNode) {
+        walk_mut(self, node)
+    }
+    fn visit_fn_call_mut(&mut self, node: &mut AstNode) {
+        walk_mut(self, node)
+    }
+    fn visit_as_mut(&mut self, node: &mut AstNode) {
+        walk_mut(self, node)
+    }
+    fn visit_array_mut(&mut self, node: &mut AstNode) {
+        walk_mut(self, node)
+    }
+    fn visit_index_mut(&mut self, node: &mut AstNode) {
+        walk_mut(self, node)
+    }
+    fn visit_struct_def_mut(&mut self, node: &mut AstNode) {
+        walk_mut(self, node)
+    }
+    fn visit_struct_init_mut(&mut self, node: &mut AstNode) {
+        walk_mut(self, node)
+    }
+    fn visit_field_access_mut(&mut self, node: &mut AstNode) {
+        walk_mut(self, node)
+    }
+}
+
+/// Visits each of the node's children in source order, allowing them to be changed
+pub fn walk_mut<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut AstNode) {
+    for child in node.children_mut() {
+        visitor.visit_node_mut(child);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::ast::{BinaryOperation, UnaryOperation};
+    use super::*;
+    use core::Position;
+
+    fn node(ast: Ast) -> AstNode {
+        AstNode {
+            node: ast,
+            pos: Position::new(1, 1),
+            type_sig: None,
+        }
+    }
+
+    fn boxed(ast: Ast) -> Box<AstNode> {
+        Box::new(node(ast))
+    }
+
+    // if f(1) > 2 { g(x) } else { -4 }
+    fn sample() -> AstNode {
+        let call_f = Ast::FnCall(
+            boxed(Ast::Identifier("f".to_string())),
+            vec![node(Ast::Integer(1))],
+        );
+        let call_g = Ast::FnCall(
+            boxed(Ast::Identifier("g".to_string())),
+            vec![node(Ast::Identifier("x".to_string()))],
+        );
+        node(Ast::IfElse(
+            boxed(Ast::Binary(
+                BinaryOperation::Greater,
+                boxed(call_f),
+                boxed(Ast::Integer(2)),
+            )),
+            boxed(Ast::Block(vec![node(call_g)])),
+            Vec::new(),
+            Some(boxed(Ast::Block(vec![node(Ast::Unary(
+                UnaryOperation::Negate,
+                boxed(Ast::Integer(4)),
+            ))]))),
+        ))
+    }
+
+    struct NodeCount(usize);
+
+    impl Visitor for NodeCount {
+        fn visit_node(&mut self, node: &AstNode) {
+            self.0 += 1;
+            walk(self, node)
+        }
+    }
+
+    struct Identifiers(Vec<String>);
+
+    impl Visitor for Identifiers {
+        fn visit_identifier(&mut self, node: &AstNode) {
+            if let Ast::Identifier(name) = &node.node {
+                self.0.push(name.clone());
+            }
+        }
+    }
+
+    struct DoubleIntegers;
+
+    impl VisitorMut for DoubleIntegers {
+        fn visit_integer_mut(&mut self, node: &mut AstNode) {
+            if let Ast::Integer(n) = &mut node.node {
+                *n *= 2;
+            }
+        }
+    }
+
+    #[test]
+    fn node_count_pass_matches_node_count() {
+        let ast = sample();
+        let mut count = NodeCount(0);
+        count.visit_node(&ast);
+        assert_eq!(count.0, ast.node_count());
+        assert_eq!(count.0, 13);
+    }
+
+    #[test]
+    fn children_are_visited_in_source_order() {
+        let mut idents = Identifiers(Vec::new());
+        idents.visit_node(&sample());
+        assert_eq!(idents.0, vec!["f", "g", "x"]);
+    }
+
+    #[test]
+    fn mutable_passes_rewrite_nodes() {
+        let mut ast = sample();
+        DoubleIntegers.visit_node_mut(&mut ast);
+        let mut integers = Vec::new();
+        let mut stack = vec![&ast];
+        while let Some(node) = stack.pop() {
+            if let Ast::Integer(n) = node.node {
+                integers.push(n);
+            }
+            stack.extend(node.children());
+        }
+        integers.sort();
+        assert_eq!(integers, vec![2, 4, 8]);
+    }
+}
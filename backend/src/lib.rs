@@ -1,3 +1,6 @@
+// the vm's own tests still have to use it
+#![cfg_attr(test, allow(deprecated))]
+
 #[macro_use]
 extern crate graviton_ast as ast;
 extern crate graviton_core as core;
@@ -24,7 +24,9 @@ pub fn type_to_cranelift<T: cranelift_module::Backend>(
                 PrimitiveType::F32 => types::F32,
                 PrimitiveType::F64 => types::F64,
             },
-            TypeSignature::Function(_sig) => module.target_config().pointer_type(),
+            TypeSignature::Function(_) | TypeSignature::Array(_) => {
+                module.target_config().pointer_type()
+            }
             TypeSignature::Custom(name) => match &**name {
                 "String" => module.target_config().pointer_type(),
                 _ => types::I32,
@@ -54,7 +56,9 @@ pub fn type_ref_to_cranelift<T: cranelift_module::Backend>(
             PrimitiveType::F32 => types::F32,
             PrimitiveType::F64 => types::F64,
         },
-        TypeSignature::Function(_sig) => module.target_config().pointer_type(),
+        TypeSignature::Function(_) | TypeSignature::Array(_) => {
+            module.target_config().pointer_type()
+        }
         TypeSignature::Custom(name) => match &**name {
             "String" => module.target_config().pointer_type(),
             _ => types::I32,
@@ -114,6 +114,7 @@ impl Native {
             data_ctx: &mut ntv.data_ctx,
             errors: &mut ntv.errors,
             debug_level,
+            loops: Vec::new(),
         };
 
         let tmp = match translator.module_to_cranelift(ast_module, &mut builder) {
@@ -190,6 +191,9 @@ struct AstTranslator<'a> {
     data_ctx: &'a mut DataContext,
     errors: &'a mut Vec<Notice>,
     debug_level: i32,
+
+    // condition block, exit block
+    loops: Vec<(Ebb, Ebb)>,
 }
 
 impl<'a> AstTranslator<'a> {
@@ -456,6 +460,7 @@ impl<'a> AstTranslator<'a> {
                         }
                     }
                     ast::UnaryOperation::Negate => Ok(builder.ins().irsub_imm(e, 0)),
+                    ast::UnaryOperation::Plus => Ok(e),
                 }
             }
             ast::Ast::Return(_expr) => {
@@ -562,7 +567,9 @@ impl<'a> AstTranslator<'a> {
                 let condition_value = self.ast_to_cranelift(&*cond, builder)?;
                 builder.ins().brz(condition_value, exit_block, &[]);
 
+                self.loops.push((body_block, exit_block));
                 self.ast_to_cranelift(&*expr, builder)?;
+                self.loops.pop();
 
                 builder.ins().jump(body_block, &[]);
 
@@ -573,8 +580,68 @@ impl<'a> AstTranslator<'a> {
 
                 Ok(builder.ins().iconst(types::I32, 0))
             }
+            ast::Ast::For(init, cond, step, expr) => {
+                // the loop variable is only visible to the loop
+                self.new_scope();
+                self.ast_to_cranelift(init, builder)?;
+
+                let cond_block = builder.create_ebb();
+                let step_block = builder.create_ebb();
+                let exit_block = builder.create_ebb();
+                builder.ins().jump(cond_block, &[]);
+                builder.switch_to_block(cond_block);
+
+                let condition_value = self.ast_to_cranelift(cond, builder)?;
+                builder.ins().brz(condition_value, exit_block, &[]);
+
+                // continue runs the step expression before checking the condition again
+                self.loops.push((step_block, exit_block));
+                self.ast_to_cranelift(expr, builder)?;
+                self.loops.pop();
+
+                builder.ins().jump(step_block, &[]);
+
+                builder.switch_to_block(step_block);
+                builder.seal_block(step_block);
+                self.ast_to_cranelift(step, builder)?;
+                builder.ins().jump(cond_block, &[]);
+
+                builder.switch_to_block(exit_block);
+
+                builder.seal_block(cond_block);
+                builder.seal_block(exit_block);
+                self.pop_scope(ast.pos);
+
+                Ok(builder.ins().iconst(types::I32, 0))
+            }
+            ast::Ast::Break | ast::Ast::Continue => {
+                let target = match (self.loops.last(), &ast.node) {
+                    (Some((_, exit_block)), ast::Ast::Break) => *exit_block,
+                    (Some((body_block, _)), _) => *body_block,
+                    (None, ast::Ast::Break) => {
+                        return Err(
+                            self.make_error(ast.pos, "Break must be within a loop".to_string())
+                        )
+                    }
+                    (None, _) => {
+                        return Err(
+                            self.make_error(ast.pos, "Continue must be within a loop".to_string())
+                        )
+                    }
+                };
+                builder.ins().jump(target, &[]);
+
+                // anything following the jump is unreachable but still needs a block to live in
+                let unreachable_block = builder.create_ebb();
+                builder.switch_to_block(unreachable_block);
+                builder.seal_block(unreachable_block);
+
+                Ok(builder.ins().iconst(types::I32, 0))
+            }
             ast::Ast::VarDecl(name, var_sig, set_expr) => {
-                let var = Variable::new(self.last_scope().variables.len());
+                // numbered across every open scope so a loop variable can't take the slot of a
+                // variable declared outside the loop
+                let var = Variable::new(self.scopes.iter().map(|s| s.variables.len()).sum());
                 let var_type = gravtypes::type_to_cranelift(&var_sig.type_sig, &self.module);
 
                 builder.declare_var(var, var_type);
@@ -738,6 +805,16 @@ impl<'a> AstTranslator<'a> {
                     _ => Ok(val),
                 }
             }
+            ast::Ast::Array(_) | ast::Ast::Index(..) => Err(self.make_error(
+                ast.pos,
+                "Arrays are not supported by the native backend".to_string(),
+            )),
+            ast::Ast::StructDef(..) | ast::Ast::StructInit(..) | ast::Ast::FieldAccess(..) => {
+                Err(self.make_error(
+                    ast.pos,
+                    "Structs are not supported by the native backend".to_string(),
+                ))
+            }
         }
     }
 
@@ -782,6 +859,7 @@ impl<'a> AstTranslator<'a> {
         fnbuilder.seal_block(fnebb);
 
         self.new_scope();
+        let outer_loops = std::mem::take(&mut self.loops);
 
         let outer_scope_ref = &self.scopes[self.scopes.len() - 2].variables;
 
@@ -841,6 +919,7 @@ impl<'a> AstTranslator<'a> {
 
         let return_ins = self.ast_to_cranelift(body_expr, &mut fnbuilder)?;
         fnbuilder.ins().return_(&[return_ins]);
+        self.loops = outer_loops;
         fnbuilder.finalize();
 
         if self.debug_level >= 3 {
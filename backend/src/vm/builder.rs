@@ -0,0 +1,72 @@
+use super::{ByteOp, Bytecode, Value};
+use core::Position;
+use std::convert::TryFrom;
+
+/// Builds bytecode one op at a time for code that doesn't start from an AST
+pub struct BytecodeBuilder {
+    bc: Bytecode,
+}
+
+/// A jump emitted with a placeholder offset that still needs to be patched
+pub struct JumpHandle {
+    idx: usize,
+    jump: fn(i16) -> ByteOp,
+}
+
+impl Default for BytecodeBuilder {
+    fn default() -> BytecodeBuilder {
+        BytecodeBuilder::new()
+    }
+}
+
+impl BytecodeBuilder {
+    pub fn new() -> BytecodeBuilder {
+        BytecodeBuilder {
+            bc: Bytecode::empty(),
+        }
+    }
+
+    /// Index the next op will be pushed at, used as the target of backward jumps
+    pub fn current_idx(&self) -> usize {
+        self.bc.ops.len()
+    }
+
+    /// Appends an op and returns its index
+    pub fn push_op(&mut self, op: ByteOp) -> usize {
+        self.bc.ops.push(op);
+        self.bc.positions.push(Position { line: 0, col: 0 });
+        self.bc.ops.len() - 1
+    }
+
+    /// Adds a value to the constant pool, reusing the slot of an equal value. Fails once the pool
+    /// is full
+    pub fn add_constant(&mut self, value: Value) -> Result<u16, String> {
+        self.bc.intern_constant(value)
+    }
+
+    /// Emits a jump to be patched later, takes the kind of jump e.g. `ByteOp::JumpFalse`
+    pub fn emit_jump(&mut self, jump: fn(i16) -> ByteOp) -> JumpHandle {
+        let idx = self.push_op(jump(0));
+        JumpHandle { idx, jump }
+    }
+
+    /// Points the jump at the next op to be pushed
+    pub fn patch_jump(&mut self, handle: JumpHandle) -> Result<(), String> {
+        let target = self.current_idx();
+        self.patch_jump_to(handle, target)
+    }
+
+    /// Points the jump at any op, jumps are relative so the offset is worked out here
+    pub fn patch_jump_to(&mut self, handle: JumpHandle, target: usize) -> Result<(), String> {
+        let offset = i16::try_from(target as isize - handle.idx as isize)
+            .map_err(|_| format!("Jump from {} to {} is out of range", handle.idx, target))?;
+        self.bc.ops[handle.idx] = (handle.jump)(offset);
+        Ok(())
+    }
+
+    /// Finishes the bytecode, checking that every constant and jump it refers to exists
+    pub fn build(self) -> Result<Bytecode, String> {
+        self.bc.validate()?;
+        Ok(self.bc)
+    }
+}
@@ -0,0 +1,141 @@
+use super::{object, ByteOp, Bytecode, Value};
+use std::collections::BTreeSet;
+
+/// A run of ops that is only entered at its first op and only left after its last
+#[derive(Debug, Clone, PartialEq)]
+pub struct BasicBlock {
+    pub start: usize,
+    // one past the last op of the block
+    pub end: usize,
+    // indices of the blocks control can go to next, a block ending in a return has none
+    pub successors: Vec<usize>,
+}
+
+/// The basic blocks of some bytecode in op order and the edges between them
+#[derive(Debug, Clone)]
+pub struct Cfg {
+    blocks: Vec<BasicBlock>,
+}
+
+impl Cfg {
+    pub fn blocks(&self) -> &[BasicBlock] {
+        &self.blocks
+    }
+
+    /// Every edge as the index of the block it leaves and the block it goes to
+    pub fn edges(&self) -> Vec<(usize, usize)> {
+        self.blocks
+            .iter()
+            .enumerate()
+            .flat_map(|(from, block)| block.successors.iter().map(move |to| (from, *to)))
+            .collect()
+    }
+
+    /// Index of the block an op is in
+    pub fn block_of(&self, op_idx: usize) -> Option<usize> {
+        self.blocks
+            .iter()
+            .position(|b| b.start <= op_idx && op_idx < b.end)
+    }
+}
+
+impl Bytecode {
+    /// Splits the ops into basic blocks. A block starts at the first op, at anything jumped to,
+    /// at a function's entry and after a jump or return
+    pub fn build_cfg(&self) -> Cfg {
+        let len = self.ops.len();
+        let targets: Vec<Option<usize>> = self.instructions().map(|(_, _, t)| t).collect();
+        let mut leaders = BTreeSet::new();
+        if len > 0 {
+            leaders.insert(0);
+        }
+        for (idx, op) in self.ops.iter().enumerate() {
+            // a jump can land just past the last op to halt, that isn't a block
+            if let Some(target) = targets[idx].filter(|t| *t < len) {
+                leaders.insert(target);
+            }
+            if (targets[idx].is_some() || matches!(op, ByteOp::Return)) && idx + 1 < len {
+                leaders.insert(idx + 1);
+            }
+        }
+        // functions are only entered by calls so nothing jumps to their first op
+        for constant in &self.constants {
+            if let Value::Object(o) = constant {
+                if let Some(function) = o.downcast_ref::<object::Function>() {
+                    if function.entry < len {
+                        leaders.insert(function.entry);
+                    }
+                }
+            }
+        }
+
+        let starts: Vec<usize> = leaders.into_iter().collect();
+        let block_at = |op_idx: usize| starts.binary_search(&op_idx).ok();
+        let blocks = starts
+            .iter()
+            .enumerate()
+            .map(|(idx, start)| {
+                let end = starts.get(idx + 1).cloned().unwrap_or(len);
+                let last = end - 1;
+                let mut successors = Vec::new();
+                if !matches!(self.ops[last], ByteOp::Jump(_) | ByteOp::Return) {
+                    successors.extend(block_at(end));
+                }
+                if let Some(target) = targets[last].and_then(block_at) {
+                    if !successors.contains(&target) {
+                        successors.push(target);
+                    }
+                }
+                BasicBlock {
+                    start: *start,
+                    end,
+                    successors,
+                }
+            })
+            .collect();
+
+        Cfg { blocks }
+    }
+}
+
+impl Bytecode {
+    /// Indices of the ops that can never run when starting from the first op, like anything
+    /// after a return that nothing jumps to
+    pub fn unreachable_ops(&self) -> Vec<usize> {
+        self.unreachable_ops_from(0)
+    }
+
+    /// Same as `unreachable_ops` but starts from `start` and only reports ops from there on,
+    /// for bytecode added to with `Bytecode::append_global`
+    pub fn unreachable_ops_from(&self, start: usize) -> Vec<usize> {
+        let cfg = self.build_cfg();
+        let mut reached = vec![false; cfg.blocks.len()];
+        let mut queue: Vec<usize> = cfg.block_of(start).into_iter().collect();
+        while let Some(idx) = queue.pop() {
+            if reached[idx] {
+                continue;
+            }
+            reached[idx] = true;
+            let block = &cfg.blocks[idx];
+            queue.extend(&block.successors);
+            // a function's body can run once a closure of it is made
+            for op in &self.ops[block.start..block.end] {
+                if let ByteOp::Closure(n) = op {
+                    if let Some(Value::Object(o)) = self.constants.get(*n as usize) {
+                        if let Some(function) = o.downcast_ref::<object::Function>() {
+                            queue.extend(cfg.block_of(function.entry));
+                        }
+                    }
+                }
+            }
+        }
+
+        cfg.blocks
+            .iter()
+            .zip(reached)
+            .filter(|(_, reached)| !reached)
+            .flat_map(|(block, _)| block.start..block.end)
+            .filter(|idx| *idx >= start)
+            .collect()
+    }
+}
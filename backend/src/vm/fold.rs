@@ -0,0 +1,155 @@
+use super::ast::{
+    visitor, Ast, AstNode, BinaryOperation, TypeSignature, UnaryOperation, VisitorMut,
+};
+use super::cast_number;
+use core::Position;
+use std::cmp::Ordering;
+
+/// Evaluates operations whose operands are all literals ahead of time, following the same
+/// rules the vm uses at runtime. Anything the vm would reject, such as dividing by zero or
+/// adding a bool to a number, is left alone so the error is still raised when it runs.
+/// Dividing integers is only folded when the result is whole so the literal stays an integer,
+/// and integer arithmetic that would wrap in `NumericMode::IntegerWrap` is left to the vm.
+pub fn fold_constants(ast: Ast) -> Ast {
+    let mut node = AstNode {
+        node: ast,
+        pos: Position { line: 0, col: 0 },
+        type_sig: None,
+    };
+    ConstantFolder.visit_node_mut(&mut node);
+    node.node
+}
+
+struct ConstantFolder;
+
+impl VisitorMut for ConstantFolder {
+    fn visit_binary_mut(&mut self, node: &mut AstNode) {
+        visitor::walk_mut(self, node);
+        if let Ast::Binary(op, l, r) = &node.node {
+            if let Some(folded) = fold_binary(*op, &l.node, &r.node) {
+                if fits_type(&folded, &node.type_sig) {
+                    node.node = folded;
+                }
+            }
+        }
+    }
+
+    fn visit_unary_mut(&mut self, node: &mut AstNode) {
+        visitor::walk_mut(self, node);
+        if let Ast::Unary(op, expr) = &node.node {
+            if let Some(folded) = fold_unary(*op, &expr.node) {
+                node.node = folded;
+            }
+        }
+    }
+}
+
+// the vm only has one number type, integer literals stay integers as long as the result is whole
+fn as_number(ast: &Ast) -> Option<(f64, bool)> {
+    match ast {
+        Ast::Integer(n) => Some((*n as f64, true)),
+        Ast::Float(n) => Some((*n, false)),
+        _ => None,
+    }
+}
+
+fn number_literal(n: f64, integer: bool) -> Ast {
+    // 2^53, past this point an f64 can't represent every integer
+    if integer && n.fract() == 0.0 && n.abs() <= 9_007_199_254_740_992.0 {
+        Ast::Integer(n as i64)
+    } else {
+        Ast::Float(n)
+    }
+}
+
+// whether a folded number is the same after wrapping it to its integer type,
+// otherwise the result depends on the vm's numeric mode
+fn fits_type(folded: &Ast, type_sig: &Option<TypeSignature>) -> bool {
+    match (as_number(folded), type_sig) {
+        (Some((n, _)), Some(type_sig @ TypeSignature::Primitive(prim)))
+            if type_sig.is_integer() =>
+        {
+            cast_number(n, *prim) == Some(n)
+        }
+        _ => true,
+    }
+}
+
+// mirrors ByteOp::Equal, values of different types are never equal
+fn literals_equal(l: &Ast, r: &Ast) -> Option<bool> {
+    match (l, r) {
+        (Ast::Bool(a), Ast::Bool(b)) => Some(a == b),
+        (Ast::Bool(_), _) | (_, Ast::Bool(_)) => {
+            as_number(l).or_else(|| as_number(r)).map(|_| false)
+        }
+        _ => {
+            let (a, _) = as_number(l)?;
+            let (b, _) = as_number(r)?;
+            Some((a - b).abs() < f64::EPSILON)
+        }
+    }
+}
+
+fn fold_binary(op: BinaryOperation, l: &Ast, r: &Ast) -> Option<Ast> {
+    match op {
+        BinaryOperation::Add
+        | BinaryOperation::Subtract
+        | BinaryOperation::Multiply
+        | BinaryOperation::Divide => {
+            let (a, a_int) = as_number(l)?;
+            let (b, b_int) = as_number(r)?;
+            let n = match op {
+                BinaryOperation::Add => a + b,
+                BinaryOperation::Subtract => a - b,
+                BinaryOperation::Multiply => a * b,
+                // the vm raises an error when dividing by zero so that's left for it to report
+                BinaryOperation::Divide if b == 0.0 => return None,
+                // `7 / 2` is 3.5 in the vm, folding it would turn an integer expression into a float literal
+                BinaryOperation::Divide if a_int && b_int && (a / b).fract() != 0.0 => return None,
+                _ => a / b,
+            };
+            Some(number_literal(n, a_int && b_int))
+        }
+        BinaryOperation::Less
+        | BinaryOperation::LessEqual
+        | BinaryOperation::Greater
+        | BinaryOperation::GreaterEqual => {
+            let (a, _) = as_number(l)?;
+            let (b, _) = as_number(r)?;
+            // <= and >= compile to the negation of > and <, which matters once a NaN is involved
+            let ord = a.partial_cmp(&b);
+            Some(Ast::Bool(match op {
+                BinaryOperation::Less => ord == Some(Ordering::Less),
+                BinaryOperation::LessEqual => ord != Some(Ordering::Greater),
+                BinaryOperation::Greater => ord == Some(Ordering::Greater),
+                _ => ord != Some(Ordering::Less),
+            }))
+        }
+        BinaryOperation::Equal => literals_equal(l, r).map(Ast::Bool),
+        BinaryOperation::NotEqual => literals_equal(l, r).map(|b| Ast::Bool(!b)),
+        BinaryOperation::And | BinaryOperation::Or => match (l, r) {
+            (Ast::Bool(a), Ast::Bool(b)) => Some(Ast::Bool(if let BinaryOperation::And = op {
+                *a && *b
+            } else {
+                *a || *b
+            })),
+            _ => None,
+        },
+        BinaryOperation::Assign => None,
+    }
+}
+
+fn fold_unary(op: UnaryOperation, expr: &Ast) -> Option<Ast> {
+    match (op, expr) {
+        (UnaryOperation::Negate, _) => {
+            let (n, integer) = as_number(expr)?;
+            Some(number_literal(-n, integer))
+        }
+        (UnaryOperation::Plus, _) => {
+            as_number(expr)?;
+            Some(expr.clone())
+        }
+        (UnaryOperation::Not, Ast::Bool(b)) => Some(Ast::Bool(!b)),
+        (UnaryOperation::Not, _) => None,
+    }
+}
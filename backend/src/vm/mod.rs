@@ -1,13 +1,24 @@
 use super::ast;
 
 use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
 use std::collections::hash_map::*;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::io::Write;
+use std::rc::Rc;
 
-use core::Position;
+use core::{GravitonError, Notice, NoticeLevel, Position};
 
+pub mod builder;
+pub mod cfg;
+pub mod fold;
 pub mod object;
 pub mod stdlib;
 
+#[cfg(test)]
+mod tests;
+
 #[derive(Clone, Debug)]
 pub struct VmError {
     pub msg: String,
@@ -18,6 +29,46 @@ impl VmError {
     fn new(msg: String, ast: &ast::AstNode) -> VmError {
         VmError { msg, pos: ast.pos }
     }
+
+    pub fn to_notice(&self) -> Notice {
+        Notice {
+            from: "VM".to_string(),
+            msg: self.msg.clone(),
+            pos: self.pos,
+            file: None,
+            level: NoticeLevel::Error,
+        }
+    }
+
+    // a line of -1 marks an error that didn't come from any particular op
+    fn known_pos(&self) -> Option<Position> {
+        if self.pos.line == -1 {
+            None
+        } else {
+            Some(self.pos)
+        }
+    }
+
+    pub fn to_compile_error(&self) -> GravitonError {
+        GravitonError::Compile {
+            msg: self.msg.clone(),
+            pos: self.known_pos(),
+        }
+    }
+
+    pub fn to_runtime_error(&self) -> GravitonError {
+        GravitonError::Runtime {
+            msg: self.msg.clone(),
+            pos: self.known_pos(),
+        }
+    }
+}
+
+// shown the same way as the `GravitonError` it becomes
+impl std::fmt::Display for VmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_runtime_error())
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -26,6 +77,145 @@ pub enum Value {
     Number(f64),
     Bool(bool),
     Object(Box<dyn object::StackVmObject>),
+    // arrays are only made while running so they never end up in serialized constants
+    #[serde(skip)]
+    Array(Rc<RefCell<Vec<Value>>>),
+    // shared like arrays so setting a field through a variable changes the struct it holds
+    #[serde(skip)]
+    Struct(Rc<RefCell<StructValue>>),
+}
+
+#[derive(Clone, Debug)]
+pub struct StructValue {
+    pub name: String,
+    pub fields: HashMap<String, Value>,
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            // objects have no general notion of equality
+            _ => false,
+        }
+    }
+}
+
+impl Value {
+    pub fn typename(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Number(_) => "number",
+            Value::Bool(_) => "bool",
+            Value::Object(o) => o.typename(),
+            Value::Array(_) => "array",
+            Value::Struct(_) => "struct",
+        }
+    }
+}
+
+/// The id a variable is referred to by in bytecode. It's the 64 bit FNV-1a hash of the name, a
+/// fixed algorithm so the ids baked into serialized bytecode don't change between builds.
+pub fn var_id(name: &str) -> u64 {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0100_0000_01b3;
+    name.bytes().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+// what `==` evaluates to, it never fails: arrays, structs and strings are compared by what they
+// hold and values of different types are never equal
+fn values_equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Nil, Value::Nil) => true,
+        (Value::Bool(a), Value::Bool(b)) => a == b,
+        (Value::Number(a), Value::Number(b)) => (a - b).abs() < f64::EPSILON,
+        (Value::Array(a), Value::Array(b)) => {
+            let (a, b) = (a.borrow(), b.borrow());
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| values_equal(a, b))
+        }
+        (Value::Struct(a), Value::Struct(b)) => {
+            let (a, b) = (a.borrow(), b.borrow());
+            a.name == b.name
+                && a.fields.len() == b.fields.len()
+                && a.fields.iter().all(|(name, a)| match b.fields.get(name) {
+                    Some(b) => values_equal(a, b),
+                    None => false,
+                })
+        }
+        (Value::Object(a), Value::Object(b)) => {
+            match (a.downcast_ref::<String>(), b.downcast_ref::<String>()) {
+                (Some(a), Some(b)) => a == b,
+                // functions and any other objects are never equal
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+// formats the error for an operation given a value of a type it doesn't support
+fn type_error(op: &str, v: &Value) -> String {
+    format!("Unsupported operand type for {}: {}", op, v.typename())
+}
+
+// integer types truncate toward zero and wrap around like an integer cast would,
+// casting to anything that isn't a number is not supported
+fn cast_number(n: f64, to: ast::PrimitiveType) -> Option<f64> {
+    let i = n.trunc() as i64;
+    Some(match to {
+        ast::PrimitiveType::I8 => i as i8 as f64,
+        ast::PrimitiveType::I16 => i as i16 as f64,
+        ast::PrimitiveType::I32 => i as i32 as f64,
+        ast::PrimitiveType::I64 => i as f64,
+        ast::PrimitiveType::U8 => i as u8 as f64,
+        ast::PrimitiveType::U16 => i as u16 as f64,
+        ast::PrimitiveType::U32 => i as u32 as f64,
+        // values past i64::MAX would saturate, so positive numbers go straight to u64
+        ast::PrimitiveType::U64 if n >= 0.0 => n.trunc() as u64 as f64,
+        ast::PrimitiveType::U64 => i as u64 as f64,
+        ast::PrimitiveType::F32 => n as f32 as f64,
+        ast::PrimitiveType::F64 => n,
+        ast::PrimitiveType::Bool | ast::PrimitiveType::Nil => return None,
+    })
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Object(o) => write!(f, "{}", o),
+            Value::Array(elems) => {
+                write!(f, "[")?;
+                for (idx, elem) in elems.borrow().iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", elem)?;
+                }
+                write!(f, "]")
+            }
+            Value::Struct(value) => {
+                let value = value.borrow();
+                // fields are in a hash map so they're sorted to print the same way every time
+                let mut fields: Vec<(&String, &Value)> = value.fields.iter().collect();
+                fields.sort_by(|a, b| a.0.cmp(b.0));
+                write!(f, "{} {{ ", value.name)?;
+                for (idx, (name, field)) in fields.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", name, field)?;
+                }
+                write!(f, " }}")
+            }
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
@@ -50,50 +240,580 @@ pub enum ByteOp {
     Less,
 
     Negate,
+    // leaves a number as it is, only checks its type
+    Plus,
+    // converts a number to the range of a primitive type, it stays an f64
+    Cast(ast::PrimitiveType),
+    // follows integer arithmetic, only does anything when the vm is in integer mode
+    Wrap(ast::PrimitiveType),
 
     ScopeOpen,
     ScopeClose,
 
-    NativeFnCall(u16, u8),
-
-    DefVar(u16),
-    DefMutVar(u16),
-    SetVar(u16),
-    GetVar(u16),
+    NativeFnCall(u64, u8),
+    Call(u8),
+    Closure(u16),
+
+    DefVar(u64),
+    DefMutVar(u64),
+    // pops the value to store, an assignment that is used as a value dups it first
+    SetVar(u64),
+    GetVar(u64),
+
+    // pops that many values into a new array, the first one pushed is the first element
+    MakeArray(u16),
+    // pops an index then an array and pushes the element at that index
+    Index,
+    // pops a value, an index then an array, stores the value at the index and leaves it on the stack
+    SetIndex,
+    // takes the constant holding the struct name, the field names are in the constants after it,
+    // pops a value for each field, the first one pushed goes to the first field
+    MakeStruct(u16, u16),
+    // pops a struct and pushes the field named by the string constant
+    GetField(u16),
+    // pops a value then a struct, stores the value in the named field and leaves it on the stack
+    SetField(u16),
 
     Jump(i16),
     JumpFalse(i16),
     JumpTrue(i16),
 
     Pop,
+    // discards that many values at once, errors if the stack doesn't have them
+    PopN(u16),
+    // pushes a copy of the value on top of the stack
+    Dup,
+    // exchanges the top two values on the stack
+    Swap,
+    Print,
+    PrintLn,
+    // errors unless the bool on top of the stack is true, leaves nil behind
+    Assert,
     Return,
 }
 
+#[derive(Clone, Debug, Default)]
+struct LoopContext {
+    begin_idx: usize,
+    scope_depth: usize,
+    // operands left waiting on the stack when the loop began
+    operand_depth: usize,
+    break_idxs: Vec<usize>,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Bytecode {
     constants: Vec<Value>,
     ops: Vec<ByteOp>,
     positions: Vec<Position>,
 
-    #[cfg(feature = "store_names")]
-    names: HashMap<u16, String>,
+    // function name, constant index of the function prototype
+    pub entry_points: HashMap<String, u16>,
+
+    #[serde(skip)]
+    fn_depth: usize,
+    #[serde(skip)]
+    scope_depth: usize,
+    // scope depth a return unwinds to, the depth the enclosing function was defined at
+    #[serde(skip)]
+    fn_scope_depth: usize,
+    #[serde(skip)]
+    loops: Vec<LoopContext>,
+    // operands of enclosing expressions waiting on the stack for the code being compiled,
+    // a break or continue has to pop them since it never gets back to the op that would
+    #[serde(skip)]
+    operand_depth: usize,
+    // variables declared in each scope being compiled and whether they are mutable,
+    // used to catch bad assignments before running
+    #[serde(skip)]
+    declared: Vec<HashMap<u64, bool>>,
+    #[serde(skip)]
+    fold: bool,
+
+    // only filled in when the store_names feature is on, the field is always there so bytecode has
+    // the same layout either way
+    names: HashMap<u64, String>,
+}
+
+pub const DEFAULT_MAX_AST_DEPTH: usize = 256;
+pub const DEFAULT_MAX_STACK: usize = 1_000_000;
+pub const DEFAULT_MAX_FRAMES: usize = 1024;
+
+/// How the vm treats arithmetic on numbers typed as integers.
+///
+/// Every number is an f64 at runtime so the width of an integer is taken from the type the
+/// semantic analyzer gave the expression when it was compiled. Bytecode compiled from an
+/// untyped AST never wraps, and only `+`, `-` and `*` wrap; division still produces a fraction.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NumericMode {
+    Float,
+    IntegerWrap,
 }
 
+const BYTECODE_MAGIC: [u8; 4] = *b"GRBC";
+// bumped whenever the layout of Bytecode or ByteOp changes, or what an op does to the stack
+const BYTECODE_VERSION: u16 = 14;
+
 impl Bytecode {
-    pub fn new(ast: ast::AstNode) -> Result<Bytecode, VmError> {
-        let mut bc = Bytecode {
+    pub fn new(ast: ast::AstNode) -> Result<Bytecode, GravitonError> {
+        Bytecode::with_max_depth(ast, DEFAULT_MAX_AST_DEPTH)
+    }
+
+    pub fn with_max_depth(ast: ast::AstNode, max_depth: usize) -> Result<Bytecode, GravitonError> {
+        Bytecode::compile(ast, max_depth).map_err(|e| e.to_compile_error())
+    }
+
+    fn compile(ast: ast::AstNode, max_depth: usize) -> Result<Bytecode, VmError> {
+        check_depth(&ast, max_depth)?;
+        let mut bc = Bytecode::empty();
+        ast_to_bytecode(&mut bc, &ast)?;
+        bc.emit(&ast, ByteOp::Return);
+        Ok(bc)
+    }
+
+    pub fn empty() -> Bytecode {
+        Bytecode {
             constants: Vec::new(),
             ops: Vec::new(),
             positions: Vec::new(),
 
-            #[cfg(feature = "store_names")]
+            entry_points: HashMap::new(),
+            fn_depth: 0,
+            scope_depth: 0,
+            fn_scope_depth: 0,
+            loops: Vec::new(),
+            operand_depth: 0,
+            declared: vec![HashMap::new()],
+            fold: false,
+
             names: HashMap::new(),
+        }
+    }
+
+    // compiles a block's expressions onto the end of the bytecode without giving them a scope of their own,
+    // so variables they define go into the global scope of a vm set up with `StackVm::push_global_scope`
+    // and functions they define stay callable from code appended later,
+    // returns the index of the first new instruction
+    pub fn append_global(&mut self, ast: ast::AstNode) -> Result<usize, VmError> {
+        check_depth(&ast, DEFAULT_MAX_AST_DEPTH)?;
+        let ast = if self.fold {
+            ast::AstNode {
+                node: fold::fold_constants(ast.node),
+                ..ast
+            }
+        } else {
+            ast
         };
-        ast_to_bytecode(&mut bc, &ast)?;
+
+        let start_idx = self.ops.len();
+        let constants_len = self.constants.len();
+        let declared = self.declared.clone();
+        let result = match &ast.node {
+            ast::Ast::Block(exprs) => block_body_to_bytecode(self, &ast, exprs),
+            _ => ast_to_bytecode(self, &ast),
+        }
+        .map(|_| self.emit(&ast, ByteOp::Return));
+
+        // a failed compile is rolled back so the bytecode can still be appended to
+        if let Err(e) = result {
+            self.ops.truncate(start_idx);
+            self.positions.truncate(start_idx);
+            self.constants.truncate(constants_len);
+            self.entry_points
+                .retain(|_, idx| (*idx as usize) < constants_len);
+            self.fn_depth = 0;
+            self.scope_depth = 0;
+            self.fn_scope_depth = 0;
+            self.loops.clear();
+            self.operand_depth = 0;
+            self.declared = declared;
+            return Err(e);
+        }
+
+        Ok(start_idx)
+    }
+
+    /// Whether code added with `append_global` from now on has its constant expressions
+    /// evaluated ahead of time with `fold::fold_constants`
+    pub fn set_fold_constants(&mut self, fold: bool) {
+        self.fold = fold;
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let mut bytes = BYTECODE_MAGIC.to_vec();
+        bytes.extend_from_slice(&BYTECODE_VERSION.to_le_bytes());
+        match bincode::serialize(self) {
+            Ok(body) => bytes.extend(body),
+            Err(e) => return Err(format!("Failed to serialize bytecode: {}", e)),
+        }
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Bytecode, String> {
+        let header_len = BYTECODE_MAGIC.len() + 2;
+        if bytes.len() < header_len || bytes[..BYTECODE_MAGIC.len()] != BYTECODE_MAGIC[..] {
+            return Err("Not a Graviton bytecode file".to_string());
+        }
+
+        let version = u16::from_le_bytes([bytes[BYTECODE_MAGIC.len()], bytes[header_len - 1]]);
+        if version != BYTECODE_VERSION {
+            return Err(format!(
+                "Bytecode version {} is not supported; expected version {}",
+                version, BYTECODE_VERSION
+            ));
+        }
+
+        let bc: Bytecode = bincode::deserialize(&bytes[header_len..])
+            .map_err(|e| format!("Failed to deserialize bytecode: {}", e))?;
+        bc.validate()?;
         Ok(bc)
     }
 
+    // checks that every constant index and jump target is in range,
+    // so bytecode that was not made by the compiler can't make the vm index out of bounds
+    pub fn validate(&self) -> Result<(), String> {
+        for (idx, op) in self.ops.iter().enumerate() {
+            match op {
+                ByteOp::Load(n)
+                | ByteOp::Closure(n)
+                | ByteOp::GetField(n)
+                | ByteOp::SetField(n)
+                    if *n as usize >= self.constants.len() =>
+                {
+                    return Err(format!(
+                        "op {}: {:?} exceeds {} constants",
+                        idx,
+                        op,
+                        self.constants.len()
+                    ));
+                }
+                ByteOp::MakeStruct(n, count)
+                    if *n as usize + *count as usize >= self.constants.len() =>
+                {
+                    return Err(format!(
+                        "op {}: {:?} exceeds {} constants",
+                        idx,
+                        op,
+                        self.constants.len()
+                    ));
+                }
+                ByteOp::Jump(d) | ByteOp::JumpFalse(d) | ByteOp::JumpTrue(d) => {
+                    // landing just past the last op is allowed, the vm halts there
+                    let target = idx as isize + *d as isize;
+                    if target < 0 || target as usize > self.ops.len() {
+                        return Err(format!(
+                            "op {}: {:?} lands at {} outside of {} ops",
+                            idx,
+                            op,
+                            target,
+                            self.ops.len()
+                        ));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        for (idx, constant) in self.constants.iter().enumerate() {
+            if let Value::Object(o) = constant {
+                if let Some(function) = o.downcast_ref::<object::Function>() {
+                    if function.entry >= self.ops.len() {
+                        return Err(format!(
+                            "constant {}: function entry {} outside of {} ops",
+                            idx,
+                            function.entry,
+                            self.ops.len()
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn ops(&self) -> &[ByteOp] {
+        &self.ops
+    }
+
+    pub fn constants(&self) -> &[Value] {
+        &self.constants
+    }
+
+    /// Each op with its index and, for jumps, the index of the op it jumps to
+    pub fn instructions(&self) -> impl Iterator<Item = (usize, &ByteOp, Option<usize>)> {
+        self.ops.iter().enumerate().map(|(idx, op)| {
+            // jumps are stored relative to the op they're in
+            let target = match op {
+                ByteOp::Jump(d) | ByteOp::JumpFalse(d) | ByteOp::JumpTrue(d) => {
+                    Some((idx as isize + *d as isize) as usize)
+                }
+                _ => None,
+            };
+            (idx, op, target)
+        })
+    }
+
+    pub fn disassemble(&self) -> String {
+        let mut out = String::new();
+        for (idx, op, target) in self.instructions() {
+            let line = match (op, target) {
+                (ByteOp::Load(n), _) => format!("Load {} ({})", n, self.disassemble_constant(*n)),
+                (ByteOp::Jump(_), Some(target)) => format!("Jump -> {:04}", target),
+                (ByteOp::JumpFalse(_), Some(target)) => format!("JumpFalse -> {:04}", target),
+                (ByteOp::JumpTrue(_), Some(target)) => format!("JumpTrue -> {:04}", target),
+                (ByteOp::Closure(n), _) => {
+                    format!("Closure {} ({})", n, self.disassemble_constant(*n))
+                }
+                (ByteOp::DefVar(id), _) => self.disassemble_var("DefVar", *id),
+                (ByteOp::DefMutVar(id), _) => self.disassemble_var("DefMutVar", *id),
+                (ByteOp::SetVar(id), _) => self.disassemble_var("SetVar", *id),
+                (ByteOp::GetVar(id), _) => self.disassemble_var("GetVar", *id),
+                (ByteOp::NativeFnCall(id, args), _) => {
+                    format!("{} {}", self.disassemble_var("NativeFnCall", *id), args)
+                }
+                (op, _) => format!("{:?}", op),
+            };
+            out.push_str(&format!("{:04} {}\n", idx, line));
+        }
+        out
+    }
+
+    // disassembly is meant for looking at bad bytecode too so it may not have been validated
+    fn disassemble_constant(&self, idx: u16) -> String {
+        match self.constants.get(idx as usize) {
+            Some(constant) => format!("{:?}", constant),
+            None => "out of range".to_string(),
+        }
+    }
+
+    // the variable names are only known when they're stored, otherwise just the id is shown
+    fn disassemble_var(&self, op: &str, id: u64) -> String {
+        match self.names.get(&id) {
+            Some(name) => format!("{} {} ({})", op, id, name),
+            None => format!("{} {}", op, id),
+        }
+    }
+
+    /// How a variable id is shown in errors, its quoted name if it was stored and the id if not
+    pub fn var_name(&self, id: u64) -> String {
+        match self.names.get(&id) {
+            Some(name) => format!("'{}'", name),
+            None => id.to_string(),
+        }
+    }
+
+    /// Removes ops that don't change the result of running the bytecode, returning how many were removed
+    pub fn optimize(&mut self) -> usize {
+        self.optimize_from(0)
+    }
+
+    /// Same as `optimize` but leaves everything before `start` where it is, for bytecode that
+    /// was appended to after the earlier code ran and may still be referenced by the vm
+    pub fn optimize_from(&mut self, start: usize) -> usize {
+        let mut removed = 0;
+        loop {
+            let pass = self.peephole(start);
+            if pass == 0 {
+                return removed;
+            }
+            removed += pass;
+        }
+    }
+
+    fn peephole(&mut self, start: usize) -> usize {
+        let len = self.ops.len();
+
+        // ops that are jumped to can only be removed if they are the first op of a pattern,
+        // anything jumping there is moved to the op following the pattern
+        let mut targets = HashSet::new();
+        for (_, _, target) in self.instructions().skip(start) {
+            if let Some(target) = target {
+                targets.insert(target);
+            }
+        }
+        for constant in &self.constants {
+            if let Value::Object(o) = constant {
+                if let Some(function) = o.downcast_ref::<object::Function>() {
+                    targets.insert(function.entry);
+                }
+            }
+        }
+
+        let mut keep = vec![true; len];
+        let mut idx = start;
+        while idx < len {
+            let next = self.ops.get(idx + 1);
+            // a run of pops is done in one step, only the first of them can be jumped to
+            let run = self.ops[idx..]
+                .iter()
+                .enumerate()
+                .take_while(|(k, op)| {
+                    matches!(op, ByteOp::Pop | ByteOp::PopN(_))
+                        && (*k == 0 || !targets.contains(&(idx + k)))
+                })
+                .map(|(_, op)| match op {
+                    ByteOp::PopN(n) => *n as usize,
+                    _ => 1,
+                })
+                .collect::<Vec<usize>>();
+            if run.len() > 1 {
+                if let Ok(count) = u16::try_from(run.iter().sum::<usize>()) {
+                    self.ops[idx] = ByteOp::PopN(count);
+                    for k in &mut keep[idx + 1..idx + run.len()] {
+                        *k = false;
+                    }
+                    idx += run.len();
+                    continue;
+                }
+            }
+            // an assignment used as a statement, the copy of the assigned value is never read
+            if let (ByteOp::Dup, Some(ByteOp::SetVar(_)), Some(ByteOp::Pop)) =
+                (&self.ops[idx], next, self.ops.get(idx + 2))
+            {
+                if !targets.contains(&(idx + 1)) && !targets.contains(&(idx + 2)) {
+                    keep[idx] = false;
+                    keep[idx + 2] = false;
+                    idx += 3;
+                    continue;
+                }
+            }
+            let pattern_len = match (&self.ops[idx], next) {
+                // a value that is pushed and immediately discarded
+                (ByteOp::Load(_), Some(ByteOp::Pop))
+                | (ByteOp::True, Some(ByteOp::Pop))
+                | (ByteOp::False, Some(ByteOp::Pop))
+                | (ByteOp::Nil, Some(ByteOp::Pop))
+                    if !targets.contains(&(idx + 1)) =>
+                {
+                    2
+                }
+                // only safe when the value is known to be a bool, otherwise the first not raises an error
+                (ByteOp::Not, Some(ByteOp::Not))
+                    if idx > start
+                        && !targets.contains(&idx)
+                        && !targets.contains(&(idx + 1))
+                        && keep[idx - 1]
+                        && produces_bool(&self.ops[idx - 1]) =>
+                {
+                    2
+                }
+                // reading back a variable that was just defined only to discard it
+                (ByteOp::GetVar(id), Some(ByteOp::Pop))
+                    if idx > start
+                        && !targets.contains(&idx)
+                        && !targets.contains(&(idx + 1))
+                        && keep[idx - 1]
+                        && matches!(
+                            self.ops[idx - 1],
+                            ByteOp::DefVar(def) | ByteOp::DefMutVar(def) if def == *id
+                        ) =>
+                {
+                    2
+                }
+                // a jump to the next op or a pop of nothing
+                (ByteOp::Jump(1), _) | (ByteOp::PopN(0), _) => 1,
+                _ => 0,
+            };
+            for k in &mut keep[idx..idx + pattern_len] {
+                *k = false;
+            }
+            idx += pattern_len.max(1);
+        }
+
+        let removed = keep.iter().filter(|k| !**k).count();
+        if removed == 0 {
+            return 0;
+        }
+
+        // where each op ends up, a removed op maps to the op that followed it
+        let mut new_idx = Vec::with_capacity(len + 1);
+        let mut kept = 0;
+        for k in &keep {
+            new_idx.push(kept);
+            if *k {
+                kept += 1;
+            }
+        }
+        new_idx.push(kept);
+
+        for (idx, op) in self.ops.iter_mut().enumerate().skip(start) {
+            if let ByteOp::Jump(d) | ByteOp::JumpFalse(d) | ByteOp::JumpTrue(d) = op {
+                let target = (idx as isize + *d as isize) as usize;
+                *d = (new_idx[target] as isize - new_idx[idx] as isize) as i16;
+            }
+        }
+        for constant in &mut self.constants {
+            if let Value::Object(o) = constant {
+                if let Some(function) = o.downcast_mut::<object::Function>() {
+                    if function.entry >= start {
+                        function.entry = new_idx[function.entry];
+                    }
+                }
+            }
+        }
+
+        let mut keep_iter = keep.iter();
+        self.ops.retain(|_| *keep_iter.next().unwrap());
+        let mut keep_iter = keep.iter();
+        self.positions.retain(|_| *keep_iter.next().unwrap());
+        removed
+    }
+
+    // adds a constant to the end of the pool, for values that need a slot of their own
+    fn push_constant(&mut self, value: Value) -> Result<u16, String> {
+        let idx = constant_idx(self.constants.len())?;
+        self.constants.push(value);
+        Ok(idx)
+    }
+
+    // reuses an existing slot in the constant pool when an equal value is already there
+    fn intern_constant(&mut self, value: Value) -> Result<u16, String> {
+        // numbers are matched by their bits so NaN can share a slot with itself while 0.0 and -0.0 stay apart,
+        // this only affects the pool; `nan == nan` still evaluates to false at runtime
+        let position = self.constants.iter().position(|c| match (c, &value) {
+            (Value::Number(a), Value::Number(b)) => a.to_bits() == b.to_bits(),
+            (Value::Object(a), Value::Object(b)) => {
+                match (a.downcast_ref::<String>(), b.downcast_ref::<String>()) {
+                    (Some(a), Some(b)) => a == b,
+                    _ => false,
+                }
+            }
+            _ => *c == value,
+        });
+        match position {
+            Some(idx) => constant_idx(idx),
+            None => self.push_constant(value),
+        }
+    }
+
+    // the id for a variable name, remembering the name for disassembly when names are stored
+    fn name_id(&mut self, name: &str) -> u64 {
+        let id = var_id(name);
+        #[cfg(feature = "store_names")]
+        self.names.insert(id, name.to_string());
+        id
+    }
+
+    fn declare(&mut self, hash: u64, mutable: bool) {
+        if let Some(scope) = self.declared.last_mut() {
+            scope.insert(hash, mutable);
+        }
+    }
+
+    // whether the innermost declaration of a variable is mutable, none if it was never declared
+    fn declared_mutable(&self, hash: u64) -> Option<bool> {
+        self.declared
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&hash).copied())
+    }
+
     fn emit(&mut self, ast: &ast::AstNode, op: ByteOp) {
+        match op {
+            ByteOp::ScopeOpen => self.scope_depth += 1,
+            ByteOp::ScopeClose => self.scope_depth -= 1,
+            _ => {}
+        }
         self.ops.push(op);
         self.positions.push(ast.pos);
     }
@@ -102,17 +822,15 @@ impl Bytecode {
 fn module_to_bytecode(bc: &mut Bytecode, module: &ast::Module) -> Result<(), VmError> {
     bc.ops.push(ByteOp::ScopeOpen);
     bc.positions.push(Position { line: 0, col: 0 });
+    bc.scope_depth += 1;
+    bc.declared.push(HashMap::new());
     let mut idx: usize = 1;
     let len = module.expressions.len();
     for e in &module.expressions {
         match &e.node {
             ast::Ast::Statement(expr) => {
-                if let ast::Ast::Block(_) = expr.node {
-                    ast_to_bytecode(bc, &*expr)?;
-                } else if let ast::Ast::Return(rexpr) = &expr.node {
-                    ast_to_bytecode(bc, &*rexpr)?;
-                    bc.ops.push(ByteOp::Return);
-                    bc.positions.push(Position { line: 0, col: 0 });
+                if let ast::Ast::Return(rexpr) = &expr.node {
+                    return_to_bytecode(bc, expr, rexpr)?;
                 } else {
                     ast_to_bytecode(bc, &e)?
                 }
@@ -125,60 +843,207 @@ fn module_to_bytecode(bc: &mut Bytecode, module: &ast::Module) -> Result<(), VmE
                     ));
                 }
                 if let ast::Ast::Return(rexpr) = &e.node {
-                    ast_to_bytecode(bc, &*rexpr)?;
-                    bc.ops.push(ByteOp::Return);
-                    bc.positions.push(Position { line: 0, col: 0 });
+                    return_to_bytecode(bc, e, rexpr)?;
                 } else {
                     ast_to_bytecode(bc, &e)?;
-                    bc.ops.push(ByteOp::Return);
-                    bc.positions.push(Position { line: 0, col: 0 });
                 }
             }
         }
         idx += 1;
     }
+    // a module that ends in a statement still leaves a value
+    if let None | Some(ast::Ast::Statement(_)) = module.expressions.last().map(|e| &e.node) {
+        bc.ops.push(ByteOp::Nil);
+        bc.positions.push(Position { line: 0, col: 0 });
+    }
     bc.ops.push(ByteOp::ScopeClose);
     bc.positions.push(Position { line: 0, col: 0 });
+    bc.scope_depth -= 1;
+    bc.declared.pop();
+    Ok(())
+}
+
+// compilation is recursive so overly nested trees are rejected before they can overflow the stack
+fn check_depth(ast: &ast::AstNode, max_depth: usize) -> Result<(), VmError> {
+    let depth = ast.depth();
+    if depth > max_depth {
+        return Err(VmError::new(
+            format!(
+                "Expression is nested too deeply; depth of {} exceeds the maximum of {}",
+                depth, max_depth
+            ),
+            ast,
+        ));
+    }
+    Ok(())
+}
+
+fn block_body_to_bytecode(
+    bc: &mut Bytecode,
+    ast: &ast::AstNode,
+    exprs: &[ast::AstNode],
+) -> Result<(), VmError> {
+    for (idx, e) in exprs.iter().enumerate() {
+        let is_last = idx + 1 == exprs.len();
+        match &e.node {
+            ast::Ast::Statement(expr) => {
+                if let ast::Ast::Return(rexpr) = &expr.node {
+                    return_to_bytecode(bc, expr, rexpr)?;
+                    if !is_last {
+                        discard_unreachable(bc, ast, &exprs[idx + 1..])?;
+                        // the block still leaves a value even though it can't be reached
+                        bc.emit(ast, ByteOp::Nil);
+                        return Ok(());
+                    }
+                } else {
+                    ast_to_bytecode(bc, e)?
+                }
+            }
+            _ => {
+                if !is_last {
+                    return Err(VmError::new(
+                        "Only the last element in a block may be an expression".to_string(),
+                        e,
+                    ));
+                }
+                if let ast::Ast::Return(rexpr) = &e.node {
+                    return_to_bytecode(bc, e, rexpr)?;
+                } else {
+                    // the tail expression's value is left on the stack as the block's value
+                    ast_to_bytecode(bc, e)?;
+                }
+            }
+        }
+    }
+    // blocks always leave exactly one value, nil when there is no tail expression
+    if let None | Some(ast::Ast::Statement(_)) = exprs.last().map(|e| &e.node) {
+        bc.emit(ast, ByteOp::Nil);
+    }
+    Ok(())
+}
+
+// code after a return never runs, it is still compiled so mistakes in it are reported but none of it is kept
+fn discard_unreachable(
+    bc: &mut Bytecode,
+    ast: &ast::AstNode,
+    exprs: &[ast::AstNode],
+) -> Result<(), VmError> {
+    let ops_len = bc.ops.len();
+    let constants_len = bc.constants.len();
+    let entry_points = bc.entry_points.clone();
+    block_body_to_bytecode(bc, ast, exprs)?;
+    bc.ops.truncate(ops_len);
+    bc.positions.truncate(ops_len);
+    bc.constants.truncate(constants_len);
+    bc.entry_points = entry_points;
+    Ok(())
+}
+
+// a return leaves every block it is nested in, so the scopes they opened are closed before returning
+// and the vm sees it at the depth of the enclosing function
+fn return_to_bytecode(
+    bc: &mut Bytecode,
+    ast: &ast::AstNode,
+    rexpr: &ast::AstNode,
+) -> Result<(), VmError> {
+    ast_to_bytecode(bc, rexpr)?;
+
+    let scope_depth = bc.scope_depth;
+    for _ in bc.fn_scope_depth..scope_depth {
+        bc.emit(ast, ByteOp::ScopeClose);
+    }
+    bc.scope_depth = scope_depth;
+
+    bc.emit(ast, ByteOp::Return);
     Ok(())
 }
 
+fn produces_bool(op: &ByteOp) -> bool {
+    matches!(
+        op,
+        ByteOp::True
+            | ByteOp::False
+            | ByteOp::Equal
+            | ByteOp::Greater
+            | ByteOp::Less
+            | ByteOp::And
+            | ByteOp::Or
+    )
+}
+
+fn constant_idx(idx: usize) -> Result<u16, String> {
+    u16::try_from(idx).map_err(|_| {
+        format!(
+            "Too many constants; the maximum is {}",
+            u16::MAX as usize + 1
+        )
+    })
+}
+
+fn arg_count(ast: &ast::AstNode, args: &[ast::AstNode]) -> Result<u8, VmError> {
+    u8::try_from(args.len()).map_err(|_| {
+        VmError::new(
+            format!(
+                "Call has {} arguments; the maximum is {}",
+                args.len(),
+                u8::MAX
+            ),
+            ast,
+        )
+    })
+}
+
+fn jump_offset(ast: &ast::AstNode, from: usize, to: usize) -> Result<i16, VmError> {
+    i16::try_from(to as isize - from as isize)
+        .map_err(|_| VmError::new("Jump offset out of range".to_string(), ast))
+}
+
+// `if x = true {}` is almost always a typo for `==`, assigning a bool would otherwise pass as a condition
+fn condition_to_bytecode(bc: &mut Bytecode, cond: &ast::AstNode) -> Result<(), VmError> {
+    if let ast::Ast::Binary(ast::BinaryOperation::Assign, _, _) = cond.node {
+        return Err(VmError::new(
+            "assignment used as condition; did you mean '=='?".to_string(),
+            cond,
+        ));
+    }
+    ast_to_bytecode(bc, cond)
+}
+
+// compiles an operand while `pending` earlier operands of the same expression are on the stack
+fn operand_to_bytecode(
+    bc: &mut Bytecode,
+    ast: &ast::AstNode,
+    pending: usize,
+) -> Result<(), VmError> {
+    bc.operand_depth += pending;
+    let result = ast_to_bytecode(bc, ast);
+    bc.operand_depth -= pending;
+    result
+}
+
 fn ast_to_bytecode(bc: &mut Bytecode, ast: &ast::AstNode) -> Result<(), VmError> {
     match &ast.node {
         ast::Ast::Identifier(ident) => {
-            let hash = crc16::State::<crc16::ARC>::calculate(ident.as_bytes());
-
-            #[cfg(feature = "store_names")]
-            bc.names.insert(hash, ident.clone());
-
+            let hash = bc.name_id(ident);
             bc.emit(&ast, ByteOp::GetVar(hash));
         }
         ast::Ast::Integer(n) => {
-            for (idx, val) in bc.constants.iter().enumerate() {
-                if let Value::Number(num) = val {
-                    if (*n as f64 - *num).abs() < std::f64::EPSILON {
-                        bc.emit(&ast, ByteOp::Load(idx as u16));
-                        return Ok(());
-                    }
-                }
-            }
-            bc.constants.push(Value::Number(*n as f64));
-            bc.emit(&ast, ByteOp::Load((bc.constants.len() - 1) as u16));
+            let idx = bc
+                .intern_constant(Value::Number(*n as f64))
+                .map_err(|msg| VmError::new(msg, ast))?;
+            bc.emit(ast, ByteOp::Load(idx));
         }
         ast::Ast::Float(n) => {
-            for (idx, val) in bc.constants.iter().enumerate() {
-                if let Value::Number(num) = val {
-                    if (*n - *num).abs() < std::f64::EPSILON {
-                        bc.emit(&ast, ByteOp::Load(idx as u16));
-                        return Ok(());
-                    }
-                }
-            }
-            bc.constants.push(Value::Number(*n));
-            bc.emit(&ast, ByteOp::Load((bc.constants.len() - 1) as u16));
+            let idx = bc
+                .intern_constant(Value::Number(*n))
+                .map_err(|msg| VmError::new(msg, ast))?;
+            bc.emit(ast, ByteOp::Load(idx));
         }
         ast::Ast::String(s) => {
-            bc.constants.push(Value::Object(Box::new(s.clone())));
-            bc.emit(&ast, ByteOp::Load((bc.constants.len() - 1) as u16));
+            let idx = bc
+                .push_constant(Value::Object(Box::new(s.clone())))
+                .map_err(|msg| VmError::new(msg, ast))?;
+            bc.emit(ast, ByteOp::Load(idx));
         }
         ast::Ast::Bool(b) => {
             bc.emit(&ast, if *b { ByteOp::True } else { ByteOp::False });
@@ -191,18 +1056,46 @@ fn ast_to_bytecode(bc: &mut Bytecode, ast: &ast::AstNode) -> Result<(), VmError>
             if let ast::BinaryOperation::Assign = op {
                 if let ast::Ast::Identifier(ident) = &l.node {
                     ast_to_bytecode(bc, &*r)?;
-                    let hash = crc16::State::<crc16::ARC>::calculate(ident.as_bytes());
+                    let hash = bc.name_id(ident);
 
-                    #[cfg(feature = "store_names")]
-                    bc.names.insert(hash, ident.clone());
+                    // the vm still checks at runtime in case the variable is defined some other way
+                    match bc.declared_mutable(hash) {
+                        Some(true) => {}
+                        Some(false) => {
+                            return Err(VmError::new(
+                                format!("cannot assign to immutable variable '{}'", ident),
+                                ast,
+                            ));
+                        }
+                        None => {
+                            return Err(VmError::new(
+                                format!("assignment to undeclared variable '{}'", ident),
+                                ast,
+                            ));
+                        }
+                    }
 
-                    bc.emit(&ast, ByteOp::SetVar(hash));
+                    // an assignment evaluates to the assigned value, the copy is left on the stack
+                    bc.emit(ast, ByteOp::Dup);
+                    bc.emit(ast, ByteOp::SetVar(hash));
+                } else if let ast::Ast::Index(array, index) = &l.node {
+                    ast_to_bytecode(bc, array)?;
+                    operand_to_bytecode(bc, index, 1)?;
+                    operand_to_bytecode(bc, r, 2)?;
+                    bc.emit(ast, ByteOp::SetIndex);
+                } else if let ast::Ast::FieldAccess(expr, field) = &l.node {
+                    ast_to_bytecode(bc, expr)?;
+                    operand_to_bytecode(bc, r, 1)?;
+                    let idx = bc
+                        .intern_constant(Value::Object(Box::new(field.clone())))
+                        .map_err(|msg| VmError::new(msg, ast))?;
+                    bc.emit(ast, ByteOp::SetField(idx));
                 } else {
                     return Err(VmError::new("Must assign to variable".to_string(), &ast));
                 }
             } else {
                 ast_to_bytecode(bc, &*l)?;
-                ast_to_bytecode(bc, &*r)?;
+                operand_to_bytecode(bc, r, 1)?;
                 match op {
                     ast::BinaryOperation::Add => bc.emit(&ast, ByteOp::Add),
                     ast::BinaryOperation::Subtract => bc.emit(&ast, ByteOp::Sub),
@@ -230,12 +1123,25 @@ fn ast_to_bytecode(bc: &mut Bytecode, ast: &ast::AstNode) -> Result<(), VmError>
 
                     ast::BinaryOperation::Assign => unreachable!(),
                 };
+
+                // integer results are wrapped to the width of their type when the vm is in integer mode
+                if let ast::BinaryOperation::Add
+                | ast::BinaryOperation::Subtract
+                | ast::BinaryOperation::Multiply = op
+                {
+                    if let Some(type_sig @ ast::TypeSignature::Primitive(prim)) = &ast.type_sig {
+                        if type_sig.is_integer() {
+                            bc.emit(ast, ByteOp::Wrap(*prim));
+                        }
+                    }
+                }
             }
         }
         ast::Ast::Unary(op, expr) => {
             ast_to_bytecode(bc, &*expr)?;
             match op {
                 ast::UnaryOperation::Negate => bc.emit(&ast, ByteOp::Negate),
+                ast::UnaryOperation::Plus => bc.emit(ast, ByteOp::Plus),
                 ast::UnaryOperation::Not => bc.emit(&ast, ByteOp::Not),
             }
         }
@@ -247,54 +1153,27 @@ fn ast_to_bytecode(bc: &mut Bytecode, ast: &ast::AstNode) -> Result<(), VmError>
         }
         ast::Ast::Block(exprs) => {
             bc.emit(&ast, ByteOp::ScopeOpen);
-            let mut idx: usize = 1;
-            let len = exprs.len();
-            for e in exprs {
-                match &e.node {
-                    ast::Ast::Statement(expr) => {
-                        if let ast::Ast::Block(_) = expr.node {
-                            ast_to_bytecode(bc, &*expr)?;
-                        } else if let ast::Ast::Return(rexpr) = &expr.node {
-                            ast_to_bytecode(bc, &*rexpr)?;
-                            bc.emit(&ast, ByteOp::Return);
-                        } else {
-                            ast_to_bytecode(bc, &e)?
-                        }
-                    }
-                    _ => {
-                        if idx != len {
-                            return Err(VmError::new(
-                                "Only the last element in a block may be an expression".to_string(),
-                                &e,
-                            ));
-                        }
-                        if let ast::Ast::Return(rexpr) = &e.node {
-                            ast_to_bytecode(bc, &*rexpr)?;
-                            bc.emit(&ast, ByteOp::Return);
-                        } else {
-                            ast_to_bytecode(bc, &e)?;
-                            bc.emit(&ast, ByteOp::Return);
-                        }
-                    }
-                }
-                idx += 1;
-            }
+            bc.declared.push(HashMap::new());
+            block_body_to_bytecode(bc, ast, exprs)?;
+            bc.declared.pop();
             bc.emit(&ast, ByteOp::ScopeClose);
         }
         ast::Ast::IfElse(ifcond, ifexpr, elseifs, elseexpr) => {
             // ppens a new scope for the if expression
             bc.emit(&ast, ByteOp::ScopeOpen);
+            bc.declared.push(HashMap::new());
 
             // generates code for condition that and creates a temporary jump instruction
-            ast_to_bytecode(bc, &*ifcond)?;
+            condition_to_bytecode(bc, ifcond)?;
             bc.emit(&ast, ByteOp::JumpFalse(1));
             let last_jump_idx = bc.ops.len() - 1;
 
             ast_to_bytecode(bc, &*ifexpr)?;
+            bc.declared.pop();
 
-            // patches the temporary jump instruction to the end of the if expression's expression
+            // patches the temporary jump instruction to the scope close after the if expression's expression
             bc.ops[last_jump_idx] =
-                ByteOp::JumpFalse((bc.ops.len() as isize - last_jump_idx as isize + 2) as i16);
+                ByteOp::JumpFalse(jump_offset(ast, last_jump_idx, bc.ops.len() + 2)?);
 
             // closes if scope
             bc.emit(&ast, ByteOp::ScopeClose);
@@ -306,20 +1185,26 @@ fn ast_to_bytecode(bc: &mut Bytecode, ast: &ast::AstNode) -> Result<(), VmError>
             bc.emit(&ast, ByteOp::Jump(1));
             last_patch_idx.push(bc.ops.len() - 1);
 
+            // closes if scope when the condition fails
+            bc.scope_depth += 1;
+            bc.emit(ast, ByteOp::ScopeClose);
+
             for (cond, expr) in elseifs {
                 // opens a new scope for the else if expression
                 bc.emit(&ast, ByteOp::ScopeOpen);
+                bc.declared.push(HashMap::new());
 
                 // generates code for condition that and creates a temporary jump instruction
-                ast_to_bytecode(bc, &*cond)?;
+                condition_to_bytecode(bc, cond)?;
                 bc.emit(&ast, ByteOp::JumpFalse(1));
                 let last_jump_idx = bc.ops.len() - 1;
 
                 ast_to_bytecode(bc, &*expr)?;
+                bc.declared.pop();
 
-                // patches the temporary jump instruction to the end of the if expression's expression
+                // patches the temporary jump instruction to the scope close after the if expression's expression
                 bc.ops[last_jump_idx] =
-                    ByteOp::JumpFalse((bc.ops.len() as isize - last_jump_idx as isize + 2) as i16);
+                    ByteOp::JumpFalse(jump_offset(ast, last_jump_idx, bc.ops.len() + 2)?);
 
                 // closes scope for the else if expression
                 bc.emit(&ast, ByteOp::ScopeClose);
@@ -327,32 +1212,49 @@ fn ast_to_bytecode(bc: &mut Bytecode, ast: &ast::AstNode) -> Result<(), VmError>
                 // adds to list of temporary jumps that need to be patched
                 bc.emit(&ast, ByteOp::Jump(1));
                 last_patch_idx.push(bc.ops.len() - 1);
+
+                // closes scope for the else if expression when the condition fails
+                bc.scope_depth += 1;
+                bc.emit(ast, ByteOp::ScopeClose);
             }
 
-            // generates code for else expression if present
-            if let Some(eexpr) = elseexpr {
-                ast_to_bytecode(bc, &*eexpr)?;
+            // generates code for else expression if present, without one the if is nil when no branch runs
+            match elseexpr {
+                Some(eexpr) => ast_to_bytecode(bc, eexpr)?,
+                None => bc.emit(ast, ByteOp::Nil),
             }
 
             // patches all temporary jumps
             for patch in last_patch_idx {
-                bc.ops[patch] = ByteOp::Jump((bc.ops.len() as isize - patch as isize) as i16);
+                bc.ops[patch] = ByteOp::Jump(jump_offset(ast, patch, bc.ops.len())?);
             }
         }
         ast::Ast::While(cond, expr) => {
             // saves index in code to the begining of the condition expression
             let begin_idx = bc.ops.len();
 
+            bc.loops.push(LoopContext {
+                begin_idx,
+                scope_depth: bc.scope_depth,
+                operand_depth: bc.operand_depth,
+                break_idxs: Vec::new(),
+            });
+
             // opens a new scope for the while expression
             bc.emit(&ast, ByteOp::ScopeOpen);
+            bc.declared.push(HashMap::new());
 
-            ast_to_bytecode(bc, &*cond)?;
+            condition_to_bytecode(bc, cond)?;
 
             // adds a temporary jump that needs to be patched that jumps to the end of the entire expression
             bc.emit(&ast, ByteOp::JumpFalse(1));
             let cond_jump_idx = bc.ops.len() - 1;
 
             ast_to_bytecode(bc, &*expr)?;
+            bc.declared.pop();
+
+            // the body's value is discarded every iteration
+            bc.emit(ast, ByteOp::Pop);
 
             // closes the while expression scope
             bc.emit(&ast, ByteOp::ScopeClose);
@@ -360,48 +1262,301 @@ fn ast_to_bytecode(bc: &mut Bytecode, ast: &ast::AstNode) -> Result<(), VmError>
             // patches jump to begining of the condition expression
             bc.emit(
                 &ast,
-                ByteOp::Jump((begin_idx as isize - bc.ops.len() as isize) as i16),
+                ByteOp::Jump(jump_offset(ast, bc.ops.len(), begin_idx)?),
             );
 
-            // patches conditional jump to end of entire expression
+            // patches conditional jump to the scope close below, the condition is evaluated once per iteration
             bc.ops[cond_jump_idx] =
-                ByteOp::JumpFalse((bc.ops.len() as isize - cond_jump_idx as isize) as i16);
+                ByteOp::JumpFalse(jump_offset(ast, cond_jump_idx, bc.ops.len())?);
+
+            // closes the scope opened before the condition that failed and leaves a value for the expression
+            // the failed condition path never went through the scope close above
+            bc.scope_depth += 1;
+            bc.emit(ast, ByteOp::ScopeClose);
+
+            // patches breaks to jump to the end of the entire expression
+            for break_idx in bc.loops.pop().unwrap().break_idxs {
+                bc.ops[break_idx] = ByteOp::Jump(jump_offset(ast, break_idx, bc.ops.len())?);
+            }
+
+            bc.emit(ast, ByteOp::Nil);
         }
-        ast::Ast::VarDecl(name, var_sig, set_expr) => {
-            if let Some(se) = set_expr {
-                ast_to_bytecode(bc, &*se)?;
+        ast::Ast::For(init, cond, step, expr) => {
+            // opens the scope that holds variables declared by the initializer
+            bc.emit(ast, ByteOp::ScopeOpen);
+            bc.declared.push(HashMap::new());
+
+            ast_to_bytecode(bc, init)?;
+            bc.emit(ast, ByteOp::Pop);
+
+            // skips the step expression on the first iteration
+            bc.emit(ast, ByteOp::Jump(1));
+            let init_jump_idx = bc.ops.len() - 1;
+
+            // continue jumps to the step expression rather than the condition
+            let step_idx = bc.ops.len();
+            ast_to_bytecode(bc, step)?;
+            bc.emit(ast, ByteOp::Pop);
+
+            let begin_idx = bc.ops.len();
+            bc.ops[init_jump_idx] = ByteOp::Jump(jump_offset(ast, init_jump_idx, begin_idx)?);
+
+            bc.loops.push(LoopContext {
+                begin_idx: step_idx,
+                scope_depth: bc.scope_depth,
+                operand_depth: bc.operand_depth,
+                break_idxs: Vec::new(),
+            });
+
+            // the rest is lowered the same way as a while loop
+            bc.emit(ast, ByteOp::ScopeOpen);
+            bc.declared.push(HashMap::new());
+
+            ast_to_bytecode(bc, cond)?;
+
+            bc.emit(ast, ByteOp::JumpFalse(1));
+            let cond_jump_idx = bc.ops.len() - 1;
+
+            ast_to_bytecode(bc, expr)?;
+            bc.declared.pop();
+            bc.emit(ast, ByteOp::Pop);
+
+            bc.emit(ast, ByteOp::ScopeClose);
+
+            bc.emit(ast, ByteOp::Jump(jump_offset(ast, bc.ops.len(), step_idx)?));
+
+            bc.ops[cond_jump_idx] =
+                ByteOp::JumpFalse(jump_offset(ast, cond_jump_idx, bc.ops.len())?);
+
+            bc.scope_depth += 1;
+            bc.emit(ast, ByteOp::ScopeClose);
+
+            // breaks land on the scope close of the initializer scope
+            for break_idx in bc.loops.pop().unwrap().break_idxs {
+                bc.ops[break_idx] = ByteOp::Jump(jump_offset(ast, break_idx, bc.ops.len())?);
+            }
+
+            bc.emit(ast, ByteOp::ScopeClose);
+            bc.declared.pop();
+
+            bc.emit(ast, ByteOp::Nil);
+        }
+        ast::Ast::Break | ast::Ast::Continue => {
+            let (begin_idx, loop_scope_depth, loop_operand_depth) = match bc.loops.last() {
+                Some(l) => (l.begin_idx, l.scope_depth, l.operand_depth),
+                None => {
+                    return Err(VmError::new(
+                        if let ast::Ast::Break = ast.node {
+                            "Break must be within a loop".to_string()
+                        } else {
+                            "Continue must be within a loop".to_string()
+                        },
+                        ast,
+                    ));
+                }
+            };
+
+            // drops the operands of any expression the break or continue is nested in,
+            // `1 + { continue; 2 }` would otherwise leave the 1 behind every iteration
+            let pending = bc.operand_depth - loop_operand_depth;
+            if pending > 0 {
+                let count = u16::try_from(pending).map_err(|_| {
+                    VmError::new("Too many operands to pop for a jump".to_string(), ast)
+                })?;
+                bc.emit(ast, ByteOp::PopN(count));
+            }
+
+            // closes every scope opened since the loop began, including the loop's own scope
+            let scope_depth = bc.scope_depth;
+            for _ in loop_scope_depth..scope_depth {
+                bc.emit(ast, ByteOp::ScopeClose);
             }
-            let hash = crc16::State::<crc16::ARC>::calculate(name.as_bytes());
+            bc.scope_depth = scope_depth;
 
-            #[cfg(feature = "store_names")]
-            bc.names.insert(hash, name.clone());
+            if let ast::Ast::Break = ast.node {
+                // adds a temporary jump that is patched once the end of the loop is known
+                bc.emit(ast, ByteOp::Jump(1));
+                let break_idx = bc.ops.len() - 1;
+                bc.loops.last_mut().unwrap().break_idxs.push(break_idx);
+            } else {
+                bc.emit(
+                    ast,
+                    ByteOp::Jump(jump_offset(ast, bc.ops.len(), begin_idx)?),
+                );
+            }
+        }
+        ast::Ast::VarDecl(name, var_sig, set_expr) => {
+            match set_expr {
+                Some(se) => ast_to_bytecode(bc, se)?,
+                None => bc.emit(ast, ByteOp::Nil),
+            }
+            let hash = bc.name_id(name);
 
             if var_sig.mutable {
                 bc.emit(&ast, ByteOp::DefMutVar(hash));
             } else {
                 bc.emit(&ast, ByteOp::DefVar(hash));
             }
+            bc.declare(hash, var_sig.mutable);
+            // defining consumes the value, a let still evaluates to it so it is read back
+            bc.emit(ast, ByteOp::GetVar(hash));
+
+            // top level function definitions can be called by name from the host
+            if let Some(se) = set_expr {
+                if let (ast::Ast::FnDef(..), 0) = (&se.node, bc.fn_depth) {
+                    let idx = constant_idx(bc.constants.len() - 1)
+                        .map_err(|msg| VmError::new(msg, ast))?;
+                    bc.entry_points.insert(name.clone(), idx);
+                }
+            }
         }
         ast::Ast::Import(module) => {
             module_to_bytecode(bc, &module)?;
         }
-        ast::Ast::FnCall(callee, args) => {
-            if let ast::Ast::Identifier(name) = &callee.node {
-                for a in args {
-                    ast_to_bytecode(bc, &a)?;
-                }
-                let hash = crc16::State::<crc16::ARC>::calculate(name.as_bytes());
-
-                #[cfg(feature = "store_names")]
-                bc.names.insert(hash, name.clone());
+        ast::Ast::FnDef(sig, param_names, body) => {
+            // skips over the function body since it is only run when called
+            bc.emit(ast, ByteOp::Jump(1));
+            let skip_jump_idx = bc.ops.len() - 1;
+
+            let mut params: Vec<(u64, bool)> = Vec::new();
+            for (param, name) in sig.params.iter().zip(param_names.iter()) {
+                params.push((bc.name_id(name), param.mutable));
+            }
 
-                bc.emit(&ast, ByteOp::NativeFnCall(hash, args.len() as u8));
-            } else {
+            let entry = bc.ops.len();
+            bc.fn_depth += 1;
+            let outer_fn_scope_depth = std::mem::replace(&mut bc.fn_scope_depth, bc.scope_depth);
+            // loops outside of the function cannot be broken out of from within it
+            let outer_loops = std::mem::take(&mut bc.loops);
+            // parameters live in a scope of their own around the body
+            bc.declared.push(params.iter().cloned().collect());
+            ast_to_bytecode(bc, body)?;
+            bc.declared.pop();
+            bc.loops = outer_loops;
+            bc.fn_scope_depth = outer_fn_scope_depth;
+            bc.fn_depth -= 1;
+            bc.emit(ast, ByteOp::Return);
+
+            bc.ops[skip_jump_idx] = ByteOp::Jump(jump_offset(ast, skip_jump_idx, bc.ops.len())?);
+
+            // the prototype is stored as a constant and the environment is captured when the closure is created
+            let idx = bc
+                .push_constant(Value::Object(Box::new(object::Function {
+                    entry,
+                    params,
+                    env: Vec::new(),
+                })))
+                .map_err(|msg| VmError::new(msg, ast))?;
+            bc.emit(ast, ByteOp::Closure(idx));
+        }
+        ast::Ast::FnCall(callee, args) => match &callee.node {
+            // print, println and assert are built in and take priority over functions with the same name
+            ast::Ast::Identifier(name)
+                if name == "print" || name == "println" || name == "assert" =>
+            {
+                if args.len() != 1 {
+                    return Err(VmError::new(
+                        format!(
+                            "Function: {} expects 1 arguments but recieved {}",
+                            name,
+                            args.len()
+                        ),
+                        ast,
+                    ));
+                }
+                ast_to_bytecode(bc, &args[0])?;
+                bc.emit(
+                    ast,
+                    match name.as_str() {
+                        "print" => ByteOp::Print,
+                        "println" => ByteOp::PrintLn,
+                        _ => ByteOp::Assert,
+                    },
+                );
+            }
+            ast::Ast::Identifier(name) => {
+                for (pending, a) in args.iter().enumerate() {
+                    operand_to_bytecode(bc, a, pending)?;
+                }
+                let hash = bc.name_id(name);
+                let count = arg_count(ast, args)?;
+                bc.emit(ast, ByteOp::NativeFnCall(hash, count));
+            }
+            _ => {
+                ast_to_bytecode(bc, callee)?;
+                for (pending, a) in args.iter().enumerate() {
+                    // the callee is under the arguments
+                    operand_to_bytecode(bc, a, pending + 1)?;
+                }
+                let count = arg_count(ast, args)?;
+                bc.emit(ast, ByteOp::Call(count));
+            }
+        },
+        ast::Ast::As(expr, type_sig) => match type_sig {
+            ast::TypeSignature::Primitive(prim) => {
+                ast_to_bytecode(bc, expr)?;
+                bc.emit(ast, ByteOp::Cast(*prim));
+            }
+            _ => {
                 return Err(VmError::new(
-                    "Function variables not support yet".to_string(),
-                    &ast,
+                    format!("Cannot cast to non primitive type {:?}", type_sig),
+                    ast,
                 ));
             }
+        },
+        ast::Ast::Array(elems) => {
+            for (pending, elem) in elems.iter().enumerate() {
+                operand_to_bytecode(bc, elem, pending)?;
+            }
+            let len = u16::try_from(elems.len()).map_err(|_| {
+                VmError::new(
+                    format!(
+                        "Array has {} elements; the maximum is {}",
+                        elems.len(),
+                        u16::MAX
+                    ),
+                    ast,
+                )
+            })?;
+            bc.emit(ast, ByteOp::MakeArray(len));
+        }
+        ast::Ast::Index(array, index) => {
+            ast_to_bytecode(bc, array)?;
+            operand_to_bytecode(bc, index, 1)?;
+            bc.emit(ast, ByteOp::Index);
+        }
+        // structs are checked by the semantic analyzer, the vm only needs their field names
+        ast::Ast::StructDef(..) => bc.emit(ast, ByteOp::Nil),
+        ast::Ast::StructInit(name, fields) => {
+            for (pending, (_, value)) in fields.iter().enumerate() {
+                operand_to_bytecode(bc, value, pending)?;
+            }
+            let count = u16::try_from(fields.len()).map_err(|_| {
+                VmError::new(
+                    format!(
+                        "Struct has {} fields; the maximum is {}",
+                        fields.len(),
+                        u16::MAX
+                    ),
+                    ast,
+                )
+            })?;
+            // the field names have to follow the name so they aren't interned
+            let name_idx = bc
+                .push_constant(Value::Object(Box::new(name.clone())))
+                .map_err(|msg| VmError::new(msg, ast))?;
+            for (field, _) in fields {
+                bc.push_constant(Value::Object(Box::new(field.clone())))
+                    .map_err(|msg| VmError::new(msg, ast))?;
+            }
+            bc.emit(ast, ByteOp::MakeStruct(name_idx, count));
+        }
+        ast::Ast::FieldAccess(expr, field) => {
+            ast_to_bytecode(bc, expr)?;
+            let idx = bc
+                .intern_constant(Value::Object(Box::new(field.clone())))
+                .map_err(|msg| VmError::new(msg, ast))?;
+            bc.emit(ast, ByteOp::GetField(idx));
         }
         other => {
             return Err(VmError::new(
@@ -413,27 +1568,95 @@ fn ast_to_bytecode(bc: &mut Bytecode, ast: &ast::AstNode) -> Result<(), VmError>
     Ok(())
 }
 
-struct Scope {
-    variables: HashMap<u16, (bool, Value)>,
+#[derive(Default)]
+pub(crate) struct Scope {
+    variables: HashMap<u64, (bool, Value)>,
+}
+
+struct CallFrame {
+    return_idx: usize,
+    stack_base: usize,
+    scope_base: usize,
+    scopes: Vec<Rc<RefCell<Scope>>>,
+    returns_to_host: bool,
+}
+
+pub enum StepResult {
+    Continue,
+    Halted(Value),
 }
 
 pub type NativeVmFn = fn(&mut StackVm, &Bytecode) -> Result<(), VmError>;
+// takes the arguments in order and returns the call's value, for functions from a host embedding the vm
+pub type HostFn = fn(&[Value]) -> Result<Value, String>;
+
+// native functions either work on the vm's stack themselves or are handed their arguments
+#[derive(Clone, Copy)]
+enum NativeFn {
+    Vm(NativeVmFn),
+    Host(HostFn),
+}
+// called before each instruction with the ip, the op about to run and the current stack
+pub type TraceFn = Box<dyn FnMut(usize, &ByteOp, &[Value])>;
 
-#[derive(Default)]
 pub struct StackVm {
     ip_idx: usize,
     pub stack: Vec<Value>,
-    scopes: Vec<Scope>,
+    scopes: Vec<Rc<RefCell<Scope>>>,
+    frames: Vec<CallFrame>,
+    // set once a global scope has been pushed that must survive between runs
+    global_scope: bool,
+    // closures created for entry points, keyed by the constant index of their prototype
+    entry_closures: HashMap<u16, object::Function>,
+    debug_level: i32,
+
+    // approximate bytes held by objects, only enforced when a limit is set
+    heap_bytes: usize,
+    max_heap_bytes: Option<usize>,
+
+    // instructions left before execution is aborted
+    op_budget: u64,
+    // whether code is being executed, a native function can call back into the vm while it is
+    running: bool,
+    max_stack: usize,
+    // calls that can be nested before giving up, catches runaway recursion
+    max_frames: usize,
+    numeric_mode: NumericMode,
+
+    writer: Box<dyn Write>,
+    trace: Option<TraceFn>,
+
+    native_fns: HashMap<u64, (u8, NativeFn)>,
+}
 
-    native_fns: HashMap<u16, (u8, NativeVmFn)>,
+impl Default for StackVm {
+    fn default() -> StackVm {
+        StackVm::new()
+    }
 }
 
-impl<'a> StackVm {
+impl StackVm {
     pub fn new() -> StackVm {
         let mut vm = StackVm {
             ip_idx: 0,
             stack: Vec::new(),
             scopes: Vec::new(),
+            frames: Vec::new(),
+            global_scope: false,
+            entry_closures: HashMap::new(),
+            debug_level: 0,
+
+            heap_bytes: 0,
+            max_heap_bytes: None,
+
+            op_budget: u64::MAX,
+            running: false,
+            max_stack: DEFAULT_MAX_STACK,
+            max_frames: DEFAULT_MAX_FRAMES,
+            numeric_mode: NumericMode::Float,
+
+            writer: Box::new(std::io::stdout()),
+            trace: None,
 
             native_fns: HashMap::new(),
         };
@@ -444,919 +1667,964 @@ impl<'a> StackVm {
     }
 
     pub fn add_fn(&mut self, name: &str, arg_count: u8, function: NativeVmFn) {
-        let hash = crc16::State::<crc16::ARC>::calculate(name.as_bytes());
-        self.native_fns.insert(hash, (arg_count, function));
+        let hash = var_id(name);
+        self.native_fns
+            .insert(hash, (arg_count, NativeFn::Vm(function)));
     }
 
-    fn stack_peek(&self, distance: usize) -> Value {
-        if let Some(v) = self.stack.get(self.stack.len() - 1 - distance) {
-            v.clone()
-        } else {
-            Value::Nil
+    /// Makes a Rust function callable from scripts by name, calls with any other number of
+    /// arguments than `arg_count` are an error. An error it returns stops the script
+    pub fn register_native(&mut self, name: &str, arg_count: u8, function: HostFn) {
+        let hash = var_id(name);
+        self.native_fns
+            .insert(hash, (arg_count, NativeFn::Host(function)));
+    }
+
+    pub fn with_writer(writer: Box<dyn Write>) -> StackVm {
+        let mut vm = StackVm::new();
+        vm.writer = writer;
+        vm
+    }
+
+    pub fn with_trace(trace: TraceFn) -> StackVm {
+        let mut vm = StackVm::new();
+        vm.trace = Some(trace);
+        vm
+    }
+
+    pub fn with_globals(globals: HashMap<String, Value>) -> StackVm {
+        let mut vm = StackVm::new();
+        for (name, value) in globals {
+            vm.define_global(&name, value);
+        }
+        vm
+    }
+
+    pub fn with_stack_limit(max_stack: usize) -> StackVm {
+        let mut vm = StackVm::new();
+        vm.max_stack = max_stack;
+        vm
+    }
+
+    pub fn with_frame_limit(max_frames: usize) -> StackVm {
+        let mut vm = StackVm::new();
+        vm.max_frames = max_frames;
+        vm
+    }
+
+    // gives the vm a scope that stays alive between runs of bytecode made with `Bytecode::append_global`
+    pub fn push_global_scope(&mut self) {
+        self.scopes.push(Rc::new(RefCell::new(Scope::default())));
+        self.global_scope = true;
+    }
+
+    /// Defines an immutable variable for scripts to read by name, meant for values from the host
+    /// set before running. It goes in the outermost scope, a global scope is added if there are none
+    pub fn define_global(&mut self, name: &str, value: Value) {
+        if self.scopes.is_empty() {
+            self.push_global_scope();
+        }
+        self.heap_bytes += StackVm::value_heap_size(&value);
+        let old = self.scopes[0]
+            .borrow_mut()
+            .variables
+            .insert(var_id(name), (false, value));
+        if let Some((_, old)) = old {
+            self.track_free(&old);
+        }
+    }
+
+    // clears whatever a previous run left behind so the vm can run again,
+    // the outermost scope can be kept so its variables are visible to the next run
+    pub fn reset(&mut self, keep_global_scope: bool) {
+        self.ip_idx = 0;
+        while let Some(value) = self.stack.pop() {
+            self.track_free(&value);
+        }
+        // the first frame holds the scopes that were active before any function was entered
+        if let Some(frame) = self.frames.drain(..).next() {
+            self.scopes = frame.scopes;
+        }
+        let keep = if keep_global_scope { 1 } else { 0 };
+        while self.scopes.len() > keep {
+            self.pop_scope();
+        }
+    }
+
+    /// Limits how many bytes of strings, arrays and structs the running code may hold, going
+    /// past it stops the run with an out of memory error. Copies of an array or struct share it
+    /// but each copy on the stack or in a variable is counted in full, so a value held in many
+    /// places counts for more than it uses
+    pub fn set_max_heap_bytes(&mut self, max_heap_bytes: Option<usize>) {
+        self.max_heap_bytes = max_heap_bytes;
+    }
+
+    pub fn set_numeric_mode(&mut self, numeric_mode: NumericMode) {
+        self.numeric_mode = numeric_mode;
+    }
+
+    pub fn heap_bytes(&self) -> usize {
+        self.heap_bytes
+    }
+
+    fn value_heap_size(value: &Value) -> usize {
+        match value {
+            Value::Object(o) => o.heap_size(),
+            // copies of an array share its elements so this is an overestimate, like for objects
+            // each copy is counted on its own
+            Value::Array(elems) => elems
+                .borrow()
+                .iter()
+                .map(|e| std::mem::size_of::<Value>() + StackVm::value_heap_size(e))
+                .sum(),
+            Value::Struct(value) => value
+                .borrow()
+                .fields
+                .iter()
+                .map(|(name, field)| {
+                    name.len() + std::mem::size_of::<Value>() + StackVm::value_heap_size(field)
+                })
+                .sum(),
+            _ => 0,
         }
     }
 
-    fn var_in_scopes_mut(
-        scope_stack: &'a mut Vec<Scope>,
-        id: u16,
-    ) -> Option<&'a mut (bool, Value)> {
-        for s in scope_stack.iter_mut().rev() {
-            match s.variables.get_mut(&id) {
-                Some(var) => return Some(var),
-                None => continue,
+    fn track_alloc(&mut self, bc: &Bytecode, value: &Value) -> Result<(), VmError> {
+        self.heap_bytes += StackVm::value_heap_size(value);
+        match self.max_heap_bytes {
+            Some(max) if self.heap_bytes > max => {
+                Err(self.make_error(bc, "Out of memory".to_string()))
             }
+            _ => Ok(()),
         }
-        None
     }
 
-    fn var_in_scopes(scope_stack: &'a [Scope], id: u16) -> Option<&'a (bool, Value)> {
+    fn track_free(&mut self, value: &Value) {
+        self.heap_bytes = self
+            .heap_bytes
+            .saturating_sub(StackVm::value_heap_size(value));
+    }
+
+    fn pop_scope(&mut self) {
+        if let Some(scope) = self.scopes.pop() {
+            // scopes captured by a closure stay alive after they are popped
+            if Rc::strong_count(&scope) == 1 {
+                for (_, value) in scope.borrow().variables.values() {
+                    self.track_free(value);
+                }
+            }
+        }
+    }
+
+    /// Pushes a value onto the stack, or fails once the stack limit is reached. Everything the vm
+    /// pushes goes through here and native functions should use it too
+    pub fn push(&mut self, bc: &Bytecode, value: Value) -> Result<(), VmError> {
+        if self.stack.len() >= self.max_stack {
+            return Err(self.make_error(bc, "Stack overflow".to_string()));
+        }
+        self.stack.push(value);
+        Ok(())
+    }
+
+    fn pop_value(&mut self, bc: &Bytecode) -> Result<Value, VmError> {
+        self.stack.pop().ok_or_else(|| self.underflow_error(bc))
+    }
+
+    // pops the right operand then the left and returns them in source order
+    fn pop_operands(&mut self, bc: &Bytecode) -> Result<(Value, Value), VmError> {
+        match (self.stack.pop(), self.stack.pop()) {
+            (Some(b), Some(a)) => Ok((a, b)),
+            _ => Err(self.underflow_error(bc)),
+        }
+    }
+
+    // pops two numbers and pushes what `f` makes of them, any other operands are an error
+    fn numeric_binop(
+        &mut self,
+        bc: &Bytecode,
+        verb: &str,
+        f: impl Fn(f64, f64) -> Value,
+    ) -> Result<(), VmError> {
+        match self.pop_operands(bc)? {
+            (Value::Number(a), Value::Number(b)) => self.push(bc, f(a, b)),
+            (a, b) => Err(self.operand_error(bc, verb, &a, &b)),
+        }
+    }
+
+    fn operand_error(&self, bc: &Bytecode, verb: &str, a: &Value, b: &Value) -> VmError {
+        self.make_error(
+            bc,
+            format!(
+                "Cannot {} {:?} and {:?}: operands must be numbers",
+                verb, a, b
+            ),
+        )
+    }
+
+    // arrays are indexed with whole numbers, anything else including out of range indices is an error
+    fn array_index(&self, bc: &Bytecode, index: &Value, len: usize) -> Result<usize, VmError> {
+        match index {
+            Value::Number(n) if n.fract() != 0.0 || n.is_nan() => {
+                Err(self.make_error(bc, format!("Array index must be an integer; got {}", n)))
+            }
+            Value::Number(n) if *n < 0.0 || *n >= len as f64 => Err(self.make_error(
+                bc,
+                format!("Array index {} is out of bounds for length {}", n, len),
+            )),
+            Value::Number(n) => Ok(*n as usize),
+            v => Err(self.make_error(bc, type_error("array index", v))),
+        }
+    }
+
+    // the name held by a string constant, used for struct and field names
+    fn constant_name(&self, bc: &Bytecode, idx: u16) -> Result<String, VmError> {
+        match bc.constants.get(idx as usize) {
+            Some(Value::Object(o)) => match o.downcast_ref::<String>() {
+                Some(name) => Ok(name.clone()),
+                None => Err(self.make_error(bc, format!("Constant {} is not a name", idx))),
+            },
+            _ => Err(self.make_error(bc, format!("Constant {} is not a name", idx))),
+        }
+    }
+
+    // bytecode that wasn't made by the compiler can pop more values than it pushed
+    fn underflow_error(&self, bc: &Bytecode) -> VmError {
+        let op = match bc.ops.get(self.ip_idx) {
+            Some(op) => format!("{:?}", op),
+            None => String::from("end of bytecode"),
+        };
+        // only the op's name, not its operands
+        let name = op.split('(').next().unwrap_or(&op);
+        self.make_error(bc, format!("Stack underflow in {}", name))
+    }
+
+    fn stack_peek(&self, bc: &Bytecode, distance: usize) -> Result<Value, VmError> {
+        self.stack
+            .len()
+            .checked_sub(distance + 1)
+            .and_then(|idx| self.stack.get(idx))
+            .cloned()
+            .ok_or_else(|| self.underflow_error(bc))
+    }
+
+    fn var_in_scopes(scope_stack: &[Rc<RefCell<Scope>>], id: u64) -> Option<Rc<RefCell<Scope>>> {
         for s in scope_stack.iter().rev() {
-            match s.variables.get(&id) {
-                Some(var) => return Some(var),
-                None => continue,
+            if s.borrow().variables.contains_key(&id) {
+                return Some(s.clone());
             }
         }
         None
     }
 
+    // the scope variables are defined in, bytecode that defines one before opening a scope is malformed
+    fn current_scope(&self, bc: &Bytecode) -> Result<&Rc<RefCell<Scope>>, VmError> {
+        self.scopes
+            .last()
+            .ok_or_else(|| self.make_error(bc, "Variable defined outside of any scope".to_string()))
+    }
+
+    // only the innermost scope is checked when defining so inner scopes can shadow outer variables
+    fn var_in_current_scope(
+        scope_stack: &[Rc<RefCell<Scope>>],
+        id: u64,
+    ) -> Option<Rc<RefCell<Scope>>> {
+        scope_stack
+            .last()
+            .filter(|s| s.borrow().variables.contains_key(&id))
+            .cloned()
+    }
+
+    fn enter_function(
+        &mut self,
+        bc: &Bytecode,
+        function: &object::Function,
+        arg_count: usize,
+        returns_to_host: bool,
+    ) -> Result<(), VmError> {
+        if self.frames.len() >= self.max_frames {
+            return Err(self.make_error(bc, "maximum recursion depth exceeded".to_string()));
+        }
+        if function.params.len() != arg_count || self.stack.len() < arg_count {
+            return Err(self.make_error(
+                bc,
+                format!(
+                    "Function expects {} arguments but recieved {}",
+                    function.params.len(),
+                    arg_count
+                ),
+            ));
+        }
+
+        let args = self.stack.split_off(self.stack.len() - arg_count);
+        let mut scope = Scope::default();
+        for (param, arg) in function.params.iter().zip(args) {
+            scope.variables.insert(param.0, (param.1, arg));
+        }
+
+        let mut scopes = function.env.clone();
+        scopes.push(Rc::new(RefCell::new(scope)));
+
+        self.frames.push(CallFrame {
+            return_idx: self.ip_idx,
+            stack_base: self.stack.len(),
+            scope_base: scopes.len(),
+            scopes: std::mem::replace(&mut self.scopes, scopes),
+            returns_to_host,
+        });
+        self.ip_idx = function.entry;
+
+        Ok(())
+    }
+
+    pub fn call(
+        &mut self,
+        bc: &Bytecode,
+        function: Value,
+        args: Vec<Value>,
+    ) -> Result<Value, VmError> {
+        // a limit from `run_with_limit` only covers that run, but a native function calling back in
+        // during a run still counts against it
+        if !self.running {
+            self.op_budget = u64::MAX;
+        }
+        let arg_count = args.len();
+        match function {
+            Value::Object(o) => match o.downcast::<object::Function>() {
+                Ok(f) => {
+                    let stack_len = self.stack.len();
+                    let entered = args
+                        .into_iter()
+                        .try_for_each(|arg| self.push(bc, arg))
+                        .and_then(|_| self.enter_function(bc, &f, arg_count, true));
+                    if let Err(e) = entered {
+                        self.stack.truncate(stack_len);
+                        return Err(e);
+                    }
+                    self.execute(bc)
+                }
+                Err(_) => Err(self.make_error(bc, "Only functions can be called".to_string())),
+            },
+            _ => Err(self.make_error(bc, "Only functions can be called".to_string())),
+        }
+    }
+
     pub fn make_error(&self, bc: &Bytecode, msg: String) -> VmError {
         VmError {
             msg,
-            pos: bc.positions[self.ip_idx],
+            // the host may call in while the vm is not running any code
+            pos: *bc
+                .positions
+                .get(self.ip_idx)
+                .unwrap_or(&Position { line: -1, col: -1 }),
         }
     }
 
-    pub fn run(&mut self, bc: Bytecode, debug_level: i32) -> Result<Value, VmError> {
-        'run: loop {
-            if debug_level >= 3 {
-                if let Some(op) = bc.ops.get(self.ip_idx) {
-                    println!("{:?}\n{:?}\n", self.stack, op);
-                }
+    pub fn run(&mut self, bc: Bytecode, debug_level: i32) -> Result<Value, GravitonError> {
+        if let Err(msg) = bc.validate() {
+            return Err(GravitonError::Runtime { msg, pos: None });
+        }
+        self.ip_idx = 0;
+        self.op_budget = u64::MAX;
+        self.debug_level = debug_level;
+        if debug_level >= 1 {
+            println!("{}", bc.disassemble());
+        }
+        self.execute(&bc).map_err(|e| e.to_runtime_error())
+    }
+
+    pub fn call_function(
+        &mut self,
+        bc: &Bytecode,
+        name: &str,
+        args: &[Value],
+    ) -> Result<Value, String> {
+        let idx = match bc.entry_points.get(name) {
+            Some(idx) => *idx,
+            None => return Err(format!("Function: {} not defined", name)),
+        };
+
+        // prefer the closure made when the module was run so it can see the module's variables
+        let function = match self.entry_closures.get(&idx) {
+            Some(function) => function.clone(),
+            None => match bc.constants.get(idx as usize) {
+                Some(Value::Object(o)) => match o.downcast_ref::<object::Function>() {
+                    Some(prototype) => {
+                        let mut function = prototype.clone();
+                        function.env = self.scopes.clone();
+                        function
+                    }
+                    None => return Err(format!("{} is not a function", name)),
+                },
+                _ => return Err(format!("{} is not a function", name)),
+            },
+        };
+
+        self.call(bc, Value::Object(Box::new(function)), args.to_vec())
+            .map_err(|e| e.to_string())
+    }
+
+    // runs bytecode starting from `start_idx`, used to run code added with `Bytecode::append_global`
+    pub fn run_from(
+        &mut self,
+        bc: &Bytecode,
+        start_idx: usize,
+        debug_level: i32,
+    ) -> Result<Value, VmError> {
+        self.ip_idx = start_idx;
+        self.op_budget = u64::MAX;
+        self.debug_level = debug_level;
+        if debug_level >= 1 {
+            println!("{}", bc.disassemble());
+        }
+        self.execute(bc)
+    }
+
+    pub fn run_with_limit(&mut self, bc: Bytecode, max_ops: u64) -> Result<Value, String> {
+        bc.validate()?;
+        self.ip_idx = 0;
+        self.op_budget = max_ops;
+        self.execute(&bc).map_err(|e| e.to_string())
+    }
+
+    fn execute(&mut self, bc: &Bytecode) -> Result<Value, VmError> {
+        let was_running = std::mem::replace(&mut self.running, true);
+        let result = loop {
+            match self.step(bc) {
+                Ok(StepResult::Continue) => {}
+                Ok(StepResult::Halted(v)) => break Ok(v),
+                Err(e) => break Err(e),
+            }
+        };
+        self.running = was_running;
+        result
+    }
+
+    pub fn ip_idx(&self) -> usize {
+        self.ip_idx
+    }
+
+    pub fn stack(&self) -> &[Value] {
+        &self.stack
+    }
+
+    /// Executes a single instruction
+    pub fn step(&mut self, bc: &Bytecode) -> Result<StepResult, VmError> {
+        if self.op_budget == 0 {
+            return Err(self.make_error(bc, "Instruction limit exceeded".to_string()));
+        }
+        self.op_budget -= 1;
+
+        if let Some(op) = bc.ops.get(self.ip_idx) {
+            // the hook only gets a shared view of the stack so it can't change what runs next
+            if let Some(trace) = &mut self.trace {
+                trace(self.ip_idx, op, &self.stack);
+            } else if self.debug_level >= 3 {
+                println!("{:?}\n{:?}\n", self.stack, op);
+            }
+        }
+        match bc.ops.get(self.ip_idx) {
+            Some(ByteOp::Load(n)) => {
+                let value = match bc.constants.get(*n as usize) {
+                    Some(value) => value.clone(),
+                    None => {
+                        return Err(
+                            self.make_error(bc, format!("Constant index {} out of range", n))
+                        );
+                    }
+                };
+                self.track_alloc(bc, &value)?;
+                self.push(bc, value)?;
             }
-            match bc.ops.get(self.ip_idx) {
-                Some(ByteOp::Load(n)) => {
-                    self.stack.push(bc.constants[*n as usize].clone());
+            Some(ByteOp::True) => {
+                self.push(bc, Value::Bool(true))?;
+            }
+            Some(ByteOp::False) => {
+                self.push(bc, Value::Bool(false))?;
+            }
+            Some(ByteOp::Nil) => {
+                self.push(bc, Value::Nil)?;
+            }
+            Some(ByteOp::Add) => match self.pop_operands(bc)? {
+                (Value::Number(a), Value::Number(b)) => self.push(bc, Value::Number(a + b))?,
+                (Value::Object(a), b) => {
+                    self.heap_bytes = self
+                        .heap_bytes
+                        .saturating_sub(a.heap_size() + StackVm::value_heap_size(&b));
+                    let value = match a.add(b) {
+                        Ok(o) => o,
+                        Err(e) => return Err(self.make_error(bc, e)),
+                    };
+                    self.track_alloc(bc, &value)?;
+                    self.push(bc, value)?;
                 }
-                Some(ByteOp::True) => {
-                    self.stack.push(Value::Bool(true));
+                (a, b) => return Err(self.operand_error(bc, "add", &a, &b)),
+            },
+            Some(ByteOp::Sub) => self.numeric_binop(bc, "subtract", |a, b| Value::Number(a - b))?,
+            Some(ByteOp::Mul) => self.numeric_binop(bc, "multiply", |a, b| Value::Number(a * b))?,
+            Some(ByteOp::Div) => match self.pop_operands(bc)? {
+                (Value::Number(a), Value::Number(b)) => {
+                    if b == 0.0 {
+                        return Err(self.make_error(bc, "Division by zero".to_string()));
+                    }
+                    self.push(bc, Value::Number(a / b))?
                 }
-                Some(ByteOp::False) => {
-                    self.stack.push(Value::Bool(false));
+                (a, b) => return Err(self.operand_error(bc, "divide", &a, &b)),
+            },
+            Some(ByteOp::Not) => match self.pop_value(bc)? {
+                Value::Bool(b) => self.push(bc, Value::Bool(!b))?,
+                v => return Err(self.make_error(bc, type_error("not", &v))),
+            },
+            Some(ByteOp::And) => match self.pop_operands(bc)? {
+                (Value::Bool(a), Value::Bool(b)) => self.push(bc, Value::Bool(a && b))?,
+                (Value::Bool(_), v) | (v, _) => {
+                    return Err(self.make_error(bc, type_error("'and'", &v)));
                 }
-                Some(ByteOp::Nil) => {
-                    self.stack.push(Value::Nil);
+            },
+            Some(ByteOp::Or) => match self.pop_operands(bc)? {
+                (Value::Bool(a), Value::Bool(b)) => self.push(bc, Value::Bool(a || b))?,
+                (Value::Bool(_), v) | (v, _) => {
+                    return Err(self.make_error(bc, type_error("'or'", &v)));
                 }
-                Some(ByteOp::Add) => match self.stack_peek(0) {
-                    Value::Nil => {
-                        return Err(self
-                            .make_error(&bc, "Binary add right value cannot be Nil".to_string()));
+            },
+            Some(ByteOp::Equal) => {
+                let (a, b) = self.pop_operands(bc)?;
+                self.track_free(&a);
+                self.track_free(&b);
+                self.push(bc, Value::Bool(values_equal(&a, &b)))?;
+            }
+            Some(ByteOp::Greater) => {
+                self.numeric_binop(bc, "compare", |a, b| Value::Bool(a > b))?
+            }
+            Some(ByteOp::Less) => self.numeric_binop(bc, "compare", |a, b| Value::Bool(a < b))?,
+            Some(ByteOp::Negate) => match self.pop_value(bc)? {
+                Value::Number(n) => self.push(bc, Value::Number(-n))?,
+                v => return Err(self.make_error(bc, type_error("negate", &v))),
+            },
+            Some(ByteOp::Plus) => match self.stack_peek(bc, 0)? {
+                Value::Number(_) => {}
+                v => return Err(self.make_error(bc, type_error("unary plus", &v))),
+            },
+            Some(ByteOp::Cast(to)) => match self.stack_peek(bc, 0)? {
+                Value::Number(n) => match cast_number(n, *to) {
+                    Some(casted) => {
+                        self.stack.pop();
+                        self.push(bc, Value::Number(casted))?;
                     }
-                    Value::Bool(_) => {
-                        return Err(self
-                            .make_error(&bc, "Binary add right value cannot be Bool".to_string()));
+                    None => {
+                        return Err(self.make_error(bc, format!("Cannot cast number to {:?}", to)));
                     }
-                    Value::Number(_) => match self.stack_peek(1) {
-                        Value::Nil => {
-                            return Err(self.make_error(
-                                &bc,
-                                "Binary add left value cannot be Nil".to_string(),
-                            ));
-                        }
-                        Value::Bool(_) => {
-                            return Err(self.make_error(
-                                &bc,
-                                "Binary add left value cannot be Bool".to_string(),
-                            ));
+                },
+                v => return Err(self.make_error(bc, type_error("cast", &v))),
+            },
+            Some(ByteOp::Wrap(to)) => {
+                if let (NumericMode::IntegerWrap, Value::Number(n)) =
+                    (self.numeric_mode, self.stack_peek(bc, 0)?)
+                {
+                    if let Some(wrapped) = cast_number(n, *to) {
+                        self.stack.pop();
+                        self.push(bc, Value::Number(wrapped))?;
+                    }
+                }
+            }
+            Some(ByteOp::ScopeOpen) => {
+                self.scopes.push(Rc::new(RefCell::new(Scope::default())));
+            }
+            Some(ByteOp::ScopeClose) => {
+                self.pop_scope();
+            }
+            Some(ByteOp::NativeFnCall(id, arg_count)) => {
+                // a function value in scope shadows a native function of the same name
+                let function_var =
+                    StackVm::var_in_scopes(&self.scopes, *id).and_then(|s| {
+                        match &s.borrow().variables[id].1 {
+                            Value::Object(o) => o.downcast_ref::<object::Function>().cloned(),
+                            _ => None,
                         }
-                        Value::Number(_) => {
-                            if let Some(Value::Number(b)) = self.stack.pop() {
-                                if let Some(Value::Number(a)) = self.stack.pop() {
-                                    self.stack.push(Value::Number(a + b));
-                                } else {
-                                    return Err(self.make_error(
-                                        &bc,
-                                        "Failed to pop binary add left".to_string(),
-                                    ));
-                                }
-                            } else {
-                                return Err(self.make_error(
-                                    &bc,
-                                    "Failed to pop binary add right".to_string(),
-                                ));
+                    });
+                if let Some(function) = function_var {
+                    self.enter_function(bc, &function, *arg_count as usize, false)?;
+                    return Ok(StepResult::Continue);
+                } else if let Some((expected, function)) = self.native_fns.get(id).copied() {
+                    if expected != *arg_count {
+                        return Err(self.make_error(
+                            bc,
+                            format!(
+                                "Function: {} expects {} arguments but recieved {}",
+                                bc.var_name(*id),
+                                expected,
+                                arg_count
+                            ),
+                        ));
+                    }
+                    let result = match function {
+                        NativeFn::Vm(function) => function(self, bc).map_err(|e| e.msg),
+                        NativeFn::Host(function) => {
+                            let arg_count = *arg_count as usize;
+                            if self.stack.len() < arg_count {
+                                return Err(self.underflow_error(bc));
                             }
-                        }
-                        Value::Object(_) => {
-                            if let Some(Value::Object(b)) = self.stack.pop() {
-                                if let Some(a) = self.stack.pop() {
-                                    self.stack.push(match b.add(a) {
-                                        Ok(o) => o,
-                                        Err(e) => return Err(self.make_error(&bc, e)),
-                                    });
-                                } else {
-                                    return Err(self.make_error(
-                                        &bc,
-                                        "Failed to pop binary add left".to_string(),
-                                    ));
-                                }
-                            } else {
-                                return Err(self.make_error(
-                                    &bc,
-                                    "Failed to pop binary add right".to_string(),
-                                ));
+                            let args = self.stack.split_off(self.stack.len() - arg_count);
+                            for arg in &args {
+                                self.track_free(arg);
                             }
-                        }
-                    },
-                    Value::Object(_) => match self.stack_peek(1) {
-                        Value::Nil => {
-                            return Err(self.make_error(
-                                &bc,
-                                "Binary add left value cannot be Nil".to_string(),
-                            ));
-                        }
-                        Value::Bool(_) => {
-                            return Err(self.make_error(
-                                &bc,
-                                "Binary add left value cannot be Bool".to_string(),
-                            ));
-                        }
-                        Value::Number(_) => {}
-                        Value::Object(_) => {
-                            if let Some(b) = self.stack.pop() {
-                                if let Some(Value::Object(a)) = self.stack.pop() {
-                                    self.stack.push(match a.add(b) {
-                                        Ok(o) => o,
-                                        Err(e) => return Err(self.make_error(&bc, e)),
-                                    });
-                                } else {
-                                    return Err(self.make_error(
-                                        &bc,
-                                        "Failed to pop binary add left".to_string(),
-                                    ));
+                            match function(&args) {
+                                Ok(value) => {
+                                    self.track_alloc(bc, &value)?;
+                                    self.push(bc, value)?;
+                                    Ok(())
                                 }
-                            } else {
-                                return Err(self.make_error(
-                                    &bc,
-                                    "Failed to pop binary add right".to_string(),
-                                ));
+                                Err(msg) => Err(msg),
                             }
                         }
-                    },
-                },
-                Some(ByteOp::Sub) => match self.stack_peek(0) {
-                    Value::Nil => {
-                        return Err(self
-                            .make_error(&bc, "Binary sub right value cannot be Nil".to_string()));
+                    };
+                    if let Err(msg) = result {
+                        return Err(self.make_error(
+                            bc,
+                            format!("Function: {} returned an error: {}", bc.var_name(*id), msg),
+                        ));
                     }
-                    Value::Bool(_) => {
-                        return Err(self
-                            .make_error(&bc, "Binary sub right value cannot be Bool".to_string()));
+                } else {
+                    return Err(
+                        self.make_error(bc, format!("Function: {} not defined", bc.var_name(*id)))
+                    );
+                }
+            }
+            Some(ByteOp::Call(arg_count)) => {
+                let callee_idx = self.stack.len().checked_sub(*arg_count as usize + 1);
+                let function = callee_idx.and_then(|idx| match &self.stack[idx] {
+                    Value::Object(o) => o.downcast_ref::<object::Function>().cloned(),
+                    _ => None,
+                });
+                match (callee_idx, function) {
+                    (Some(idx), Some(function)) => {
+                        self.stack.remove(idx);
+                        self.enter_function(bc, &function, *arg_count as usize, false)?;
+                        return Ok(StepResult::Continue);
                     }
-                    Value::Number(_) => match self.stack_peek(1) {
-                        Value::Nil => {
-                            return Err(self.make_error(
-                                &bc,
-                                "Binary sub left value cannot be Nil".to_string(),
-                            ));
-                        }
-                        Value::Bool(_) => {
-                            return Err(self.make_error(
-                                &bc,
-                                "Binary sub left value cannot be Bool".to_string(),
-                            ));
-                        }
-                        Value::Number(_) => {
-                            if let Some(Value::Number(b)) = self.stack.pop() {
-                                if let Some(Value::Number(a)) = self.stack.pop() {
-                                    self.stack.push(Value::Number(a - b));
-                                } else {
-                                    return Err(self.make_error(
-                                        &bc,
-                                        "Failed to pop binary sub left".to_string(),
-                                    ));
-                                }
-                            } else {
-                                return Err(self.make_error(
-                                    &bc,
-                                    "Failed to pop binary sub right".to_string(),
-                                ));
-                            }
-                        }
-                        Value::Object(_) => {
-                            return Err(self
-                                .make_error(&bc, "Binary sub objects not supported".to_string()));
-                        }
-                    },
-                    Value::Object(_) => {
-                        return Err(
-                            self.make_error(&bc, "Binary sub objects not supported".to_string())
-                        );
-                    }
-                },
-                Some(ByteOp::Mul) => match self.stack_peek(0) {
-                    Value::Nil => {
-                        return Err(self
-                            .make_error(&bc, "Binary mul right value cannot be Nil".to_string()));
-                    }
-                    Value::Bool(_) => {
-                        return Err(self
-                            .make_error(&bc, "Binary mul right value cannot be Bool".to_string()));
-                    }
-                    Value::Number(_) => match self.stack_peek(1) {
-                        Value::Nil => {
-                            return Err(self.make_error(
-                                &bc,
-                                "Binary mul left value cannot be Nil".to_string(),
-                            ));
-                        }
-                        Value::Bool(_) => {
-                            return Err(self.make_error(
-                                &bc,
-                                "Binary mul left value cannot be Bool".to_string(),
-                            ));
-                        }
-                        Value::Number(_) => {
-                            if let Some(Value::Number(b)) = self.stack.pop() {
-                                if let Some(Value::Number(a)) = self.stack.pop() {
-                                    self.stack.push(Value::Number(a * b));
-                                } else {
-                                    return Err(self.make_error(
-                                        &bc,
-                                        "Failed to pop binary mul left".to_string(),
-                                    ));
-                                }
-                            } else {
-                                return Err(self.make_error(
-                                    &bc,
-                                    "Failed to pop binary mul right".to_string(),
-                                ));
-                            }
-                        }
-                        Value::Object(_) => {
-                            return Err(self
-                                .make_error(&bc, "Binary nul objects not supported".to_string()));
-                        }
-                    },
-                    Value::Object(_) => {
-                        return Err(
-                            self.make_error(&bc, "Binary mul objects not supported".to_string())
-                        );
-                    }
-                },
-                Some(ByteOp::Div) => match self.stack_peek(0) {
-                    Value::Nil => {
-                        return Err(self
-                            .make_error(&bc, "Binary div right value cannot be Nil".to_string()));
-                    }
-                    Value::Bool(_) => {
-                        return Err(self
-                            .make_error(&bc, "Binary div right value cannot be Bool".to_string()));
-                    }
-                    Value::Number(_) => match self.stack_peek(1) {
-                        Value::Nil => {
-                            return Err(self.make_error(
-                                &bc,
-                                "Binary div left value cannot be Nil".to_string(),
-                            ));
-                        }
-                        Value::Bool(_) => {
-                            return Err(self.make_error(
-                                &bc,
-                                "Binary div left value cannot be Bool".to_string(),
-                            ));
-                        }
-                        Value::Number(_) => {
-                            if let Some(Value::Number(b)) = self.stack.pop() {
-                                if let Some(Value::Number(a)) = self.stack.pop() {
-                                    self.stack.push(Value::Number(a / b));
-                                } else {
-                                    return Err(self.make_error(
-                                        &bc,
-                                        "Failed to pop binary div left".to_string(),
-                                    ));
-                                }
-                            } else {
-                                return Err(self.make_error(
-                                    &bc,
-                                    "Failed to pop binary div right".to_string(),
-                                ));
-                            }
-                        }
-                        Value::Object(_) => {
-                            return Err(self
-                                .make_error(&bc, "Binary div objects not supported".to_string()));
-                        }
-                    },
-                    Value::Object(_) => {
-                        return Err(
-                            self.make_error(&bc, "Binary div objects not supported".to_string())
-                        );
-                    }
-                },
-                Some(ByteOp::Not) => match self.stack_peek(0) {
-                    Value::Nil => {
-                        return Err(
-                            self.make_error(&bc, "Unary not value cannot be Nil".to_string())
-                        );
-                    }
-                    Value::Bool(_) => {
-                        if let Some(Value::Bool(b)) = self.stack.pop() {
-                            self.stack.push(Value::Bool(!b));
-                        } else {
-                            return Err(
-                                self.make_error(&bc, "Failed to pop unary not value".to_string())
-                            );
-                        }
-                    }
-                    Value::Number(_) => {
-                        return Err(
-                            self.make_error(&bc, "Unary value cannot be Number".to_string())
-                        );
-                    }
-                    Value::Object(_) => {
-                        return Err(
-                            self.make_error(&bc, "Unary not objects not supported".to_string())
-                        );
-                    }
-                },
-                Some(ByteOp::And) => match self.stack_peek(0) {
-                    Value::Nil => {
-                        return Err(self.make_error(
-                            &bc,
-                            "Boolean \'and\' right value cannot be Nil".to_string(),
-                        ));
-                    }
-                    Value::Bool(_) => match self.stack_peek(1) {
-                        Value::Nil => {
-                            return Err(self.make_error(
-                                &bc,
-                                "Boolean \'and\' left value cannot be Nil".to_string(),
-                            ));
-                        }
-                        Value::Bool(_) => {
-                            if let Some(Value::Bool(b)) = self.stack.pop() {
-                                if let Some(Value::Bool(a)) = self.stack.pop() {
-                                    self.stack.push(Value::Bool(a && b));
-                                } else {
-                                    return Err(self.make_error(
-                                        &bc,
-                                        "Failed to pop boolean \'and\' left".to_string(),
-                                    ));
-                                }
-                            } else {
-                                return Err(self.make_error(
-                                    &bc,
-                                    "Failed to pop boolean \'and\' right".to_string(),
-                                ));
-                            }
-                        }
-                        Value::Number(_) => {
-                            return Err(self.make_error(
-                                &bc,
-                                "Boolean \'and\' left value cannot be Number".to_string(),
-                            ));
-                        }
-                        Value::Object(_) => {
-                            return Err(self.make_error(
-                                &bc,
-                                "Boolean \'and\' objects not supported".to_string(),
-                            ));
-                        }
-                    },
-                    Value::Number(_) => {
-                        return Err(self.make_error(
-                            &bc,
-                            "Boolean \'and\' right value cannot be Number".to_string(),
-                        ));
-                    }
-                    Value::Object(_) => {
-                        return Err(self
-                            .make_error(&bc, "Boolean \'and\' objects not supported".to_string()));
-                    }
-                },
-                Some(ByteOp::Or) => match self.stack_peek(0) {
-                    Value::Nil => {
-                        return Err(self.make_error(
-                            &bc,
-                            "Boolean \'or\' right value cannot be Nil".to_string(),
-                        ));
-                    }
-                    Value::Bool(_) => match self.stack_peek(1) {
-                        Value::Nil => {
-                            return Err(self.make_error(
-                                &bc,
-                                "Boolean \'or\' right value cannot be Nil".to_string(),
-                            ));
-                        }
-                        Value::Bool(_) => {
-                            if let Some(Value::Bool(b)) = self.stack.pop() {
-                                if let Some(Value::Bool(a)) = self.stack.pop() {
-                                    self.stack.push(Value::Bool(a || b));
-                                } else {
-                                    return Err(self.make_error(
-                                        &bc,
-                                        "Failed to pop boolean \'or\' left".to_string(),
-                                    ));
-                                }
-                            } else {
-                                return Err(self.make_error(
-                                    &bc,
-                                    "Failed to pop boolean \'or\' right".to_string(),
-                                ));
-                            }
-                        }
-                        Value::Number(_) => {
-                            return Err(self.make_error(
-                                &bc,
-                                "Boolean \'or\' value cannot be Number".to_string(),
-                            ));
-                        }
-                        Value::Object(_) => {
-                            return Err(self.make_error(
-                                &bc,
-                                "Boolean \'or\' objects not supported".to_string(),
-                            ));
-                        }
-                    },
-                    Value::Number(_) => {
-                        return Err(self
-                            .make_error(&bc, "Boolean \'or\' value cannot be Number".to_string()));
-                    }
-                    Value::Object(_) => {
-                        return Err(self
-                            .make_error(&bc, "Boolean \'or\' objects not supported".to_string()));
+                    _ => {
+                        return Err(self.make_error(bc, "Only functions can be called".to_string()));
                     }
-                },
-                Some(ByteOp::Equal) => match self.stack_peek(0) {
-                    Value::Nil => match self.stack_peek(1) {
-                        Value::Nil => {
-                            if let Some(Value::Nil) = self.stack.pop() {
-                                if let Some(Value::Nil) = self.stack.pop() {
-                                    self.stack.push(Value::Bool(true));
-                                } else {
-                                    return Err(self.make_error(
-                                        &bc,
-                                        "Failed to pop binary equal left".to_string(),
-                                    ));
-                                }
-                            } else {
-                                return Err(self.make_error(
-                                    &bc,
-                                    "Failed to pop binary equal right".to_string(),
-                                ));
-                            }
-                        }
-                        Value::Bool(_) => {
-                            return Err(
-                                self.make_error(&bc, "Cannot compare Nil to Bool".to_string())
-                            );
-                        }
-                        Value::Number(_) => {
-                            return Err(
-                                self.make_error(&bc, "Cannot compare Nil to Number".to_string())
-                            );
-                        }
-                        Value::Object(_) => {
-                            return Err(self
-                                .make_error(&bc, "Objects comparison not supported".to_string()));
-                        }
-                    },
-                    Value::Bool(_) => match self.stack_peek(1) {
-                        Value::Nil => {
-                            return Err(
-                                self.make_error(&bc, "Cannot compare Bool to Nil".to_string())
-                            );
-                        }
-                        Value::Bool(_) => {
-                            if let Some(Value::Bool(b)) = self.stack.pop() {
-                                if let Some(Value::Bool(a)) = self.stack.pop() {
-                                    self.stack.push(Value::Bool(a == b));
-                                } else {
-                                    return Err(self.make_error(
-                                        &bc,
-                                        "Failed to pop binary equal left".to_string(),
-                                    ));
-                                }
-                            } else {
-                                return Err(self.make_error(
-                                    &bc,
-                                    "Failed to pop binary equal right".to_string(),
-                                ));
-                            }
-                        }
-                        Value::Number(_) => {
-                            return Err(
-                                self.make_error(&bc, "Cannot compare Bool to Number".to_string())
-                            );
-                        }
-                        Value::Object(_) => {
-                            return Err(self
-                                .make_error(&bc, "Objects comparison not supported".to_string()));
-                        }
-                    },
-                    Value::Number(_) => match self.stack_peek(1) {
-                        Value::Nil => {
-                            return Err(
-                                self.make_error(&bc, "Cannot compare Number to Nil".to_string())
-                            );
-                        }
-                        Value::Bool(_) => {
-                            return Err(
-                                self.make_error(&bc, "Cannot compare Number to Number".to_string())
-                            );
-                        }
-                        Value::Number(_) => {
-                            if let Some(Value::Number(b)) = self.stack.pop() {
-                                if let Some(Value::Number(a)) = self.stack.pop() {
-                                    self.stack
-                                        .push(Value::Bool((a - b).abs() < std::f64::EPSILON));
-                                } else {
-                                    return Err(self.make_error(
-                                        &bc,
-                                        "Failed to pop binary equal left".to_string(),
-                                    ));
-                                }
-                            } else {
-                                return Err(self.make_error(
-                                    &bc,
-                                    "Failed to pop binary equal right".to_string(),
-                                ));
-                            }
-                        }
-                        Value::Object(_) => {
-                            return Err(self
-                                .make_error(&bc, "Objects comparison not supported".to_string()));
+                }
+            }
+            Some(ByteOp::Closure(idx)) => match bc.constants.get(*idx as usize) {
+                Some(Value::Object(o)) => match o.downcast_ref::<object::Function>() {
+                    Some(prototype) => {
+                        let mut function = prototype.clone();
+                        function.env = self.scopes.clone();
+                        if bc.entry_points.values().any(|i| i == idx) {
+                            self.entry_closures.insert(*idx, function.clone());
                         }
-                    },
-                    Value::Object(_) => {
-                        return Err(
-                            self.make_error(&bc, "Objects comparison not supported".to_string())
-                        );
+                        let value = Value::Object(Box::new(function));
+                        self.track_alloc(bc, &value)?;
+                        self.push(bc, value)?;
                     }
-                },
-                Some(ByteOp::Greater) => match self.stack_peek(0) {
-                    Value::Nil => {
-                        return Err(self.make_error(
-                            &bc,
-                            "Binary greater right value cannot be Nil".to_string(),
-                        ));
-                    }
-                    Value::Bool(_) => {
-                        return Err(self.make_error(
-                            &bc,
-                            "Binary greater right value cannot be Bool".to_string(),
-                        ));
-                    }
-                    Value::Number(_) => match self.stack_peek(1) {
-                        Value::Nil => {
-                            return Err(self.make_error(
-                                &bc,
-                                "Binary greater value cannot be Nil".to_string(),
-                            ));
-                        }
-                        Value::Bool(_) => {
-                            return Err(self.make_error(
-                                &bc,
-                                "Binary greater value cannot be Bool".to_string(),
-                            ));
-                        }
-                        Value::Number(_) => {
-                            if let Some(Value::Number(b)) = self.stack.pop() {
-                                if let Some(Value::Number(a)) = self.stack.pop() {
-                                    self.stack.push(Value::Bool(a > b));
-                                } else {
-                                    return Err(self.make_error(
-                                        &bc,
-                                        "Failed to pop binary greater left".to_string(),
-                                    ));
-                                }
-                            } else {
-                                return Err(self.make_error(
-                                    &bc,
-                                    "Failed to pop binary greater right".to_string(),
-                                ));
-                            }
-                        }
-                        Value::Object(_) => {
-                            return Err(self
-                                .make_error(&bc, "Objects comparison not supported".to_string()));
-                        }
-                    },
-                    Value::Object(_) => {
+                    None => {
                         return Err(
-                            self.make_error(&bc, "Objects comparison not supported".to_string())
+                            self.make_error(bc, "Closure constant must be a function".to_string())
                         );
                     }
                 },
-                Some(ByteOp::Less) => match self.stack_peek(0) {
-                    Value::Nil => {
-                        return Err(self
-                            .make_error(&bc, "Binary less right value cannot be Nil".to_string()));
-                    }
-                    Value::Bool(_) => {
+                Some(_) => {
+                    return Err(
+                        self.make_error(bc, "Closure constant must be a function".to_string())
+                    );
+                }
+                None => {
+                    return Err(self.make_error(bc, format!("Constant index {} out of range", idx)));
+                }
+            },
+            Some(ByteOp::DefVar(id)) => match StackVm::var_in_current_scope(&self.scopes, *id) {
+                Some(_) => {
+                    return Err(self.make_error(
+                        bc,
+                        format!("Variable: {} already defined", bc.var_name(*id)),
+                    ));
+                }
+                None => {
+                    // the value moves from the stack into the scope so it is already tracked
+                    let value = self.stack.pop().ok_or_else(|| self.underflow_error(bc))?;
+                    let var = (false, value);
+                    self.current_scope(bc)?
+                        .borrow_mut()
+                        .variables
+                        .insert(*id, var);
+                }
+            },
+            Some(ByteOp::DefMutVar(id)) => match StackVm::var_in_current_scope(&self.scopes, *id) {
+                Some(_) => {
+                    return Err(self.make_error(
+                        bc,
+                        format!("Variable: {} already defined", bc.var_name(*id)),
+                    ));
+                }
+                None => {
+                    // the value moves from the stack into the scope so it is already tracked
+                    let value = self.stack.pop().ok_or_else(|| self.underflow_error(bc))?;
+                    let var = (true, value);
+                    self.current_scope(bc)?
+                        .borrow_mut()
+                        .variables
+                        .insert(*id, var);
+                }
+            },
+            Some(ByteOp::SetVar(id)) => match StackVm::var_in_scopes(&self.scopes, *id) {
+                Some(scope) => {
+                    let mut scope = scope.borrow_mut();
+                    let val = scope.variables.get_mut(id).unwrap();
+                    if val.0 {
+                        // the value moves from the stack into the variable so it is already tracked
+                        let new = self.stack.pop().ok_or_else(|| self.underflow_error(bc))?;
+                        let old = std::mem::replace(&mut val.1, new);
+                        drop(scope);
+                        self.track_free(&old);
+                    } else {
                         return Err(self.make_error(
-                            &bc,
-                            "Binary less right value cannot be Bool".to_string(),
+                            bc,
+                            format!("Variable {} is not mutable", bc.var_name(*id)),
                         ));
                     }
-                    Value::Number(_) => match self.stack_peek(1) {
-                        Value::Nil => {
-                            return Err(
-                                self.make_error(&bc, "Binary less value cannot be Nil".to_string())
-                            );
-                        }
-                        Value::Bool(_) => {
-                            return Err(self
-                                .make_error(&bc, "Binary less value cannot be Bool".to_string()));
-                        }
-                        Value::Number(_) => {
-                            if let Some(Value::Number(b)) = self.stack.pop() {
-                                if let Some(Value::Number(a)) = self.stack.pop() {
-                                    self.stack.push(Value::Bool(a < b));
-                                } else {
-                                    return Err(self.make_error(
-                                        &bc,
-                                        "Failed to pop binary less left".to_string(),
-                                    ));
-                                }
-                            } else {
-                                return Err(self.make_error(
-                                    &bc,
-                                    "Failed to pop binary less right".to_string(),
-                                ));
-                            }
-                        }
-                        Value::Object(_) => {
-                            return Err(self
-                                .make_error(&bc, "Objects comparison not supported".to_string()));
-                        }
-                    },
-                    Value::Object(_) => {
-                        return Err(
-                            self.make_error(&bc, "Objects comparison not supported".to_string())
-                        );
-                    }
-                },
-                Some(ByteOp::Negate) => match self.stack_peek(0) {
-                    Value::Nil => {
-                        return Err(
-                            self.make_error(&bc, "Unary negate value cannot be Nil".to_string())
-                        );
-                    }
-                    Value::Bool(_) => {
-                        return Err(
-                            self.make_error(&bc, "Unary negate value cannot be Bool".to_string())
-                        );
-                    }
-                    Value::Number(_) => {
-                        if let Some(Value::Number(n)) = self.stack.pop() {
-                            self.stack.push(Value::Number(-n));
-                        } else {
-                            return Err(self
-                                .make_error(&bc, "Failed to pop unary negate value".to_string()));
-                        }
-                    }
-                    Value::Object(_) => {
-                        return Err(
-                            self.make_error(&bc, "Objects comparison not supported".to_string())
-                        );
-                    }
-                },
-                Some(ByteOp::ScopeOpen) => {
-                    self.scopes.push(Scope {
-                        variables: HashMap::new(),
-                    });
                 }
-                Some(ByteOp::ScopeClose) => {
-                    self.scopes.pop();
-                }
-                Some(ByteOp::NativeFnCall(id, arg_count)) => {
-                    if let Some(function) = self.native_fns.get(id) {
-                        if function.0 != *arg_count {
-                            #[cfg(feature = "store_names")]
-                            return Err(self.make_error(
-                                &bc,
-                                format!(
-                                    "Function: {} expects {} arguments but recieved {}",
-                                    bc.names[id], function.0, arg_count
-                                ),
-                            ));
-
-                            #[cfg(not(feature = "store_names"))]
-                            return Err(self.make_error(
-                                &bc,
-                                format!(
-                                    "Function: {} expects {} arguments but recieved {}",
-                                    id, function.0, arg_count
-                                ),
-                            ));
-                        } else if let Err(e) = (function.1)(self, &bc) {
-                            #[cfg(feature = "store_names")]
-                            return Err(self.make_error(
-                                &bc,
-                                format!("Function: {} returned an error: {}", bc.names[id], e.msg),
-                            ));
-
-                            #[cfg(not(feature = "store_names"))]
-                            return Err(self.make_error(
-                                &bc,
-                                format!("Function: {} returned an error: {}", id, e.msg),
-                            ));
-                        }
-                    } else {
-                        #[cfg(feature = "store_names")]
-                        return Err(
-                            self.make_error(&bc, format!("Function: {} not defined", bc.names[id]))
-                        );
-
-                        #[cfg(not(feature = "store_names"))]
-                        return Err(self.make_error(&bc, format!("Function: {} not defined", id)));
-                    }
+                None => {
+                    return Err(
+                        self.make_error(bc, format!("Variable {} not defined", bc.var_name(*id)))
+                    );
                 }
-                Some(ByteOp::DefVar(id)) => match StackVm::var_in_scopes(&*self.scopes, *id) {
-                    Some(_) => {
-                        #[cfg(feature = "store_names")]
-                        return Err(self.make_error(
-                            &bc,
-                            format!("Variable: {} already defined", bc.names[id]),
-                        ));
-
-                        #[cfg(not(feature = "store_names"))]
-                        return Err(
-                            self.make_error(&bc, format!("Variable: {} already defined", id))
-                        );
+            },
+            Some(ByteOp::GetVar(id)) => match StackVm::var_in_scopes(&self.scopes, *id) {
+                Some(scope) => {
+                    let value = scope.borrow().variables[id].1.clone();
+                    self.track_alloc(bc, &value)?;
+                    self.push(bc, value)?;
+                }
+                None => {
+                    return Err(
+                        self.make_error(bc, format!("Variable {} not defined", bc.var_name(*id)))
+                    );
+                }
+            },
+            Some(ByteOp::MakeArray(len)) => {
+                let len = *len as usize;
+                if self.stack.len() < len {
+                    return Err(self.underflow_error(bc));
+                }
+                let elems = self.stack.split_off(self.stack.len() - len);
+                // the elements move into the array so they are counted as part of it instead
+                for elem in &elems {
+                    self.track_free(elem);
+                }
+                let value = Value::Array(Rc::new(RefCell::new(elems)));
+                self.track_alloc(bc, &value)?;
+                self.push(bc, value)?;
+            }
+            Some(ByteOp::Index) => {
+                let (array, index) = self.pop_operands(bc)?;
+                self.track_free(&array);
+                let elems = match array {
+                    Value::Array(elems) => elems,
+                    v => return Err(self.make_error(bc, type_error("index", &v))),
+                };
+                let idx = self.array_index(bc, &index, elems.borrow().len())?;
+                let value = elems.borrow()[idx].clone();
+                self.track_alloc(bc, &value)?;
+                self.push(bc, value)?;
+            }
+            Some(ByteOp::SetIndex) => {
+                let (index, value) = self.pop_operands(bc)?;
+                let array = match self.stack.pop() {
+                    Some(array) => array,
+                    None => {
+                        return Err(self.underflow_error(bc));
                     }
+                };
+                // the array's copy on the stack is gone, the element is changed through its Rc
+                self.track_free(&array);
+                let elems = match array {
+                    Value::Array(elems) => elems,
+                    v => return Err(self.make_error(bc, type_error("index", &v))),
+                };
+                let idx = self.array_index(bc, &index, elems.borrow().len())?;
+                // the stored value is a copy of the one left on the stack
+                let old = std::mem::replace(&mut elems.borrow_mut()[idx], value.clone());
+                self.track_free(&old);
+                self.track_alloc(bc, &value)?;
+                self.push(bc, value)?;
+            }
+            Some(ByteOp::MakeStruct(name_idx, count)) => {
+                let count = *count as usize;
+                if self.stack.len() < count {
+                    return Err(self.underflow_error(bc));
+                }
+                let name = self.constant_name(bc, *name_idx)?;
+                let values = self.stack.split_off(self.stack.len() - count);
+                let mut fields = HashMap::new();
+                for (idx, value) in values.into_iter().enumerate() {
+                    // the values move into the struct so they are counted as part of it instead
+                    self.track_free(&value);
+                    let field = self.constant_name(bc, *name_idx + 1 + idx as u16)?;
+                    fields.insert(field, value);
+                }
+                let value = Value::Struct(Rc::new(RefCell::new(StructValue { name, fields })));
+                self.track_alloc(bc, &value)?;
+                self.push(bc, value)?;
+            }
+            Some(ByteOp::GetField(field_idx)) => {
+                let field = self.constant_name(bc, *field_idx)?;
+                let value = match self.stack.pop() {
+                    Some(Value::Struct(value)) => value,
+                    Some(v) => return Err(self.make_error(bc, type_error("field access", &v))),
                     None => {
-                        self.scopes.last_mut().unwrap().variables.insert(
-                            *id,
-                            if let Some(val) = self.stack.pop() {
-                                self.stack.push(val.clone());
-                                (false, val)
-                            } else {
-                                (false, Value::Nil)
-                            },
-                        );
+                        return Err(self.underflow_error(bc));
                     }
-                },
-                Some(ByteOp::DefMutVar(id)) => match StackVm::var_in_scopes(&*self.scopes, *id) {
-                    Some(_) => {
-                        #[cfg(feature = "store_names")]
-                        return Err(self.make_error(
-                            &bc,
-                            format!("Variable: {} already defined", bc.names[id]),
-                        ));
-
-                        #[cfg(not(feature = "store_names"))]
-                        return Err(
-                            self.make_error(&bc, format!("Variable: {} already defined", id))
-                        );
+                };
+                self.track_free(&Value::Struct(value.clone()));
+                let field_value = value.borrow().fields.get(&field).cloned();
+                match field_value {
+                    Some(field_value) => {
+                        self.track_alloc(bc, &field_value)?;
+                        self.push(bc, field_value)?;
                     }
                     None => {
-                        self.scopes.last_mut().unwrap().variables.insert(
-                            *id,
-                            if let Some(val) = self.stack.pop() {
-                                self.stack.push(val.clone());
-                                (true, val)
-                            } else {
-                                (true, Value::Nil)
-                            },
-                        );
-                    }
-                },
-                Some(ByteOp::SetVar(id)) => {
-                    match StackVm::var_in_scopes_mut(&mut self.scopes, *id) {
-                        Some(val) => {
-                            if val.0 {
-                                if !self.stack.is_empty() {
-                                    val.1 = self.stack.pop().unwrap();
-                                    self.stack.push(val.1.clone());
-                                } else {
-                                    val.1 = Value::Nil;
-                                }
-                            } else {
-                                #[cfg(feature = "store_names")]
-                                return Err(self.make_error(
-                                    &bc,
-                                    format!("Variable {} is not mutable", bc.names[id]),
-                                ));
-
-                                #[cfg(not(feature = "store_names"))]
-                                return Err(
-                                    self.make_error(&bc, format!("Variable {} is not mutable", id))
-                                );
-                            }
-                        }
-                        None => {
-                            return Err(
-                                self.make_error(&bc, format!("Variable {} not defined", id))
-                            );
-                        }
+                        let msg = format!("Struct {} has no field {}", value.borrow().name, field);
+                        return Err(self.make_error(bc, msg));
                     }
                 }
-                Some(ByteOp::GetVar(id)) => match StackVm::var_in_scopes(&self.scopes, *id) {
-                    Some(val) => {
-                        self.stack.push(val.1.clone());
+            }
+            Some(ByteOp::SetField(field_idx)) => {
+                let field = self.constant_name(bc, *field_idx)?;
+                let (target, value) = self.pop_operands(bc)?;
+                self.track_free(&target);
+                let target = match target {
+                    Value::Struct(target) => target,
+                    v => return Err(self.make_error(bc, type_error("field access", &v))),
+                };
+                let old = match target.borrow_mut().fields.get_mut(&field) {
+                    // the stored value is a copy of the one left on the stack
+                    Some(slot) => Some(std::mem::replace(slot, value.clone())),
+                    None => None,
+                };
+                match old {
+                    Some(old) => {
+                        self.track_free(&old);
+                        self.track_alloc(bc, &value)?;
+                        self.push(bc, value)?;
                     }
                     None => {
-                        #[cfg(feature = "store_names")]
-                        return Err(self.make_error(
-                            &bc,
-                            format!("Failed to find variable {} in scope", bc.names[id]),
-                        ));
-
-                        #[cfg(not(feature = "store_names"))]
-                        return Err(self
-                            .make_error(&bc, format!("Failed to find variable {} in scope", id)));
+                        let msg = format!("Struct {} has no field {}", target.borrow().name, field);
+                        return Err(self.make_error(bc, msg));
                     }
-                },
-                Some(ByteOp::Jump(distance)) => {
+                }
+            }
+            Some(ByteOp::Jump(distance)) => {
+                self.ip_idx = (self.ip_idx as isize + *distance as isize) as usize;
+                return Ok(StepResult::Continue);
+            }
+            Some(ByteOp::JumpFalse(distance)) => match self.pop_value(bc)? {
+                Value::Bool(false) => {
                     self.ip_idx = (self.ip_idx as isize + *distance as isize) as usize;
-                    continue 'run;
+                    return Ok(StepResult::Continue);
                 }
-                Some(ByteOp::JumpFalse(distance)) => match self.stack_peek(0) {
-                    Value::Nil => {
-                        return Err(
-                            self.make_error(&bc, "Jump on false value cannot be Nil".to_string())
-                        );
-                    }
-                    Value::Bool(_) => {
-                        if let Some(Value::Bool(b)) = self.stack.pop() {
-                            if !b {
-                                self.ip_idx = (self.ip_idx as isize + *distance as isize) as usize;
-                                continue 'run;
-                            }
-                        } else {
-                            return Err(self
-                                .make_error(&bc, "Failed to pop jump on false value".to_string()));
-                        }
-                    }
-                    Value::Number(_) => {
-                        return Err(self
-                            .make_error(&bc, "Jump on false value cannot be Number".to_string()));
-                    }
-                    Value::Object(_) => {
-                        return Err(self
-                            .make_error(&bc, "Jump on false value cannot be Object".to_string()));
-                    }
-                },
-                Some(ByteOp::JumpTrue(distance)) => match self.stack_peek(0) {
-                    Value::Nil => {
-                        return Err(
-                            self.make_error(&bc, "Jump on true value cannot be Nil".to_string())
-                        );
-                    }
-                    Value::Bool(_) => {
-                        if let Some(Value::Bool(b)) = self.stack.pop() {
-                            if b {
-                                self.ip_idx = (self.ip_idx as isize + *distance as isize) as usize;
-                                continue 'run;
-                            }
-                        } else {
-                            return Err(self
-                                .make_error(&bc, "Failed to pop jump on true value".to_string()));
-                        }
-                    }
-                    Value::Number(_) => {
-                        return Err(
-                            self.make_error(&bc, "Jump on true value cannot be bool".to_string())
-                        );
-                    }
-                    Value::Object(_) => {
-                        return Err(self
-                            .make_error(&bc, "Jump on false value cannot be Object".to_string()));
-                    }
-                },
-                Some(ByteOp::Pop) => {
-                    self.stack.pop();
+                Value::Bool(true) => {}
+                v => return Err(self.make_error(bc, type_error("jump on false", &v))),
+            },
+            Some(ByteOp::JumpTrue(distance)) => match self.pop_value(bc)? {
+                Value::Bool(true) => {
+                    self.ip_idx = (self.ip_idx as isize + *distance as isize) as usize;
+                    return Ok(StepResult::Continue);
                 }
-                Some(ByteOp::Return) => {
-                    if let Some(v) = self.stack.pop() {
-                        self.scopes.pop();
-                        if !self.scopes.is_empty() {
-                            self.stack.push(v);
-                            while match bc.ops[self.ip_idx] {
-                                ByteOp::ScopeClose => false,
-                                _ => true,
-                            } {
-                                self.ip_idx += 1;
-                            }
-                        } else {
-                            return Ok(v);
-                        }
+                Value::Bool(false) => {}
+                v => return Err(self.make_error(bc, type_error("jump on true", &v))),
+            },
+            Some(op @ ByteOp::Print) | Some(op @ ByteOp::PrintLn) => match self.stack.pop() {
+                Some(value) => {
+                    self.track_free(&value);
+                    let result = if let ByteOp::PrintLn = op {
+                        writeln!(self.writer, "{}", value)
                     } else {
-                        self.scopes.pop();
-                        if !self.scopes.is_empty() {
-                            self.stack.push(Value::Nil);
-                        } else {
-                            return Ok(Value::Nil);
-                        }
+                        write!(self.writer, "{}", value).and_then(|_| self.writer.flush())
+                    };
+                    if let Err(e) = result {
+                        return Err(self.make_error(bc, e.to_string()));
                     }
+                    // printing is an expression so it leaves nil behind
+                    self.push(bc, Value::Nil)?;
                 }
                 None => {
-                    break 'run;
+                    return Err(self.underflow_error(bc));
                 }
-            };
-            self.ip_idx += 1;
-        }
-        Ok(Value::Nil)
+            },
+            Some(ByteOp::Assert) => match self.stack_peek(bc, 0)? {
+                Value::Bool(b) => {
+                    self.stack.pop();
+                    if !b {
+                        return Err(self.make_error(bc, "assertion failed".to_string()));
+                    }
+                    self.push(bc, Value::Nil)?;
+                }
+                v => return Err(self.make_error(bc, type_error("assert", &v))),
+            },
+            Some(ByteOp::Pop) => match self.stack.pop() {
+                Some(value) => self.track_free(&value),
+                None => return Err(self.underflow_error(bc)),
+            },
+            Some(ByteOp::PopN(count)) => {
+                let count = *count as usize;
+                if self.stack.len() < count {
+                    return Err(self.underflow_error(bc));
+                }
+                for value in self.stack.split_off(self.stack.len() - count) {
+                    self.track_free(&value);
+                }
+            }
+            Some(ByteOp::Dup) => match self.stack.last().cloned() {
+                Some(value) => {
+                    self.track_alloc(bc, &value)?;
+                    self.push(bc, value)?;
+                }
+                None => {
+                    return Err(self.underflow_error(bc));
+                }
+            },
+            Some(ByteOp::Swap) => {
+                let len = self.stack.len();
+                if len < 2 {
+                    return Err(self.underflow_error(bc));
+                }
+                self.stack.swap(len - 1, len - 2);
+            }
+            Some(ByteOp::Return) => {
+                let in_function = match self.frames.last() {
+                    Some(frame) => self.scopes.len() <= frame.scope_base,
+                    None => false,
+                };
+                if in_function {
+                    let frame = self.frames.pop().unwrap();
+                    let v = if self.stack.len() > frame.stack_base {
+                        self.stack.pop().unwrap()
+                    } else {
+                        Value::Nil
+                    };
+                    self.stack.truncate(frame.stack_base);
+                    self.scopes = frame.scopes;
+                    self.ip_idx = frame.return_idx;
+                    if frame.returns_to_host {
+                        return Ok(StepResult::Halted(v));
+                    }
+                    self.push(bc, v)?;
+                } else {
+                    // outside of a function a return ends the run, only the global scope outlives it
+                    let keep = if self.global_scope { 1 } else { 0 };
+                    while self.scopes.len() > keep {
+                        self.pop_scope();
+                    }
+                    let v = self.stack.pop().unwrap_or(Value::Nil);
+                    return Ok(StepResult::Halted(v));
+                }
+            }
+            None => {
+                return Ok(StepResult::Halted(Value::Nil));
+            }
+        };
+        self.ip_idx += 1;
+        Ok(StepResult::Continue)
     }
 }
@@ -1,11 +1,23 @@
-use super::Value;
+use super::{Scope, Value};
 use downcast_rs::Downcast;
+use serde::{Deserialize, Serialize};
+use std::{cell::RefCell, rc::Rc};
 
 #[typetag::serde(tag = "StackVmObject")]
 pub trait StackVmObject:
     StackVmObjectClone + std::fmt::Debug + std::fmt::Display + Downcast
 {
     fn add(&self, r: Value) -> Result<Value, String>;
+
+    // name of the object's type used in error messages
+    fn typename(&self) -> &'static str {
+        "object"
+    }
+
+    // approximate number of bytes owned by the object, used for heap accounting
+    fn heap_size(&self) -> usize {
+        std::mem::size_of_val(self)
+    }
 }
 downcast_rs::impl_downcast!(StackVmObject);
 
@@ -40,4 +52,44 @@ impl StackVmObject for String {
         };
         Ok(Value::Object(Box::new(s)))
     }
+
+    fn heap_size(&self) -> usize {
+        std::mem::size_of::<String>() + self.capacity()
+    }
+
+    fn typename(&self) -> &'static str {
+        "string"
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Function {
+    pub entry: usize,
+    pub params: Vec<(u64, bool)>,
+
+    #[serde(skip)]
+    pub(crate) env: Vec<Rc<RefCell<Scope>>>,
+}
+
+impl std::fmt::Debug for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Function({} params @ {})", self.params.len(), self.entry)
+    }
+}
+
+impl std::fmt::Display for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<function>")
+    }
+}
+
+#[typetag::serde]
+impl StackVmObject for Function {
+    fn add(&self, _r: Value) -> Result<Value, String> {
+        Err("Cannot add to a function".to_string())
+    }
+
+    fn typename(&self) -> &'static str {
+        "function"
+    }
 }
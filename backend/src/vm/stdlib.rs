@@ -13,7 +13,7 @@ pub fn read_num(vm: &mut StackVm, bc: &Bytecode) -> Result<(), VmError> {
         Err(e) => return Err(vm.make_error(bc, e.to_string())),
     };
 
-    vm.stack.push(value);
+    vm.push(bc, value)?;
 
     Ok(())
 }
@@ -30,7 +30,7 @@ pub fn read_bool(vm: &mut StackVm, bc: &Bytecode) -> Result<(), VmError> {
         _ => return Err(vm.make_error(bc, "Not true or false".to_string())),
     };
 
-    vm.stack.push(value);
+    vm.push(bc, value)?;
 
     Ok(())
 }
@@ -43,47 +43,42 @@ pub fn read_line(vm: &mut StackVm, bc: &Bytecode) -> Result<(), VmError> {
 
     let value = Value::Object(Box::new(input.trim().to_string()));
 
-    vm.stack.push(value);
+    vm.push(bc, value)?;
 
     Ok(())
 }
 
-pub fn println(vm: &mut StackVm, _bc: &Bytecode) -> Result<(), VmError> {
-    match vm.stack.pop() {
-        Some(val) => match val {
-            Value::Nil => println!("nil"),
-            Value::Bool(b) => println!("{}", b),
-            Value::Number(n) => println!("{}", n),
-            Value::Object(o) => println!("{}", o),
-        },
-        None => println!("No value in stack"),
+pub fn println(vm: &mut StackVm, bc: &Bytecode) -> Result<(), VmError> {
+    let result = match vm.stack.pop() {
+        Some(val) => writeln!(vm.writer, "{}", val),
+        None => writeln!(vm.writer, "No value in stack"),
+    };
+    if let Err(e) = result {
+        return Err(vm.make_error(bc, e.to_string()));
     }
     Ok(())
 }
 
-pub fn print(vm: &mut StackVm, _bc: &Bytecode) -> Result<(), VmError> {
-    match vm.stack.pop() {
-        Some(val) => match val {
-            Value::Nil => print!("nil"),
-            Value::Bool(b) => print!("{}", b),
-            Value::Number(n) => print!("{}", n),
-            Value::Object(o) => print!("{}", o),
-        },
-        None => println!("No value in stack"),
+pub fn print(vm: &mut StackVm, bc: &Bytecode) -> Result<(), VmError> {
+    let result = match vm.stack.pop() {
+        Some(val) => write!(vm.writer, "{}", val),
+        None => writeln!(vm.writer, "No value in stack"),
+    };
+    if let Err(e) = result.and_then(|_| vm.writer.flush()) {
+        return Err(vm.make_error(bc, e.to_string()));
     }
-    std::io::stdout().flush().unwrap();
     Ok(())
 }
 
 pub fn vmto_number(vm: &mut StackVm, bc: &Bytecode) -> Result<(), VmError> {
     match vm.stack.pop() {
         Some(val) => match val {
-            Value::Nil => vm.stack.push(Value::Number(0.0)),
-            Value::Bool(b) => vm.stack.push(Value::Number(if b { 1.0 } else { 0.0 })),
-            Value::Number(n) => vm.stack.push(Value::Number(n)),
+            Value::Nil => vm.push(bc, Value::Number(0.0))?,
+            Value::Bool(b) => vm.push(bc, Value::Number(if b { 1.0 } else { 0.0 }))?,
+            Value::Number(n) => vm.push(bc, Value::Number(n))?,
             Value::Object(o) => match o.downcast::<String>() {
                 Ok(s) => match s.trim().parse::<f64>() {
-                    Ok(n) => vm.stack.push(Value::Number(n)),
+                    Ok(n) => vm.push(bc, Value::Number(n))?,
                     Err(e) => return Err(vm.make_error(bc, e.to_string())),
                 },
                 Err(_) => {
@@ -92,6 +87,12 @@ pub fn vmto_number(vm: &mut StackVm, bc: &Bytecode) -> Result<(), VmError> {
                     )
                 }
             },
+            Value::Array(_) => {
+                return Err(vm.make_error(bc, "Cannot convert an array to a number".to_string()))
+            }
+            Value::Struct(_) => {
+                return Err(vm.make_error(bc, "Cannot convert a struct to a number".to_string()))
+            }
         },
         None => return Err(vm.make_error(bc, "No value in stack to convert".to_string())),
     }
@@ -101,10 +102,12 @@ pub fn vmto_number(vm: &mut StackVm, bc: &Bytecode) -> Result<(), VmError> {
 pub fn vmto_bool(vm: &mut StackVm, bc: &Bytecode) -> Result<(), VmError> {
     match vm.stack.pop() {
         Some(val) => match val {
-            Value::Nil => vm.stack.push(Value::Bool(false)),
-            Value::Bool(b) => vm.stack.push(Value::Bool(b)),
-            Value::Number(n) => vm.stack.push(Value::Bool(n > 0.0)),
-            Value::Object(_) => vm.stack.push(Value::Bool(false)),
+            Value::Nil => vm.push(bc, Value::Bool(false))?,
+            Value::Bool(b) => vm.push(bc, Value::Bool(b))?,
+            Value::Number(n) => vm.push(bc, Value::Bool(n > 0.0))?,
+            Value::Object(_) | Value::Array(_) | Value::Struct(_) => {
+                vm.push(bc, Value::Bool(false))?
+            }
         },
         None => return Err(vm.make_error(bc, "No value in stack to convert".to_string())),
     }
@@ -114,10 +117,13 @@ pub fn vmto_bool(vm: &mut StackVm, bc: &Bytecode) -> Result<(), VmError> {
 pub fn vmto_string(vm: &mut StackVm, bc: &Bytecode) -> Result<(), VmError> {
     match vm.stack.pop() {
         Some(val) => match val {
-            Value::Nil => vm.stack.push(Value::Object(Box::new("Nil".to_string()))),
-            Value::Bool(b) => vm.stack.push(Value::Object(Box::new(format!("{}", b)))),
-            Value::Number(n) => vm.stack.push(Value::Object(Box::new(format!("{}", n)))),
-            Value::Object(o) => vm.stack.push(Value::Object(Box::new(format!("{:?}", o)))),
+            Value::Nil => vm.push(bc, Value::Object(Box::new("Nil".to_string())))?,
+            Value::Bool(b) => vm.push(bc, Value::Object(Box::new(format!("{}", b))))?,
+            Value::Number(n) => vm.push(bc, Value::Object(Box::new(format!("{}", n))))?,
+            Value::Object(o) => vm.push(bc, Value::Object(Box::new(format!("{:?}", o))))?,
+            Value::Array(_) | Value::Struct(_) => {
+                vm.push(bc, Value::Object(Box::new(format!("{}", val))))?
+            }
         },
         None => return Err(vm.make_error(bc, "No value in stack to convert".to_string())),
     }
@@ -144,6 +150,7 @@ pub fn get_stdlib_signatures() -> SemanticStdLib {
     stdlib.add_fn(String::from("read_bool"), make_fn_sig! { () -> Bool });
     stdlib.add_fn(String::from("println"), make_fn_sig! { (String) -> Nil });
     stdlib.add_fn(String::from("print"), make_fn_sig! { (String) -> Nil });
+    stdlib.add_fn(String::from("assert"), make_fn_sig! { (Bool) -> Nil });
     stdlib.add_fn(String::from("nums"), make_fn_sig! { (String) -> I32 });
     stdlib.add_fn(String::from("numb"), make_fn_sig! { (Bool) -> I32 });
     stdlib.add_fn(String::from("booln"), make_fn_sig! { (I32) -> Bool });
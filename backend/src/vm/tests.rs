@@ -0,0 +1,1054 @@
+use super::builder::BytecodeBuilder;
+use super::*;
+use graviton_frontend::parser::Parser;
+
+fn parse_block(source: &str) -> ast::AstNode {
+    let (module, _) = Parser::parse(source, None).expect("source should parse");
+    let pos = module.expressions[0].pos;
+    ast::AstNode {
+        node: ast::Ast::Block(module.expressions),
+        pos,
+        type_sig: None,
+    }
+}
+
+fn compile(source: &str) -> Bytecode {
+    Bytecode::new(parse_block(source)).expect("source should compile")
+}
+
+fn run(source: &str) -> Result<Value, GravitonError> {
+    StackVm::new().run(compile(source), 0)
+}
+
+fn assert_number<E: std::fmt::Debug>(result: Result<Value, E>, expected: f64) {
+    match result {
+        Ok(Value::Number(n)) if n == expected => {}
+        other => panic!("expected {}, got {:?}", expected, other),
+    }
+}
+
+fn build(constants: &[f64], ops: &[ByteOp]) -> Result<Bytecode, String> {
+    let mut builder = BytecodeBuilder::new();
+    for c in constants {
+        builder.add_constant(Value::Number(*c))?;
+    }
+    for op in ops {
+        builder.push_op(*op);
+    }
+    builder.build()
+}
+
+fn compile_error(source: &str) -> String {
+    match Bytecode::new(parse_block(source)) {
+        Ok(_) => panic!("expected {:?} to fail to compile", source),
+        Err(e) => e.to_string(),
+    }
+}
+
+// runs hand-built ops over the number constants and returns the result as text
+fn run_ops(constants: &[f64], ops: &[ByteOp]) -> Result<String, String> {
+    let bc = build(constants, ops).expect("bytecode should be valid");
+    StackVm::new()
+        .run(bc, 0)
+        .map(|v| v.to_string())
+        .map_err(|e| e.to_string())
+}
+
+fn run_error(constants: &[f64], ops: &[ByteOp]) -> String {
+    let bc = build(constants, ops).expect("bytecode should be valid");
+    match StackVm::new().run(bc, 0) {
+        Ok(v) => panic!("expected {:?} to fail, got {:?}", ops, v),
+        Err(e) => e.to_string(),
+    }
+}
+
+// a writer the test can still read from after handing it to the vm
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn fold(source: &str) -> ast::Ast {
+    let mut block = parse_block(source);
+    match &mut block.node {
+        ast::Ast::Block(exprs) => fold::fold_constants(exprs.remove(0).node),
+        _ => unreachable!(),
+    }
+}
+
+fn sqrt(args: &[Value]) -> Result<Value, String> {
+    match &args[0] {
+        Value::Number(n) if *n >= 0.0 => Ok(Value::Number(n.sqrt())),
+        v => Err(format!("cannot take the square root of {}", v)),
+    }
+}
+
+thread_local! {
+    static TICKS: std::cell::Cell<u32> = const { std::cell::Cell::new(0) };
+}
+
+// true for the first three calls and false after that
+fn tick(_: &[Value]) -> Result<Value, String> {
+    let calls = TICKS.with(|t| {
+        t.set(t.get() + 1);
+        t.get()
+    });
+    Ok(Value::Bool(calls <= 3))
+}
+
+// calls its first argument with its second
+fn apply(vm: &mut StackVm, bc: &Bytecode) -> Result<(), VmError> {
+    let arg = vm.stack.pop().unwrap();
+    let function = vm.stack.pop().unwrap();
+    let result = vm.call(bc, function, vec![arg])?;
+    vm.push(bc, result)
+}
+
+// pushes as many copies of its argument as it says
+fn spread(vm: &mut StackVm, bc: &Bytecode) -> Result<(), VmError> {
+    match vm.stack.pop() {
+        Some(Value::Number(n)) => (0..n as usize).try_for_each(|_| vm.push(bc, Value::Number(n))),
+        _ => Err(vm.make_error(bc, "spread takes a number".to_string())),
+    }
+}
+
+#[test]
+fn continue_pops_pending_operands() {
+    let source = "let mut i = 0; let mut x = 0; \
+                  while i < 1000 { i += 1; x = 1 + { continue; 2 }; }; i";
+    let mut vm = StackVm::with_stack_limit(100);
+    assert_number(vm.run(compile(source), 0), 1000.0);
+    assert!(vm.stack().is_empty());
+}
+
+#[test]
+fn break_pops_pending_operands() {
+    let mut vm = StackVm::new();
+    let result = vm.run(
+        compile("let mut x = 0; while true { x = [1, 2 + { break; 3 }]; }; x"),
+        0,
+    );
+    assert_number(result, 0.0);
+    assert!(vm.stack().is_empty());
+}
+
+#[test]
+fn leaked_values_stay_on_the_stack() {
+    // the repl shows these at debug level 2, the compiler should never leave any
+    let bc = build(
+        &[1.0, 2.0],
+        &[ByteOp::Load(0), ByteOp::Load(1), ByteOp::Return],
+    )
+    .unwrap();
+    let mut vm = StackVm::new();
+    assert_number(vm.run(bc, 0), 2.0);
+    assert!(matches!(vm.stack(), [Value::Number(n)] if *n == 1.0));
+
+    vm.reset(false);
+    assert!(vm.stack().is_empty());
+}
+
+#[test]
+fn variable_ids_are_fnv1a_hashes() {
+    assert_eq!(var_id(""), 0xcbf2_9ce4_8422_2325);
+    assert_eq!(var_id("a"), 0xaf63_dc4c_8601_ec8c);
+    assert_eq!(var_id("foobar"), 0x8594_4171_f739_67e8);
+
+    // these two shared an id back when ids were 16 bits
+    assert_ne!(var_id("dad"), var_id("haa"));
+    assert_number(run("{ let dad = 1; { let haa = 2; dad + haa } }"), 3.0);
+}
+
+#[test]
+fn inner_scopes_shadow_but_redefining_is_an_error() {
+    let shadowed = [
+        ByteOp::ScopeOpen,
+        ByteOp::Load(0),
+        ByteOp::DefVar(1),
+        ByteOp::ScopeOpen,
+        ByteOp::Load(1),
+        ByteOp::DefVar(1),
+        ByteOp::GetVar(1),
+        ByteOp::ScopeClose,
+        ByteOp::ScopeClose,
+        ByteOp::Return,
+    ];
+    assert_number(
+        StackVm::new().run(build(&[1.0, 2.0], &shadowed).unwrap(), 0),
+        2.0,
+    );
+
+    let redefined = [
+        ByteOp::ScopeOpen,
+        ByteOp::Load(0),
+        ByteOp::DefVar(1),
+        ByteOp::Load(1),
+        ByteOp::DefVar(1),
+        ByteOp::Return,
+    ];
+    let error = StackVm::new()
+        .run(build(&[1.0, 2.0], &redefined).unwrap(), 0)
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("already defined"), "{}", error);
+}
+
+#[test]
+fn appended_code_sees_earlier_variables() {
+    let mut bc = Bytecode::empty();
+    let mut vm = StackVm::new();
+    vm.push_global_scope();
+    let start = bc.append_global(parse_block("let dad = 1;")).unwrap();
+    vm.run_from(&bc, start, 0).unwrap();
+    vm.reset(true);
+
+    let start = bc
+        .append_global(parse_block("{ let haa = 2; dad + haa }"))
+        .unwrap();
+    assert_number(vm.run_from(&bc, start, 0), 3.0);
+}
+
+#[test]
+fn setting_an_element_frees_the_array_copy() {
+    let source = "let mut i = 0; \
+                  while i < 20000 { i += 1; let a = [1, 2, 3]; a[0] = 5; }; i";
+    let mut vm = StackVm::new();
+    vm.set_max_heap_bytes(Some(100_000));
+    assert_number(vm.run(compile(source), 0), 20000.0);
+}
+
+#[test]
+fn setting_a_field_frees_the_struct_copy() {
+    let source = "struct Point { x: I32, y: I32 } let mut i = 0; \
+                  while i < 20000 { i += 1; let p = Point { x: 1, y: 2 }; p.y = 5; }; i";
+    let mut vm = StackVm::new();
+    vm.set_max_heap_bytes(Some(100_000));
+    assert_number(vm.run(compile(source), 0), 20000.0);
+}
+
+#[test]
+fn field_access_on_a_non_struct_is_a_runtime_error() {
+    let mut builder = BytecodeBuilder::new();
+    let one = builder.add_constant(Value::Number(1.0)).unwrap();
+    let field = builder
+        .add_constant(Value::Object(Box::new("x".to_string())))
+        .unwrap();
+    builder.push_op(ByteOp::Load(one));
+    builder.push_op(ByteOp::GetField(field));
+    builder.push_op(ByteOp::Return);
+    let error = StackVm::new()
+        .run(builder.build().unwrap(), 0)
+        .unwrap_err()
+        .to_string();
+    assert!(
+        error.contains("Unsupported operand type for field access"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn malformed_bytecode_is_an_error() {
+    let error = run_error(&[], &[ByteOp::Nil, ByteOp::DefVar(1), ByteOp::Return]);
+    assert!(error.contains("outside of any scope"), "{}", error);
+    let error = run_error(&[], &[ByteOp::Nil, ByteOp::DefMutVar(1), ByteOp::Return]);
+    assert!(error.contains("outside of any scope"), "{}", error);
+
+    let error = run_error(&[], &[ByteOp::GetVar(1), ByteOp::Return]);
+    assert!(error.contains("not defined"), "{}", error);
+    let error = run_error(&[], &[ByteOp::Nil, ByteOp::SetVar(1), ByteOp::Return]);
+    assert!(error.contains("not defined"), "{}", error);
+
+    let error = run_error(
+        &[],
+        &[
+            ByteOp::ScopeOpen,
+            ByteOp::Nil,
+            ByteOp::DefVar(1),
+            ByteOp::True,
+            ByteOp::SetVar(1),
+            ByteOp::Return,
+        ],
+    );
+    assert!(error.contains("is not mutable"), "{}", error);
+
+    // closing more scopes than were opened is harmless
+    assert!(matches!(
+        StackVm::new().run(
+            build(&[], &[ByteOp::ScopeClose, ByteOp::Nil, ByteOp::Return]).unwrap(),
+            0
+        ),
+        Ok(Value::Nil)
+    ));
+}
+
+#[test]
+fn bytecode_without_names_has_the_same_layout() {
+    let mut bc = compile("let x = 1; x");
+    let id = var_id("x");
+    let loaded = Bytecode::from_bytes(&bc.to_bytes().unwrap()).unwrap();
+    if cfg!(feature = "store_names") {
+        assert_eq!(loaded.var_name(id), "'x'");
+    }
+
+    // what a build without the store_names feature writes
+    bc.names.clear();
+    let stripped = Bytecode::from_bytes(&bc.to_bytes().unwrap()).unwrap();
+    assert_eq!(stripped.var_name(id), id.to_string());
+    assert_number(StackVm::new().run(loaded, 0), 1.0);
+    assert_number(StackVm::new().run(stripped, 0), 1.0);
+}
+
+#[test]
+fn print_writes_to_the_vm_writer() {
+    let out = SharedBuffer::default();
+    let mut vm = StackVm::with_writer(Box::new(out.clone()));
+    let result = vm.run(compile("print(1); println(true); print([2, 3])"), 0);
+    assert!(matches!(result, Ok(Value::Nil)));
+    assert_eq!(
+        String::from_utf8(out.0.borrow().clone()).unwrap(),
+        "1true\n[2, 3]"
+    );
+}
+
+#[test]
+fn values_display_without_their_variant() {
+    assert_eq!(Value::Number(42.0).to_string(), "42");
+    assert_eq!(Value::Number(1.5).to_string(), "1.5");
+    assert_eq!(Value::Number(-0.0).to_string(), "-0");
+    assert_eq!(Value::Bool(true).to_string(), "true");
+    assert_eq!(Value::Nil.to_string(), "nil");
+    assert_eq!(Value::Object(Box::new("hi".to_string())).to_string(), "hi");
+
+    let point = run("struct Point { y: I32, x: I32 } Point { y: 2, x: 1 }").unwrap();
+    assert_eq!(point.to_string(), "Point { x: 1, y: 2 }");
+}
+
+#[test]
+fn constants_are_folded() {
+    assert!(matches!(fold("1 + 2 * 3"), ast::Ast::Integer(7)));
+    assert!(matches!(fold("!(1 < 2)"), ast::Ast::Bool(false)));
+    assert!(matches!(fold("x + 0"), ast::Ast::Binary(..)));
+    assert!(matches!(fold("1 / 0"), ast::Ast::Binary(..)));
+
+    // integer division is only folded when the result stays a whole number
+    assert!(matches!(fold("6 / 2"), ast::Ast::Integer(3)));
+    assert!(matches!(fold("7 / 2"), ast::Ast::Binary(..)));
+    assert!(matches!(fold("7.0 / 2"), ast::Ast::Float(n) if n == 3.5));
+}
+
+#[test]
+fn integer_overflow_is_left_to_the_vm() {
+    let mut add = parse_block("2147483647 + 1");
+    if let ast::Ast::Block(exprs) = &mut add.node {
+        exprs[0].type_sig = Some(ast::TypeSignature::Primitive(ast::PrimitiveType::I32));
+    }
+    let mut bc = Bytecode::empty();
+    bc.set_fold_constants(true);
+    let start = bc.append_global(add).unwrap();
+    assert!(bc.ops()[start..]
+        .iter()
+        .any(|op| matches!(op, ByteOp::Wrap(ast::PrimitiveType::I32))));
+}
+
+#[test]
+fn appended_code_is_folded_when_enabled() {
+    let mut bc = Bytecode::empty();
+    bc.set_fold_constants(true);
+    let start = bc.append_global(parse_block("1 + 2 * 3")).unwrap();
+    assert_eq!(bc.ops().len() - start, 2);
+
+    let mut vm = StackVm::new();
+    vm.push_global_scope();
+    assert_number(vm.run_from(&bc, start, 0), 7.0);
+}
+
+#[test]
+fn huge_arrays_stop_at_the_heap_limit() {
+    let elems = vec!["0"; 10_000].join(", ");
+    let source = format!("let a = [{}]; a", elems);
+
+    let mut vm = StackVm::new();
+    vm.set_max_heap_bytes(Some(100_000));
+    let error = vm.run(compile(&source), 0).unwrap_err().to_string();
+    assert!(error.contains("Out of memory"), "{}", error);
+
+    // every copy is counted, so holding the same array twice takes twice the bytes
+    let one = 1_000 * std::mem::size_of::<Value>();
+    let elems = vec!["0"; 1_000].join(", ");
+    let mut vm = StackVm::new();
+    vm.set_max_heap_bytes(Some(one * 2 + one / 2));
+    assert!(vm
+        .run(compile(&format!("let a = [{}]; a", elems)), 0)
+        .is_ok());
+    vm.reset(false);
+    let error = vm
+        .run(
+            compile(&format!("let a = [{}]; let b = a; [a, b]", elems)),
+            0,
+        )
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("Out of memory"), "{}", error);
+}
+
+#[test]
+fn corrupted_bytecode_is_rejected() {
+    let bytes = compile("let x = 1; x").to_bytes().unwrap();
+    assert!(Bytecode::from_bytes(&bytes).is_ok());
+    assert!(Bytecode::from_bytes(&bytes[..bytes.len() / 2]).is_err());
+    assert!(Bytecode::from_bytes(b"GRB").is_err());
+
+    let mut wrong_version = bytes.clone();
+    wrong_version[4] = wrong_version[4].wrapping_add(1);
+    let error = Bytecode::from_bytes(&wrong_version).unwrap_err();
+    assert!(error.contains("not supported"), "{}", error);
+
+    // every op that indexes the constants is checked
+    for op in &[
+        ByteOp::Load(7),
+        ByteOp::Closure(7),
+        ByteOp::GetField(7),
+        ByteOp::SetField(7),
+        ByteOp::MakeStruct(0, 7),
+        ByteOp::Jump(70),
+    ] {
+        let mut bc = compile("let x = 1; x");
+        bc.ops.insert(0, *op);
+        bc.positions.insert(0, Position { line: 0, col: 0 });
+        let error = Bytecode::from_bytes(&bc.to_bytes().unwrap()).unwrap_err();
+        assert!(error.starts_with("op 0"), "{:?}: {}", op, error);
+
+        // disassembling doesn't need the bytecode to be valid
+        bc.disassemble();
+    }
+
+    let mut bc = compile("let x = 1; x");
+    bc.ops.insert(0, ByteOp::Load(7));
+    bc.positions.insert(0, Position { line: 0, col: 0 });
+    assert!(bc.disassemble().contains("Load 7 (out of range)"));
+}
+
+#[test]
+fn op_limit_only_covers_its_own_run() {
+    let bc =
+        compile("let count = (n: I32) -> I32 { let mut i = 0; while i < n { i += 1; }; i }; 0");
+    let mut vm = StackVm::new();
+    assert_number(vm.run_with_limit(bc.clone(), 50), 0.0);
+    assert_number(
+        vm.call_function(&bc, "count", &[Value::Number(1000.0)]),
+        1000.0,
+    );
+
+    let error = vm.run_with_limit(bc.clone(), 5).unwrap_err();
+    assert!(error.contains("Instruction limit exceeded"), "{}", error);
+    assert_number(vm.call_function(&bc, "count", &[Value::Number(10.0)]), 10.0);
+}
+
+#[test]
+fn dup_and_swap() {
+    let swapped = build(
+        &[1.0, 3.0],
+        &[
+            ByteOp::Load(0),
+            ByteOp::Load(1),
+            ByteOp::Swap,
+            ByteOp::Sub,
+            ByteOp::Return,
+        ],
+    );
+    assert_number(StackVm::new().run(swapped.unwrap(), 0), 2.0);
+
+    let squared = build(
+        &[3.0],
+        &[ByteOp::Load(0), ByteOp::Dup, ByteOp::Mul, ByteOp::Return],
+    );
+    assert_number(StackVm::new().run(squared.unwrap(), 0), 9.0);
+
+    let error = run_error(&[], &[ByteOp::Dup, ByteOp::Return]);
+    assert!(error.ends_with("Stack underflow in Dup"), "{}", error);
+    let error = run_error(&[1.0], &[ByteOp::Load(0), ByteOp::Swap, ByteOp::Return]);
+    assert!(error.ends_with("Stack underflow in Swap"), "{}", error);
+}
+
+#[test]
+fn pop_runs_fold_into_pop_n() {
+    let ops = [
+        ByteOp::Load(0),
+        ByteOp::Load(1),
+        ByteOp::Load(2),
+        ByteOp::Load(3),
+        ByteOp::Pop,
+        ByteOp::Pop,
+        ByteOp::PopN(1),
+        ByteOp::Return,
+    ];
+    let plain = build(&[1.0, 2.0, 3.0, 4.0], &ops).unwrap();
+    let mut optimized = plain.clone();
+    // the last load is dropped with the pop after it and the pops that are left become one
+    assert_eq!(optimized.optimize(), 3);
+    assert_eq!(optimized.ops().len(), plain.ops().len() - 3);
+    assert!(matches!(optimized.ops()[3], ByteOp::PopN(2)));
+
+    assert_number(StackVm::new().run(plain, 0), 1.0);
+    assert_number(StackVm::new().run(optimized, 0), 1.0);
+
+    // a PopN of nothing is dropped
+    let mut empty = build(&[1.0], &[ByteOp::Load(0), ByteOp::PopN(0), ByteOp::Return]).unwrap();
+    empty.optimize();
+    assert_eq!(empty.ops().len(), 2);
+
+    let error = run_error(&[1.0], &[ByteOp::Load(0), ByteOp::PopN(2), ByteOp::Return]);
+    assert!(error.ends_with("Stack underflow in PopN"), "{}", error);
+}
+
+#[test]
+fn unvalidated_constant_indices_are_an_error() {
+    // run_from doesn't validate so the vm checks the index itself
+    for op in &[ByteOp::Load(3), ByteOp::Closure(3)] {
+        let mut bc = build(&[1.0], &[ByteOp::Load(0), ByteOp::Return]).unwrap();
+        bc.ops[0] = *op;
+        let error = StackVm::new().run_from(&bc, 0, 0).unwrap_err().to_string();
+        assert!(error.contains("Constant index 3 out of range"), "{}", error);
+    }
+}
+
+#[test]
+fn host_globals_are_visible_to_scripts() {
+    let (mut module, _) = Parser::parse("let result = base_rate * 2; result", None).unwrap();
+    let mut globals = stdlib::get_stdlib_signatures();
+    globals.add_var(
+        "base_rate".to_string(),
+        ast::TypeSignature::Primitive(ast::PrimitiveType::I32),
+    );
+    assert!(ast::semantic::SemanticAnalyzer::analyze(&mut module, None, Some(globals)).is_ok());
+    let pos = module.expressions[0].pos;
+    let bc = Bytecode::new(ast::AstNode {
+        node: ast::Ast::Block(module.expressions),
+        pos,
+        type_sig: None,
+    })
+    .unwrap();
+
+    let mut vm = StackVm::new();
+    vm.define_global("base_rate", Value::Number(3.0));
+    assert_number(vm.run(bc.clone(), 0), 6.0);
+
+    // redefining replaces the value for the next run
+    vm.reset(true);
+    vm.define_global("base_rate", Value::Number(10.0));
+    assert_number(vm.run(bc, 0), 20.0);
+
+    let error = compile_error("base_rate = 2; base_rate");
+    assert!(
+        error.contains("undeclared variable 'base_rate'"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn registered_natives_can_be_called() {
+    let mut vm = StackVm::new();
+    vm.register_native("sqrt", 1, sqrt);
+    assert_number(vm.run(compile("sqrt(16)"), 0), 4.0);
+    assert_number(vm.run(compile("1 + sqrt(sqrt(16))"), 0), 3.0);
+
+    let error = vm.run(compile("sqrt(16, 2)"), 0).unwrap_err().to_string();
+    assert!(
+        error.contains("expects 1 arguments but recieved 2"),
+        "{}",
+        error
+    );
+
+    // an error from the function stops the script with its message
+    vm.reset(false);
+    let error = vm.run(compile("sqrt(0 - 1)"), 0).unwrap_err().to_string();
+    assert!(
+        error.contains("cannot take the square root of -1"),
+        "{}",
+        error
+    );
+
+    // a script's own function of the same name takes priority
+    vm.reset(false);
+    assert_number(
+        vm.run(compile("let sqrt = (x: I32) -> I32 x; sqrt(16)"), 0),
+        16.0,
+    );
+}
+
+#[test]
+fn while_loops_have_a_back_edge_and_an_exit_edge() {
+    let bc = compile("let mut i = 0; while i < 3 { i = i + 1; }; i");
+    let cfg = bc.build_cfg();
+    let targets: Vec<(usize, &ByteOp, usize)> = bc
+        .instructions()
+        .filter_map(|(idx, op, target)| target.map(|t| (idx, op, t)))
+        .collect();
+
+    let (cond_idx, _, exit) = *targets
+        .iter()
+        .find(|(_, op, _)| matches!(op, ByteOp::JumpFalse(_)))
+        .unwrap();
+    let cond_block = cfg.block_of(cond_idx).unwrap();
+    let body_block = cfg.block_of(cond_idx + 1).unwrap();
+    let exit_block = cfg.block_of(exit).unwrap();
+    assert_eq!(
+        cfg.blocks()[cond_block].successors,
+        vec![body_block, exit_block]
+    );
+
+    let (back_idx, _, begin) = *targets
+        .iter()
+        .find(|(idx, op, target)| matches!(op, ByteOp::Jump(_)) && target < idx)
+        .unwrap();
+    let back_block = cfg.block_of(back_idx).unwrap();
+    let begin_block = cfg.block_of(begin).unwrap();
+    assert_eq!(cfg.blocks()[back_block].successors, vec![begin_block]);
+    assert!(cfg.edges().contains(&(back_block, begin_block)));
+    assert!(begin_block <= cond_block);
+
+    assert!(bc.unreachable_ops().is_empty());
+}
+
+#[test]
+fn bytecode_round_trips_through_bytes() {
+    let bc = compile(
+        "let square = (x: I32) -> I32 x * x; let mut total = 0; \
+         for let mut i = 1; i <= 3; i += 1 { total += square(i); }; total",
+    );
+    let loaded = Bytecode::from_bytes(&bc.to_bytes().unwrap()).unwrap();
+    assert_eq!(loaded.disassemble(), bc.disassemble());
+    assert_eq!(loaded.entry_points, bc.entry_points);
+    assert_number(StackVm::new().run(loaded.clone(), 0), 14.0);
+
+    let mut vm = StackVm::new();
+    vm.run(loaded.clone(), 0).unwrap();
+    assert_number(
+        vm.call_function(&loaded, "square", &[Value::Number(5.0)]),
+        25.0,
+    );
+}
+
+#[test]
+fn while_conditions_run_once_per_iteration() {
+    let mut vm = StackVm::new();
+    vm.register_native("tick", 0, tick);
+    assert_number(
+        vm.run(compile("let mut i = 0; while tick() { i = i + 1; }; i"), 0),
+        3.0,
+    );
+    // once for each of the three iterations and once more to stop
+    assert_eq!(TICKS.with(|t| t.get()), 4);
+}
+
+#[test]
+fn lambdas_can_be_stored_and_called() {
+    assert_number(run("let f = (x: I32) -> I32 x + 1; f(2)"), 3.0);
+    assert_number(run("let a = 10; let f = (x: I32) -> I32 x + a; f(2)"), 12.0);
+    assert_number(
+        run("let add = (x: I32) -> I32 (y: I32) -> I32 x + y; let add5 = add(5); add5(3)"),
+        8.0,
+    );
+    assert_number(run("((x: I32) -> I32 x * 2)(4)"), 8.0);
+}
+
+#[test]
+fn lambdas_can_be_passed_to_natives() {
+    let mut vm = StackVm::new();
+    vm.add_fn("apply", 2, apply);
+    assert_number(
+        vm.run(
+            compile("let a = 1; apply((x: I32) -> I32 x + a, 41) + 1"),
+            0,
+        ),
+        43.0,
+    );
+
+    vm.reset(false);
+    let error = vm.run(compile("apply(5, 1)"), 0).unwrap_err().to_string();
+    assert!(error.contains("Only functions can be called"), "{}", error);
+}
+
+#[test]
+fn functions_can_be_called_by_name() {
+    let bc = compile(
+        "let base = 100; let add = (x: I32, y: I32) -> I32 x + y + base; \
+         let neg = (x: I32) -> I32 0 - x; 0",
+    );
+    let mut vm = StackVm::new();
+    vm.run(bc.clone(), 0).unwrap();
+    assert_number(
+        vm.call_function(&bc, "add", &[Value::Number(1.0), Value::Number(2.0)]),
+        103.0,
+    );
+    assert_number(vm.call_function(&bc, "neg", &[Value::Number(3.0)]), -3.0);
+
+    assert_eq!(
+        vm.call_function(&bc, "missing", &[]).unwrap_err(),
+        "Function: missing not defined"
+    );
+    let error = vm.call_function(&bc, "neg", &[]).unwrap_err();
+    assert!(error.contains("expects 1 arguments"), "{}", error);
+}
+
+#[test]
+fn ast_depth_is_limited() {
+    let ast = parse_block("1 + 2 * 3");
+    assert_eq!(ast.depth(), 4);
+    assert_eq!(ast.node_count(), 6);
+
+    // adding on to the left nests one level deeper each time
+    let source = format!("0{}", " + 1".repeat(20));
+    assert_eq!(parse_block(&source).depth(), 22);
+    assert!(Bytecode::with_max_depth(parse_block(&source), 22).is_ok());
+
+    let error = Bytecode::with_max_depth(parse_block(&source), 21)
+        .unwrap_err()
+        .to_string();
+    assert!(
+        error.ends_with("Expression is nested too deeply; depth of 22 exceeds the maximum of 21"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn jumps_too_far_are_a_compile_error() {
+    // two ops a statement puts the end of the body well past what an i16 can jump
+    let source = format!(
+        "let mut i = 0; while i < 1 {{ {} i = 1; }}",
+        "1; ".repeat(20000)
+    );
+    let error = Bytecode::new(parse_block(&source)).unwrap_err().to_string();
+    assert!(error.ends_with("Jump offset out of range"), "{}", error);
+
+    let source = format!(
+        "let mut i = 0; while i < 1 {{ {} i = 1; }}; i",
+        "1; ".repeat(1000)
+    );
+    assert_number(run(&source), 1.0);
+}
+
+#[test]
+fn calls_take_at_most_255_arguments() {
+    let args = |n: usize| vec!["0"; n].join(", ");
+    compile(&format!("f({})", args(255)));
+
+    let error = compile_error(&format!("f({})", args(256)));
+    assert!(
+        error.ends_with("Call has 256 arguments; the maximum is 255"),
+        "{}",
+        error
+    );
+    let error = compile_error(&format!("[f][0]({})", args(300)));
+    assert!(
+        error.ends_with("Call has 300 arguments; the maximum is 255"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn stack_limit_stops_deep_expressions() {
+    // each open bracket leaves one more operand waiting on the stack
+    let nested = |n: usize| format!("{}1{}", "1 + (".repeat(n), ")".repeat(n));
+
+    let mut vm = StackVm::with_stack_limit(8);
+    assert_number(vm.run(compile(&nested(6)), 0), 7.0);
+
+    vm.reset(false);
+    let error = vm.run(compile(&nested(20)), 0).unwrap_err().to_string();
+    assert!(error.ends_with("Stack overflow"), "{}", error);
+
+    // compiling recurses once per level so this needs the 8MB a main thread gets, test threads
+    // only get 2MB
+    let deep = std::thread::Builder::new()
+        .stack_size(8 * 1024 * 1024)
+        .spawn(move || {
+            assert_number(StackVm::new().run(compile(&nested(250)), 0), 251.0);
+            Bytecode::new(parse_block(&nested(300)))
+                .unwrap_err()
+                .to_string()
+        })
+        .unwrap();
+    let error = deep.join().unwrap();
+    assert!(
+        error.contains("Expression is nested too deeply"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn stack_limit_covers_every_push() {
+    let mut vm = StackVm::with_stack_limit(4);
+    vm.add_fn("spread", 1, spread);
+    let error = vm.run(compile("spread(10); 0"), 0).unwrap_err().to_string();
+    assert!(error.ends_with("Stack overflow"), "{}", error);
+
+    // arguments the host passes in count as well, and are taken off again when they don't fit
+    let bc =
+        compile("let sum = (a: I32, b: I32, c: I32, d: I32, e: I32) -> I32 a + b + c + d + e; 0");
+    let mut vm = StackVm::with_stack_limit(4);
+    vm.run(bc.clone(), 0).unwrap();
+    let args: Vec<Value> = (1..=5).map(|n| Value::Number(f64::from(n))).collect();
+    let error = vm.call_function(&bc, "sum", &args).unwrap_err();
+    assert!(error.ends_with("Stack overflow"), "{}", error);
+    assert!(vm.stack().is_empty());
+    let mut vm = StackVm::with_stack_limit(5);
+    vm.run(bc.clone(), 0).unwrap();
+    assert_number(vm.call_function(&bc, "sum", &args), 15.0);
+}
+
+#[test]
+fn field_names_share_a_constant() {
+    let bc =
+        compile("struct P { x: I32 }; let mut p = P { x: 1 }; p.x = p.x + 1; p.x = p.x + 1; p.x");
+    let xs = bc
+        .constants
+        .iter()
+        .filter(|c| match c {
+            Value::Object(o) => o.downcast_ref::<String>().map(String::as_str) == Some("x"),
+            _ => false,
+        })
+        .count();
+    // the accesses reuse the name that follows the struct name for `P { x: 1 }`
+    assert_eq!(xs, 1);
+    assert_number(StackVm::new().run(bc, 0), 3.0);
+}
+
+#[test]
+fn constant_pool_is_limited() {
+    // strings aren't interned so each one takes a slot
+    let source = vec!["\"s\""; u16::MAX as usize + 1].join("; ");
+    compile(&source);
+    let error = compile_error(&format!("{}; \"s\"", source));
+    assert!(
+        error.ends_with("Too many constants; the maximum is 65536"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn identical_literals_share_a_constant() {
+    let bc = compile("let a = 7; let b = 7 + 7; a + b + 1");
+    let sevens = bc
+        .constants
+        .iter()
+        .filter(|c| matches!(c, Value::Number(n) if *n == 7.0))
+        .count();
+    assert_eq!(sevens, 1);
+    assert_eq!(bc.constants.len(), 2);
+    assert_number(StackVm::new().run(bc, 0), 22.0);
+}
+
+#[test]
+fn constants_are_matched_by_their_bits() {
+    let mut bc = compile("0");
+    let zero = bc.intern_constant(Value::Number(0.0));
+    let negative_zero = bc.intern_constant(Value::Number(-0.0));
+    assert_ne!(zero, negative_zero);
+    assert_eq!(bc.intern_constant(Value::Number(-0.0)), negative_zero);
+
+    let nan = bc.intern_constant(Value::Number(f64::NAN));
+    assert_eq!(bc.intern_constant(Value::Number(f64::NAN)), nan);
+
+    // -0.0 only becomes a literal of its own once the negation is folded
+    let literal = |node| ast::AstNode {
+        node,
+        pos: core::Position::new(1, 1),
+        type_sig: None,
+    };
+    assert!(matches!(fold("-0.0"), ast::Ast::Float(n) if n.is_sign_negative()));
+    let block = literal(ast::Ast::Block(vec![
+        literal(ast::Ast::Statement(Box::new(literal(fold("0.0"))))),
+        literal(fold("-0.0")),
+    ]));
+    let bc = Bytecode::new(block).unwrap();
+    assert_eq!(bc.constants.len(), 2);
+    assert!(matches!(
+        StackVm::new().run(bc, 0),
+        Ok(Value::Number(n)) if n == 0.0 && n.is_sign_negative()
+    ));
+}
+
+#[test]
+fn assigning_an_undeclared_variable_is_a_compile_error() {
+    let error = compile_error("let y = 1;\nx = 5");
+    assert!(
+        error.starts_with("2:") && error.ends_with("assignment to undeclared variable 'x'"),
+        "{}",
+        error
+    );
+    // a variable from an outer block is still declared
+    assert_number(run("let mut x = 1; { x = 5; }; x"), 5.0);
+}
+
+#[test]
+fn builder_jumps_make_a_loop() {
+    // let mut n = 4; let mut sum = 0; while n > 0 { sum = sum + n; n = n - 1; }; sum
+    let mut builder = BytecodeBuilder::new();
+    let zero = builder.add_constant(Value::Number(0.0)).unwrap();
+    let one = builder.add_constant(Value::Number(1.0)).unwrap();
+    let four = builder.add_constant(Value::Number(4.0)).unwrap();
+    assert_eq!(builder.add_constant(Value::Number(1.0)), Ok(one));
+
+    builder.push_op(ByteOp::ScopeOpen);
+    builder.push_op(ByteOp::Load(four));
+    builder.push_op(ByteOp::DefMutVar(1));
+    builder.push_op(ByteOp::Load(zero));
+    builder.push_op(ByteOp::DefMutVar(2));
+
+    let begin = builder.current_idx();
+    builder.push_op(ByteOp::GetVar(1));
+    builder.push_op(ByteOp::Load(zero));
+    builder.push_op(ByteOp::Greater);
+    let exit = builder.emit_jump(ByteOp::JumpFalse);
+    for op in &[
+        ByteOp::GetVar(2),
+        ByteOp::GetVar(1),
+        ByteOp::Add,
+        ByteOp::SetVar(2),
+        ByteOp::GetVar(1),
+        ByteOp::Load(one),
+        ByteOp::Sub,
+        ByteOp::SetVar(1),
+    ] {
+        builder.push_op(*op);
+    }
+    let back = builder.emit_jump(ByteOp::Jump);
+    builder.patch_jump_to(back, begin).unwrap();
+    builder.patch_jump(exit).unwrap();
+
+    builder.push_op(ByteOp::GetVar(2));
+    builder.push_op(ByteOp::ScopeClose);
+    builder.push_op(ByteOp::Return);
+
+    let bc = builder.build().unwrap();
+    assert!(matches!(bc.ops[8], ByteOp::JumpFalse(10)));
+    assert!(matches!(bc.ops[17], ByteOp::Jump(-12)));
+    assert_number(StackVm::new().run(bc, 0), 10.0);
+
+    // build checks where the jumps land
+    let mut builder = BytecodeBuilder::new();
+    let jump = builder.emit_jump(ByteOp::Jump);
+    builder.patch_jump_to(jump, 100).unwrap();
+    assert!(builder.build().is_err());
+}
+
+#[test]
+fn equality_never_fails() {
+    let equal = |source: &str| match run(source) {
+        Ok(Value::Bool(b)) => b,
+        other => panic!("expected a bool from {}, got {:?}", source, other),
+    };
+    assert!(equal("\"a\" == \"a\""));
+    assert!(!equal("\"a\" == \"b\""));
+    assert!(equal("[1, [2, 3]] == [1, [2, 3]]"));
+    assert!(!equal("[1, 2] == [1, 2, 3]"));
+    assert!(equal(
+        "struct P { x: I32, y: I32 } P { x: 1, y: 2 } == P { y: 2, x: 1 }"
+    ));
+    assert!(!equal(
+        "struct P { x: I32, y: I32 } P { x: 1, y: 2 } == P { x: 1, y: 3 }"
+    ));
+    assert!(!equal("let f = () -> I32 1; f == f"));
+
+    // the compiler keeps mismatched types apart but hand-built code can still compare them
+    let mut builder = BytecodeBuilder::new();
+    let hi = builder
+        .add_constant(Value::Object(Box::new("hi".to_string())))
+        .unwrap();
+    let one = builder.add_constant(Value::Number(1.0)).unwrap();
+    for op in &[
+        ByteOp::Load(hi),
+        ByteOp::Load(one),
+        ByteOp::Equal,
+        ByteOp::Return,
+    ] {
+        builder.push_op(*op);
+    }
+    let mut vm = StackVm::new();
+    assert!(matches!(
+        vm.run(builder.build().unwrap(), 0),
+        Ok(Value::Bool(false))
+    ));
+    // both operands are gone so nothing they held is still counted
+    assert_eq!(vm.heap_bytes(), 0);
+}
+
+#[test]
+fn operators_on_the_stack() {
+    use ByteOp::*;
+    // constant 0 is 7 and constant 1 is 2
+    let binary = |op: ByteOp| run_ops(&[7.0, 2.0], &[Load(0), Load(1), op, Return]);
+    assert_eq!(binary(Add).unwrap(), "9");
+    assert_eq!(binary(Sub).unwrap(), "5");
+    assert_eq!(binary(Mul).unwrap(), "14");
+    assert_eq!(binary(Div).unwrap(), "3.5");
+    assert_eq!(binary(Greater).unwrap(), "true");
+    assert_eq!(binary(Less).unwrap(), "false");
+    assert_eq!(binary(Equal).unwrap(), "false");
+    assert!(binary(And)
+        .unwrap_err()
+        .ends_with("Unsupported operand type for 'and': number"));
+
+    let logic = |a: ByteOp, b: ByteOp, op: ByteOp| run_ops(&[], &[a, b, op, Return]);
+    assert_eq!(logic(True, False, And).unwrap(), "false");
+    assert_eq!(logic(True, True, And).unwrap(), "true");
+    assert_eq!(logic(False, True, Or).unwrap(), "true");
+    assert_eq!(logic(False, False, Or).unwrap(), "false");
+    assert!(logic(True, Nil, Or)
+        .unwrap_err()
+        .ends_with("Unsupported operand type for 'or': nil"));
+
+    let unary = |ops: &[ByteOp]| run_ops(&[7.0], ops);
+    assert_eq!(unary(&[Load(0), Negate, Return]).unwrap(), "-7");
+    assert_eq!(unary(&[False, Not, Return]).unwrap(), "true");
+    assert!(unary(&[Load(0), Not, Return])
+        .unwrap_err()
+        .ends_with("Unsupported operand type for not: number"));
+    assert!(unary(&[True, Negate, Return])
+        .unwrap_err()
+        .ends_with("Unsupported operand type for negate: bool"));
+
+    let error = run_ops(&[7.0, 0.0], &[Load(0), Load(1), Div, Return]).unwrap_err();
+    assert!(error.ends_with("Division by zero"), "{}", error);
+    let error = run_ops(&[7.0], &[Load(0), True, Sub, Return]).unwrap_err();
+    assert!(error.contains("Cannot subtract"), "{}", error);
+
+    // the condition is popped whether the jump is taken or not
+    for (condition, jump, expected) in &[
+        (True, JumpFalse(2), "7"),
+        (False, JumpFalse(2), "nil"),
+        (True, JumpTrue(2), "nil"),
+        (False, JumpTrue(2), "7"),
+    ] {
+        let ops = [Nil, *condition, *jump, Load(0), Return];
+        assert_eq!(unary(&ops).unwrap(), *expected);
+    }
+    assert!(unary(&[Load(0), JumpFalse(1), Return])
+        .unwrap_err()
+        .ends_with("Unsupported operand type for jump on false: number"));
+
+    for op in &[Add, Sub, Div, Equal, And, Or] {
+        let error = run_ops(&[7.0], &[Load(0), *op, Return]).unwrap_err();
+        assert!(error.contains("Stack underflow"), "{:?}: {}", op, error);
+    }
+    for op in &[Not, Negate, JumpFalse(1), JumpTrue(1)] {
+        let error = run_ops(&[], &[*op, Return]).unwrap_err();
+        assert!(error.contains("Stack underflow"), "{:?}: {}", op, error);
+    }
+}
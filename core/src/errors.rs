@@ -0,0 +1,132 @@
+use super::{Notice, NoticeLevel, Position};
+
+/// An error from one of the stages of turning source into a result, for use by code
+/// embedding graviton as a library. Notices are still used for reporting in the compiler
+/// since a stage can produce more than one of them.
+#[derive(Debug, Clone)]
+pub enum GravitonError {
+    Lex { msg: String, pos: Option<Position> },
+    Parse { msg: String, pos: Option<Position> },
+    Compile { msg: String, pos: Option<Position> },
+    Runtime { msg: String, pos: Option<Position> },
+}
+
+impl GravitonError {
+    pub fn msg(&self) -> &str {
+        match self {
+            GravitonError::Lex { msg, .. }
+            | GravitonError::Parse { msg, .. }
+            | GravitonError::Compile { msg, .. }
+            | GravitonError::Runtime { msg, .. } => msg,
+        }
+    }
+
+    pub fn pos(&self) -> Option<Position> {
+        match self {
+            GravitonError::Lex { pos, .. }
+            | GravitonError::Parse { pos, .. }
+            | GravitonError::Compile { pos, .. }
+            | GravitonError::Runtime { pos, .. } => *pos,
+        }
+    }
+
+    // the name a notice is reported from, compiling to and running bytecode are both done by the vm
+    fn stage_name(&self) -> &'static str {
+        match self {
+            GravitonError::Lex { .. } => "Lexer",
+            GravitonError::Parse { .. } => "Parser",
+            GravitonError::Compile { .. } | GravitonError::Runtime { .. } => "VM",
+        }
+    }
+
+    pub fn to_notice(&self) -> Notice {
+        Notice {
+            from: self.stage_name().to_string(),
+            msg: self.msg().to_string(),
+            pos: self.pos().unwrap_or(Position { line: -1, col: -1 }),
+            file: None,
+            level: NoticeLevel::Error,
+        }
+    }
+}
+
+// tabs are expanded to this many spaces so the marker under a line lines up with it
+const TAB_WIDTH: usize = 4;
+
+/// The source line a position is on with tabs expanded, and a marker line with a `^` under the
+/// position's column and `~` under the rest of the line. A column past the end of the line puts
+/// the caret just after the last character. None if the source doesn't have that line.
+pub fn source_snippet(source: &str, pos: Position) -> Option<(String, String)> {
+    if pos.line < 1 {
+        return None;
+    }
+    let line = source.lines().nth(pos.line as usize - 1)?;
+
+    let mut expanded = String::new();
+    // display width of each char, columns count chars so this maps a column to where it's printed
+    let mut widths = Vec::new();
+    for c in line.chars() {
+        if c == '\t' {
+            expanded.push_str(&" ".repeat(TAB_WIDTH));
+            widths.push(TAB_WIDTH);
+        } else {
+            expanded.push(c);
+            widths.push(1);
+        }
+    }
+
+    let col = (pos.col.max(1) as usize - 1).min(widths.len());
+    let before: usize = widths[..col].iter().sum();
+    let after: usize = widths[col..].iter().sum::<usize>().saturating_sub(1);
+    let marker = format!("{}^{}", "~".repeat(before), "~".repeat(after));
+    Some((expanded, marker))
+}
+
+/// Orders notices for reporting. Each file's notices stay together, in the order the files
+/// first show up, and are sorted by position with exact duplicates dropped.
+pub fn sort_notices(notices: &[Notice]) -> Vec<Notice> {
+    let mut files: Vec<&Option<String>> = Vec::new();
+    for n in notices {
+        if !files.contains(&&n.file) {
+            files.push(&n.file);
+        }
+    }
+    let mut sorted = notices.to_vec();
+    // positions below line 1 mark the end of the file or the whole module so they go last,
+    // the sort is stable so notices at the same position keep the order they were made in
+    sorted.sort_by_key(|n| {
+        let file_idx = files.iter().position(|f| **f == n.file);
+        (file_idx, n.pos.line < 1, n.pos)
+    });
+    sorted.dedup();
+    sorted
+}
+
+// positions below line 1 are used as markers for the end of the file or the whole module
+impl From<&Notice> for GravitonError {
+    fn from(notice: &Notice) -> GravitonError {
+        let msg = notice.msg.clone();
+        let pos = if notice.pos.line > 0 {
+            Some(notice.pos)
+        } else {
+            None
+        };
+        match notice.from.as_str() {
+            "Lexer" => GravitonError::Lex { msg, pos },
+            "Parser" => GravitonError::Parse { msg, pos },
+            "VM" => GravitonError::Runtime { msg, pos },
+            _ => GravitonError::Compile { msg, pos },
+        }
+    }
+}
+
+impl std::fmt::Display for GravitonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.pos() {
+            Some(pos) => write!(f, "{}: {}", pos, self.msg()),
+            None => write!(f, "{}", self.msg()),
+        }
+    }
+}
+
+impl std::error::Error for GravitonError {}
@@ -3,7 +3,11 @@ use serde::{Deserialize, Serialize};
 extern crate colored;
 use colored::*;
 
-#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub mod errors;
+pub use errors::{sort_notices, GravitonError};
+
+// ordered by line then column, so sorting positions puts them in source order
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Position {
     pub line: i32,
     pub col: i32,
@@ -17,7 +21,7 @@ impl Position {
 
 impl std::fmt::Display for Position {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "line: {}, col: {}", self.line, self.col)
+        write!(f, "{}:{}", self.line, self.col)
     }
 }
 
@@ -30,7 +34,7 @@ pub enum NoticeLevel {
     Critical,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Notice {
     pub from: String,
     pub msg: String,
@@ -63,98 +67,26 @@ impl<'a> Notice {
             }
         );
         if self.pos.line > 0 {
-            if let Some(s) = source {
-                if let Some(f) = &self.file {
-                    let mut line = 1;
-                    for l in s.lines() {
-                        if line == self.pos.line {
-                            eprintln!(
-                                "{}\n\tat: {}\n\t{}\n\t{}{}{}",
-                                self.msg,
-                                format!(
-                                    "{}{}:{}:{}{}",
-                                    "[".bold(),
-                                    f,
-                                    self.pos.line,
-                                    self.pos.col,
-                                    "]".bold()
-                                ),
-                                l,
-                                if self.pos.col > 1 {
-                                    String::from("~").repeat((self.pos.col - 1) as usize).red()
-                                } else {
-                                    "".red()
-                                },
-                                "^".red(),
-                                if (self.pos.col as usize) < l.len() {
-                                    String::from("~")
-                                        .repeat(l.len() - self.pos.col as usize)
-                                        .red()
-                                } else {
-                                    "".red()
-                                },
-                            );
-                        }
-                        line += 1;
-                    }
-                } else {
-                    let mut line = 1;
-                    for l in s.lines() {
-                        if line == self.pos.line {
-                            eprintln!(
-                                "{}\n\tat: {}\n\t{}\n\t{}{}{}",
-                                self.msg,
-                                format!(
-                                    "{}Line: {}, Col: {}{}",
-                                    "[".bold(),
-                                    self.pos.line,
-                                    self.pos.col,
-                                    "]".bold()
-                                ),
-                                l,
-                                if self.pos.col > 1 {
-                                    String::from("~").repeat((self.pos.col - 1) as usize).red()
-                                } else {
-                                    "".red()
-                                },
-                                "^".red(),
-                                if (self.pos.col as usize) < l.len() {
-                                    String::from("~")
-                                        .repeat(l.len() - self.pos.col as usize)
-                                        .red()
-                                } else {
-                                    "".red()
-                                },
-                            );
-                        }
-                        line += 1;
-                    }
-                }
-            } else if let Some(f) = &self.file {
-                eprintln!(
-                    "{}\n\tat: {}",
-                    self.msg,
-                    format!(
-                        "{}{}:{}:{}{}",
-                        "[".bold(),
-                        f,
-                        self.pos.line,
-                        self.pos.col,
-                        "]".bold()
-                    ),
-                );
+            let location = if let Some(f) = &self.file {
+                format!("{}{}:{}{}", "[".bold(), f, self.pos, "]".bold())
             } else {
-                eprintln!(
-                    "{}\n\tat: {}",
+                format!(
+                    "{}Line: {}, Col: {}{}",
+                    "[".bold(),
+                    self.pos.line,
+                    self.pos.col,
+                    "]".bold()
+                )
+            };
+            match source.and_then(|s| errors::source_snippet(s, self.pos)) {
+                Some((line, marker)) => eprintln!(
+                    "{}\n\tat: {}\n\t{}\n\t{}",
                     self.msg,
-                    format!(
-                        "{}Line: {}, Col: {}{}",
-                        "[".bold(),
-                        self.pos.line,
-                        self.pos.col,
-                        "]".bold()
-                    ),
-                );
+                    location,
+                    line,
+                    marker.red()
+                ),
+                None => eprintln!("{}\n\tat: {}", self.msg, location),
             }
         } else if self.pos.line == -1 {
             if let Some(f) = &self.file {
@@ -200,6 +132,14 @@ impl<'a> Notice {
     }
 }
 
+/// Turns colored output off when asked to by a flag or the `NO_COLOR` environment variable,
+/// otherwise it's left to `colored` which only colors output going to a terminal
+pub fn init_colors(no_color: bool) {
+    if no_color || std::env::var_os("NO_COLOR").is_some() {
+        colored::control::set_override(false);
+    }
+}
+
 pub fn contains_errors(notices: &[Notice]) -> bool {
     let mut were_errors = false;
     for n in notices {
@@ -24,6 +24,7 @@ lazy_static! {
         m.insert("while", TokenType::KwWhile);
         m.insert("for", TokenType::KwFor);
         m.insert("break", TokenType::KwBreak);
+        m.insert("continue", TokenType::KwContinue);
 
         m.insert("true", TokenType::KwTrue);
         m.insert("false", TokenType::KwFalse);
@@ -37,12 +38,21 @@ lazy_static! {
     };
 }
 
+/// Lexes the whole source, the last token is always an `Eof` positioned at the end of the source
+pub fn tokenize(source: &str) -> Vec<Token<'_>> {
+    let mut lexer = Lexer::new(source);
+    let mut tokens: Vec<Token<'_>> = lexer.by_ref().collect();
+    tokens.push(Token::new(TokenType::Eof, TokenData::None, lexer.start_pos));
+    tokens
+}
+
 #[derive(Debug, Clone)]
 pub struct Lexer<'a> {
     full_source: &'a str,
     source: Option<&'a str>,
     start_pos: Position,
     pos: Position,
+    // byte offset into the source, positions count chars instead so they line up with what an editor shows
     idx: usize,
 }
 
@@ -63,8 +73,10 @@ impl<'a> Lexer<'a> {
                 let mut chars = src.chars();
                 let c = chars.next();
                 self.source = Some(chars.as_str());
-                self.idx += 1;
-                self.pos.col += 1;
+                if let Some(c) = c {
+                    self.idx += c.len_utf8();
+                    self.pos.col += 1;
+                }
                 c
             }
             None => None,
@@ -78,6 +90,13 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    fn peek_next(&self) -> Option<char> {
+        match self.source {
+            Some(src) => src.chars().nth(1),
+            None => None,
+        }
+    }
+
     fn skip_white_space(&mut self) {
         while match self.peek() {
             Some(c) if c == '\n' => {
@@ -93,6 +112,67 @@ impl<'a> Lexer<'a> {
         self.start_pos = self.pos;
     }
 
+    // reads what follows a backslash in a string
+    fn escape_sequence(&mut self) -> Result<char, String> {
+        match self.peek() {
+            Some('n') => {
+                self.advance();
+                Ok('\n')
+            }
+            Some('t') => {
+                self.advance();
+                Ok('\t')
+            }
+            Some('r') => {
+                self.advance();
+                Ok('\r')
+            }
+            Some('0') => {
+                self.advance();
+                Ok('\0')
+            }
+            Some('\\') => {
+                self.advance();
+                Ok('\\')
+            }
+            Some('\"') => {
+                self.advance();
+                Ok('\"')
+            }
+            Some('u') => {
+                self.advance();
+                if self.peek() != Some('{') {
+                    return Err("Expected '{' after unicode escape '\\u'".to_string());
+                }
+                self.advance();
+                let mut digits = String::new();
+                while let Some(c) = self.peek() {
+                    if c == '}' || c == '"' {
+                        break;
+                    }
+                    digits.push(c);
+                    self.advance();
+                }
+                if self.peek() != Some('}') {
+                    return Err("Unterminated unicode escape".to_string());
+                }
+                self.advance();
+                match u32::from_str_radix(&digits, 16)
+                    .ok()
+                    .and_then(std::char::from_u32)
+                {
+                    Some(c) if !digits.is_empty() && digits.len() <= 6 => Ok(c),
+                    _ => Err(format!("invalid unicode escape '\\u{{{}}}'", digits)),
+                }
+            }
+            None => Err("Unterminated string".to_string()),
+            Some(c) => {
+                self.advance();
+                Err(format!("invalid escape sequence '\\{}'", c))
+            }
+        }
+    }
+
     fn check_ident(ident: &str) -> TokenType {
         match IDENT_MAP.get(ident) {
             Some(token_type) => *token_type,
@@ -125,12 +205,12 @@ impl<'a> Lexer<'a> {
                     self.start_pos,
                 )),
                 '[' => Some(Token::new(
-                    TokenType::RBracket,
+                    TokenType::LBracket,
                     TokenData::None,
                     self.start_pos,
                 )),
                 ']' => Some(Token::new(
-                    TokenType::LBracket,
+                    TokenType::RBracket,
                     TokenData::None,
                     self.start_pos,
                 )),
@@ -141,6 +221,11 @@ impl<'a> Lexer<'a> {
                     self.start_pos,
                 )),
                 '.' => Some(Token::new(TokenType::Dot, TokenData::None, self.start_pos)),
+                '?' => Some(Token::new(
+                    TokenType::Question,
+                    TokenData::None,
+                    self.start_pos,
+                )),
                 ';' => Some(Token::new(
                     TokenType::Semicolon,
                     TokenData::None,
@@ -167,7 +252,17 @@ impl<'a> Lexer<'a> {
                     self.start_pos,
                 )),
 
-                '+' => Some(Token::new(TokenType::Plus, TokenData::None, self.start_pos)),
+                '+' => match self.peek() {
+                    Some('=') => {
+                        self.advance();
+                        Some(Token::new(
+                            TokenType::PlusEqual,
+                            TokenData::None,
+                            self.start_pos,
+                        ))
+                    }
+                    _ => Some(Token::new(TokenType::Plus, TokenData::None, self.start_pos)),
+                },
                 '-' => match self.peek() {
                     Some('>') => {
                         self.advance();
@@ -177,14 +272,40 @@ impl<'a> Lexer<'a> {
                             self.start_pos,
                         ))
                     }
+                    Some('=') => {
+                        self.advance();
+                        Some(Token::new(
+                            TokenType::MinusEqual,
+                            TokenData::None,
+                            self.start_pos,
+                        ))
+                    }
                     _ => Some(Token::new(
                         TokenType::Minus,
                         TokenData::None,
                         self.start_pos,
                     )),
                 },
-                '*' => Some(Token::new(TokenType::Star, TokenData::None, self.start_pos)),
+                '*' => match self.peek() {
+                    Some('=') => {
+                        self.advance();
+                        Some(Token::new(
+                            TokenType::StarEqual,
+                            TokenData::None,
+                            self.start_pos,
+                        ))
+                    }
+                    _ => Some(Token::new(TokenType::Star, TokenData::None, self.start_pos)),
+                },
                 '/' => match self.peek() {
+                    Some('=') => {
+                        self.advance();
+                        Some(Token::new(
+                            TokenType::SlashEqual,
+                            TokenData::None,
+                            self.start_pos,
+                        ))
+                    }
                     Some('/') => {
                         self.advance();
                         while let Some(c) = self.peek() {
@@ -196,6 +317,37 @@ impl<'a> Lexer<'a> {
                         }
                         self.get_tok()
                     }
+                    Some('*') => {
+                        self.advance();
+                        // block comments nest so commenting out code that has comments still works
+                        let mut depth = 1;
+                        while depth > 0 {
+                            match self.advance() {
+                                // the newline has already been consumed so the next char starts the line
+                                Some('\n') => {
+                                    self.pos.line += 1;
+                                    self.pos.col = 1;
+                                }
+                                Some('/') if self.peek() == Some('*') => {
+                                    self.advance();
+                                    depth += 1;
+                                }
+                                Some('*') if self.peek() == Some('/') => {
+                                    self.advance();
+                                    depth -= 1;
+                                }
+                                Some(_) => {}
+                                None => {
+                                    return Some(Token::new(
+                                        TokenType::Err,
+                                        TokenData::String("Unterminated block comment".to_string()),
+                                        self.start_pos,
+                                    ))
+                                }
+                            }
+                        }
+                        self.get_tok()
+                    }
                     _ => Some(Token::new(
                         TokenType::Slash,
                         TokenData::None,
@@ -257,40 +409,55 @@ impl<'a> Lexer<'a> {
                 },
 
                 '\"' => {
-                    let start_idx = self.idx;
-                    while let Some(c) = self.peek() {
-                        if c != '\"' {
-                            self.advance();
-                        } else {
-                            break;
+                    // escapes are decoded as the string is read, a bad one is reported once the whole string is read
+                    let mut value = String::new();
+                    let mut error: Option<(String, Position)> = None;
+                    loop {
+                        match self.peek() {
+                            None => {
+                                return Some(Token::new(
+                                    TokenType::Err,
+                                    TokenData::String("Unterminated string".to_string()),
+                                    self.start_pos,
+                                ))
+                            }
+                            Some('\"') => {
+                                self.advance();
+                                break;
+                            }
+                            Some('\\') => {
+                                let escape_pos = self.pos;
+                                self.advance();
+                                match self.escape_sequence() {
+                                    Ok(c) => value.push(c),
+                                    Err(e) => {
+                                        if error.is_none() {
+                                            error = Some((e, escape_pos));
+                                        }
+                                    }
+                                }
+                            }
+                            Some(c) => {
+                                if c == '\n' {
+                                    self.pos.line += 1;
+                                    self.pos.col = 0;
+                                }
+                                value.push(c);
+                                self.advance();
+                            }
                         }
                     }
-                    self.advance();
 
-                    if self.peek().is_none() {
-                        return Some(Token::new(
-                            TokenType::Err,
-                            TokenData::String("Unterminated string".to_string()),
+                    match error {
+                        Some((msg, pos)) => {
+                            Some(Token::new(TokenType::Err, TokenData::String(msg), pos))
+                        }
+                        None => Some(Token::new(
+                            TokenType::String,
+                            TokenData::String(value),
                             self.start_pos,
-                        ));
+                        )),
                     }
-
-                    let slice = match self.full_source.get(start_idx..self.idx - 1) {
-                        Some(s) => s,
-                        None => {
-                            return Some(Token::new(
-                                TokenType::Err,
-                                TokenData::String("Failed to index into source".to_string()),
-                                self.start_pos,
-                            ))
-                        }
-                    };
-
-                    Some(Token::new(
-                        TokenType::String,
-                        TokenData::Str(slice),
-                        self.start_pos,
-                    ))
                 }
 
                 l if l.is_alphabetic() || l == '_' => {
@@ -302,7 +469,7 @@ impl<'a> Lexer<'a> {
                             break;
                         }
                     }
-                    let slice = match self.full_source.get(start_idx - 1..self.idx) {
+                    let slice = match self.full_source.get(start_idx - l.len_utf8()..self.idx) {
                         Some(s) => s,
                         None => {
                             return Some(Token::new(
@@ -325,33 +492,94 @@ impl<'a> Lexer<'a> {
                     }
                 }
 
+                '0' if self.peek() == Some('x') || self.peek() == Some('b') => {
+                    let radix = match self.advance() {
+                        Some('x') => 16,
+                        _ => 2,
+                    };
+                    let mut digits = String::new();
+                    while let Some(c) = self.peek() {
+                        if c.is_digit(radix) {
+                            digits.push(c);
+                        } else if c != '_' {
+                            break;
+                        }
+                        self.advance();
+                    }
+
+                    if digits.is_empty() {
+                        return Some(Token::new(
+                            TokenType::Err,
+                            TokenData::Str("Expected digits after number prefix"),
+                            self.start_pos,
+                        ));
+                    }
+                    if let Some(c) = self.peek() {
+                        if c.is_alphanumeric() {
+                            return Some(Token::new(
+                                TokenType::Err,
+                                TokenData::String(format!("Invalid digit {:?} in number", c)),
+                                self.start_pos,
+                            ));
+                        }
+                    }
+
+                    Some(Token::new(
+                        TokenType::Number,
+                        TokenData::Integer(match i64::from_str_radix(&digits, radix) {
+                            Ok(n) => n,
+                            Err(e) => {
+                                return Some(Token::new(
+                                    TokenType::Err,
+                                    TokenData::String(format!(
+                                        "Failed parse integer form source: {}",
+                                        e
+                                    )),
+                                    self.start_pos,
+                                ))
+                            }
+                        }),
+                        self.start_pos,
+                    ))
+                }
+
                 d if d.is_digit(10) => {
-                    let start_idx = self.idx;
+                    // underscores are only separators so they are left out of the parsed digits
+                    let mut digits = d.to_string();
                     let mut is_float = false;
                     while let Some(c) = self.peek() {
                         if c == '.' {
                             is_float = true;
+                            digits.push(c);
                             self.advance();
                         } else if c.is_digit(10) {
+                            digits.push(c);
                             self.advance();
+                        } else if c == '_' {
+                            self.advance();
+                        } else if (c == 'e' || c == 'E')
+                            && match self.peek_next() {
+                                Some(n) => n.is_ascii_digit() || n == '+' || n == '-',
+                                None => false,
+                            }
+                        {
+                            is_float = true;
+                            digits.push(c);
+                            self.advance();
+                            if let Some(sign) = self.peek() {
+                                if sign == '+' || sign == '-' {
+                                    digits.push(sign);
+                                    self.advance();
+                                }
+                            }
                         } else {
                             break;
                         }
                     }
-                    let slice = match self.full_source.get(start_idx - 1..self.idx) {
-                        Some(s) => s,
-                        None => {
-                            return Some(Token::new(
-                                TokenType::Err,
-                                TokenData::String("Failed to index into source".to_string()),
-                                self.start_pos,
-                            ))
-                        }
-                    };
                     Some(Token::new(
                         TokenType::Number,
                         if is_float {
-                            TokenData::Float(match slice.parse::<f64>() {
+                            TokenData::Float(match digits.parse::<f64>() {
                                 Ok(f) => f,
                                 Err(e) => {
                                     return Some(Token::new(
@@ -365,7 +593,7 @@ impl<'a> Lexer<'a> {
                                 }
                             })
                         } else {
-                            TokenData::Integer(match slice.parse::<i64>() {
+                            TokenData::Integer(match digits.parse::<i64>() {
                                 Ok(f) => f,
                                 Err(e) => {
                                     return Some(Token::new(
@@ -382,9 +610,10 @@ impl<'a> Lexer<'a> {
                         self.start_pos,
                     ))
                 }
-                _ => Some(Token::new(
+                // positions count chars so a multi-byte character only takes up one column
+                c => Some(Token::new(
                     TokenType::Err,
-                    TokenData::Str("Unexpected character"),
+                    TokenData::String(format!("unexpected character '{}'", c)),
                     self.start_pos,
                 )),
             },
@@ -392,3 +621,158 @@ impl<'a> Lexer<'a> {
         }
     }
 }
+
+// yields tokens until the end of the source, no Eof token is produced so `for tok in lexer` ends there
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        self.get_tok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn types(source: &str) -> Vec<TokenType> {
+        Lexer::new(source).map(|t| t.type_).collect()
+    }
+
+    #[test]
+    fn comments_are_skipped() {
+        // nothing after the comment, not even a newline
+        assert_eq!(types("x // hi"), vec![TokenType::Identifier]);
+        assert_eq!(types("// hi"), vec![]);
+        assert_eq!(
+            types("x // hi\ny"),
+            vec![TokenType::Identifier, TokenType::Identifier]
+        );
+
+        assert_eq!(types("/* a /* b */ c */ y"), vec![TokenType::Identifier]);
+        assert_eq!(types("x /* a */"), vec![TokenType::Identifier]);
+
+        let tokens: Vec<Token> = Lexer::new("/* a /* b */ c").collect();
+        assert_eq!(tokens.len(), 1);
+        assert!(matches!(
+            &tokens[0].data,
+            TokenData::String(msg) if msg == "Unterminated block comment"
+        ));
+    }
+
+    #[test]
+    fn block_comments_keep_positions() {
+        let positions =
+            |source: &str| -> Vec<Position> { Lexer::new(source).map(|t| t.pos).collect() };
+        assert_eq!(
+            positions("/* a\n  b */ x"),
+            vec![Position { line: 2, col: 8 }]
+        );
+        assert_eq!(
+            positions("x /* a\n/* b\n*/\n c */  y\nz"),
+            vec![
+                Position { line: 1, col: 1 },
+                Position { line: 4, col: 8 },
+                Position { line: 5, col: 1 },
+            ]
+        );
+    }
+
+    fn integer(source: &str) -> i64 {
+        match Lexer::new(source).get_tok().unwrap().data {
+            TokenData::Integer(n) => n,
+            other => panic!("expected an integer from {}, got {:?}", source, other),
+        }
+    }
+
+    fn float(source: &str) -> f64 {
+        match Lexer::new(source).get_tok().unwrap().data {
+            TokenData::Float(f) => f,
+            other => panic!("expected a float from {}, got {:?}", source, other),
+        }
+    }
+
+    #[test]
+    fn number_literals() {
+        assert_eq!(integer("42"), 42);
+        assert_eq!(integer("0xFF"), 255);
+        assert_eq!(integer("0x_1f"), 31);
+        assert_eq!(integer("0b1010"), 10);
+        assert_eq!(integer("1_000_000"), 1_000_000);
+
+        assert_eq!(float("1.5"), 1.5);
+        assert_eq!(float("1.5e3"), 1500.0);
+        assert_eq!(float("1.5E+2"), 150.0);
+        assert_eq!(float("2e-2"), 0.02);
+        // without digits after it the e is an identifier of its own
+        assert_eq!(types("3e"), vec![TokenType::Number, TokenType::Identifier]);
+
+        let token = Lexer::new("0x").get_tok().unwrap();
+        assert_eq!(token.type_, TokenType::Err);
+        assert!(matches!(
+            token.data,
+            TokenData::Str("Expected digits after number prefix")
+        ));
+        let token = Lexer::new("0b102").get_tok().unwrap();
+        assert_eq!(token.type_, TokenType::Err);
+        assert!(matches!(
+            token.data,
+            TokenData::String(msg) if msg == "Invalid digit '2' in number"
+        ));
+    }
+
+    #[test]
+    fn tokenize_ends_with_eof() {
+        let tokens: Vec<(TokenType, i32, i32)> = tokenize("let x = 1;\n  x + 2 // hi\n")
+            .iter()
+            .map(|t| (t.type_, t.pos.line, t.pos.col))
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                (TokenType::KwLet, 1, 1),
+                (TokenType::Identifier, 1, 5),
+                (TokenType::Equal, 1, 7),
+                (TokenType::Number, 1, 9),
+                (TokenType::Semicolon, 1, 10),
+                (TokenType::Identifier, 2, 3),
+                (TokenType::Plus, 2, 5),
+                (TokenType::Number, 2, 7),
+                (TokenType::Eof, 3, 1),
+            ]
+        );
+
+        let tokens = tokenize("");
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].type_, TokenType::Eof);
+    }
+
+    #[test]
+    fn lexer_is_an_iterator() {
+        // unlike tokenize there is no Eof at the end
+        assert_eq!(
+            types("let x = 1; // c\n x + 2 /* d */"),
+            vec![
+                TokenType::KwLet,
+                TokenType::Identifier,
+                TokenType::Equal,
+                TokenType::Number,
+                TokenType::Semicolon,
+                TokenType::Identifier,
+                TokenType::Plus,
+                TokenType::Number,
+            ]
+        );
+
+        let identifiers = Lexer::new("a + b * c")
+            .filter(|t| t.type_ == TokenType::Identifier)
+            .count();
+        assert_eq!(identifiers, 3);
+
+        let mut seen = 0;
+        for _ in Lexer::new("") {
+            seen += 1;
+        }
+        assert_eq!(seen, 0);
+    }
+}
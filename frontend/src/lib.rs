@@ -1,7 +1,7 @@
 extern crate graviton_ast as ast;
 extern crate graviton_core as core;
 
-use core::{Notice, NoticeLevel, Position};
+use core::{GravitonError, Notice, NoticeLevel, Position};
 
 pub mod lexer;
 pub mod parser;
@@ -6,10 +6,11 @@ use super::{
     {
         lexer::Lexer,
         token::{Token, TokenData, TokenType},
-        Notice, NoticeLevel, Position,
+        GravitonError, Notice, NoticeLevel, Position,
     },
 };
 use memmap::Mmap;
+use std::path::PathBuf;
 
 #[repr(u8)]
 #[derive(Clone, Copy)]
@@ -36,7 +37,7 @@ struct ParseRule {
     precedence: Prec,
 }
 
-const PARSER_RULE_TABLE: [ParseRule; 46] = [
+const PARSER_RULE_TABLE: [ParseRule; 52] = [
     ParseRule {
         prefix: grouping_or_fn,
         infix: call,
@@ -58,9 +59,9 @@ const PARSER_RULE_TABLE: [ParseRule; 46] = [
         precedence: Prec::None,
     }, // TokenType::RCurly
     ParseRule {
-        prefix: nil_func,
-        infix: nil_func,
-        precedence: Prec::None,
+        prefix: array,
+        infix: index,
+        precedence: Prec::Call,
     }, // TokenType::LBracket
     ParseRule {
         prefix: nil_func,
@@ -74,8 +75,8 @@ const PARSER_RULE_TABLE: [ParseRule; 46] = [
     }, // TokenType::Comma
     ParseRule {
         prefix: nil_func,
-        infix: nil_func,
-        precedence: Prec::None,
+        infix: field_access,
+        precedence: Prec::Call,
     }, // TokenType::Dot
     ParseRule {
         prefix: nil_func,
@@ -83,7 +84,7 @@ const PARSER_RULE_TABLE: [ParseRule; 46] = [
         precedence: Prec::None,
     }, // TokenType::Semicolon
     ParseRule {
-        prefix: nil_func,
+        prefix: unary,
         infix: binary,
         precedence: Prec::Term,
     }, // TokenType::Plus
@@ -104,7 +105,27 @@ const PARSER_RULE_TABLE: [ParseRule; 46] = [
     }, // TokenType::Slash
     ParseRule {
         prefix: nil_func,
-        infix: binary,
+        infix: compound_assign,
+        precedence: Prec::Assignment,
+    }, // TokenType::PlusEqual
+    ParseRule {
+        prefix: nil_func,
+        infix: compound_assign,
+        precedence: Prec::Assignment,
+    }, // TokenType::MinusEqual
+    ParseRule {
+        prefix: nil_func,
+        infix: compound_assign,
+        precedence: Prec::Assignment,
+    }, // TokenType::StarEqual
+    ParseRule {
+        prefix: nil_func,
+        infix: compound_assign,
+        precedence: Prec::Assignment,
+    }, // TokenType::SlashEqual
+    ParseRule {
+        prefix: unary,
+        infix: nil_func,
         precedence: Prec::None,
     }, // TokenType::Bang
     ParseRule {
@@ -147,6 +168,11 @@ const PARSER_RULE_TABLE: [ParseRule; 46] = [
         infix: nil_func,
         precedence: Prec::None,
     }, // TokenType::Colon
+    ParseRule {
+        prefix: nil_func,
+        infix: ternary,
+        precedence: Prec::Assignment,
+    }, // TokenType::Question
     ParseRule {
         prefix: nil_func,
         infix: nil_func,
@@ -183,7 +209,7 @@ const PARSER_RULE_TABLE: [ParseRule; 46] = [
         precedence: Prec::None,
     }, // TokenType::KwSelf
     ParseRule {
-        prefix: nil_func,
+        prefix: struct_def,
         infix: nil_func,
         precedence: Prec::None,
     }, // TokenType::KwStruct
@@ -228,15 +254,20 @@ const PARSER_RULE_TABLE: [ParseRule; 46] = [
         precedence: Prec::None,
     }, // TokenType::KwWhile
     ParseRule {
-        prefix: nil_func,
+        prefix: for_,
         infix: nil_func,
         precedence: Prec::None,
     }, // TokenType::KwFor
     ParseRule {
-        prefix: nil_func,
+        prefix: break_,
         infix: nil_func,
         precedence: Prec::None,
     }, // TokenType::KwBreak
+    ParseRule {
+        prefix: continue_,
+        infix: nil_func,
+        precedence: Prec::None,
+    }, // TokenType::KwContinue
     ParseRule {
         prefix: literal,
         infix: nil_func,
@@ -278,8 +309,12 @@ pub struct Parser<'a> {
     current: Token<'a>,
     previous: Token<'a>,
     notices: Vec<Notice>,
+    // set after an error until the parser synchronizes so one mistake isn't reported many times
+    panic_mode: bool,
 
     file_name: Option<&'a str>,
+    // canonical paths of the files whose imports are being parsed, used to catch circular imports
+    importing: Vec<PathBuf>,
 
     prefix_node: AstNode,
 }
@@ -288,17 +323,40 @@ impl<'a> Parser<'a> {
     fn advance(&mut self) {
         self.previous = self.current.clone();
 
-        if let Some(t) = self.lex.get_tok() {
-            self.current = t;
-        } else {
-            self.current = Token::new(
-                TokenType::Eof,
-                TokenData::None,
-                Position { line: -1, col: -1 },
-            );
+        // errors from the lexer are reported where they happened and skipped so the rest of the source still parses
+        loop {
+            if let Some(t) = self.lex.next() {
+                self.current = t;
+            } else {
+                self.current = Token::new(
+                    TokenType::Eof,
+                    TokenData::None,
+                    Position { line: -1, col: -1 },
+                );
+            }
+
+            if self.current.type_ != TokenType::Err {
+                break;
+            }
+            self.lexer_error();
         }
     }
 
+    fn lexer_error(&mut self) {
+        let msg = match &self.current.data {
+            TokenData::String(s) => s.clone(),
+            TokenData::Str(s) => s.to_string(),
+            _ => "Invalid token".to_string(),
+        };
+        self.notices.push(Notice {
+            level: NoticeLevel::Error,
+            pos: self.current.pos,
+            msg,
+            file: self.file_name.map(|name| name.to_string()),
+            from: "Lexer".to_string(),
+        });
+    }
+
     fn consume(&mut self, type_: TokenType, err_msg: &'static str) -> Result<(), Notice> {
         if self.current.type_ == type_ {
             self.advance();
@@ -320,6 +378,35 @@ impl<'a> Parser<'a> {
         source: &'a str,
         file_name: Option<&'a str>,
     ) -> Result<(ast::Module, Vec<Notice>), Vec<Notice>> {
+        Parser::parse_imported(source, file_name, Vec::new())
+    }
+
+    /// `parse` for code using graviton as a library, warnings are left out and every error is
+    /// returned instead of only being reported
+    pub fn parse_module(
+        source: &'a str,
+        file_name: Option<&'a str>,
+    ) -> Result<ast::Module, Vec<GravitonError>> {
+        match Parser::parse(source, file_name) {
+            Ok((module, _)) => Ok(module),
+            Err(notices) => Err(notices
+                .iter()
+                .filter(|n| matches!(n.level, NoticeLevel::Error | NoticeLevel::Critical))
+                .map(GravitonError::from)
+                .collect()),
+        }
+    }
+
+    fn parse_imported(
+        source: &'a str,
+        file_name: Option<&'a str>,
+        mut importing: Vec<PathBuf>,
+    ) -> Result<(ast::Module, Vec<Notice>), Vec<Notice>> {
+        // source that isn't from a file, like a repl line, can't be imported so it's never part of a cycle
+        if let Some(path) = file_name.and_then(|name| std::fs::canonicalize(name).ok()) {
+            importing.push(path);
+        }
+
         let mut p = Parser {
             lex: Lexer::new(source),
             current: Token::new(
@@ -333,8 +420,10 @@ impl<'a> Parser<'a> {
                 Position { line: -1, col: -1 },
             ),
             notices: Vec::new(),
+            panic_mode: false,
 
             file_name,
+            importing,
 
             prefix_node: AstNode {
                 node: Ast::Block(Vec::new()),
@@ -346,8 +435,16 @@ impl<'a> Parser<'a> {
         p.advance();
         let mut exprs: Vec<AstNode> = Vec::new();
         while !p.check(TokenType::Eof) {
-            if let Ok(ast) = maybe_statement_else_expression(&mut p) {
-                exprs.push(ast);
+            if p.skip_empty_statement() {
+                continue;
+            }
+            match maybe_statement_else_expression(&mut p) {
+                Ok(ast) => exprs.push(ast),
+                Err(_) => {
+                    if p.panic_mode {
+                        p.synchronize();
+                    }
+                }
             }
         }
         let _ = p.consume(TokenType::Eof, "Expected EOF");
@@ -388,7 +485,10 @@ impl<'a> Parser<'a> {
             },
             from: "Parser".to_string(),
         };
-        self.notices.push(error.clone());
+        if !self.panic_mode {
+            self.notices.push(error.clone());
+        }
+        self.panic_mode = true;
         error
     }
 
@@ -404,17 +504,23 @@ impl<'a> Parser<'a> {
             },
             from: "Parser".to_string(),
         };
-        self.notices.push(error.clone());
+        if !self.panic_mode {
+            self.notices.push(error.clone());
+        }
+        self.panic_mode = true;
         error
     }
 
     // For error recovery
     fn synchronize(&mut self) {
-        while !(self.check(TokenType::RCurly)
+        self.panic_mode = false;
+        while !(self.check(TokenType::Semicolon)
+            || self.check(TokenType::RCurly)
             // || self.check(TokenType::KwFn)
             || self.check(TokenType::KwStruct)
             || self.check(TokenType::KwLet)
             || self.check(TokenType::KwWhile)
+            || self.check(TokenType::KwFor)
             || self.check(TokenType::KwReturn)
             || self.check(TokenType::KwIf)
             || self.check(TokenType::Eof))
@@ -427,6 +533,16 @@ impl<'a> Parser<'a> {
         }
     }
 
+    // a semicolon with no expression before it, e.g. the second one in `a;;`, doesn't add anything
+    fn skip_empty_statement(&mut self) -> bool {
+        if self.check(TokenType::Semicolon) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
     fn new_node(&self, pos: Position, ast: Ast) -> AstNode {
         AstNode {
             node: ast,
@@ -455,6 +571,14 @@ fn type_signature<'a>(p: &mut Parser<'a>) -> Result<(bool, TypeSignature), Notic
             TokenData::Str(s) => s,
             _ => return Err(p.make_error("Could not read identifier name from token")),
         })
+    } else if p.check(TokenType::LBracket) {
+        p.advance();
+        let elem_type = type_signature(p)?;
+        p.consume(
+            TokenType::RBracket,
+            "Expected right bracket to close array type",
+        )?;
+        TypeSignature::Array(Box::new(elem_type.1))
     } else if p.check(TokenType::LParen) {
         p.advance();
         /*p.consume(
@@ -462,7 +586,10 @@ fn type_signature<'a>(p: &mut Parser<'a>) -> Result<(bool, TypeSignature), Notic
             "Expected opening left parenthesis for function parameters",
         )?;*/
         let mut params: Vec<VariableSignature> = Vec::new();
-        if p.check(TokenType::Identifier) || p.check(TokenType::LParen) {
+        if p.check(TokenType::Identifier)
+            || p.check(TokenType::LParen)
+            || p.check(TokenType::LBracket)
+        {
             let mut type_sig = type_signature(p)?;
 
             params.push(VariableSignature {
@@ -553,11 +680,15 @@ fn parse_precedence<'a>(p: &mut Parser<'a>, precedence: Prec) -> Result<AstNode,
 }
 
 fn maybe_statement_else_expression<'a>(p: &mut Parser<'a>) -> Result<AstNode, Notice> {
-    let start_pos = p.previous.pos;
+    // unlike the parse rules nothing has been consumed yet, the statement starts at the current token
+    let start_pos = p.current.pos;
     let expr = expression(p)?;
     if p.check(TokenType::Semicolon) {
         p.advance();
         Ok(p.new_node(start_pos, Ast::Statement(Box::new(expr))))
+    } else if let Ast::StructDef(..) = expr.node {
+        // struct definitions end with a curly bracket like a block so the semicolon is optional
+        Ok(p.new_node(start_pos, Ast::Statement(Box::new(expr))))
     } else {
         Ok(expr)
     }
@@ -601,9 +732,103 @@ fn identifier<'a>(p: &mut Parser<'a>) -> Result<AstNode, Notice> {
         TokenData::Str(s) => (*s).to_string(),
         _ => return Err(p.make_error("Could not read identifier name from token")),
     };
+
+    // `Name { field: ...` starts a struct initializer, looking for the colon keeps
+    // conditions like `if x { y }` from being read as one
+    let old_lex = p.lex.clone();
+    let old_previous = p.previous.clone();
+    let old_current = p.current.clone();
+    let is_struct_init = if p.check(TokenType::LCurly) {
+        p.advance();
+        if p.check(TokenType::Identifier) {
+            p.advance();
+            p.check(TokenType::Colon)
+        } else {
+            false
+        }
+    } else {
+        false
+    };
+    p.lex = old_lex;
+    p.current = old_current;
+    p.previous = old_previous;
+
+    if is_struct_init {
+        return struct_init(p, start_pos, name);
+    }
     Ok(p.new_node(start_pos, Ast::Identifier(name)))
 }
 
+fn identifier_name<'a>(p: &mut Parser<'a>) -> Result<String, Notice> {
+    match &p.previous.data {
+        TokenData::String(s) => Ok(s.clone()),
+        TokenData::Str(s) => Ok((*s).to_string()),
+        _ => Err(p.make_error("Could not read identifier name from token")),
+    }
+}
+
+fn struct_def<'a>(p: &mut Parser<'a>) -> Result<AstNode, Notice> {
+    let start_pos = p.previous.pos;
+    p.consume(TokenType::Identifier, "Expected identifier for struct name")?;
+    let name = identifier_name(p)?;
+    p.consume(
+        TokenType::LCurly,
+        "Expected left curly bracket to open struct fields",
+    )?;
+
+    let mut fields: Vec<(String, TypeSignature)> = Vec::new();
+    while !p.check(TokenType::RCurly) {
+        p.consume(TokenType::Identifier, "Expected identifier for field name")?;
+        let field = identifier_name(p)?;
+        p.consume(TokenType::Colon, "Expected colon after field name")?;
+        let type_sig = type_signature(p)?;
+        fields.push((field, type_sig.1));
+
+        if !p.check(TokenType::Comma) {
+            break;
+        }
+        p.advance();
+    }
+
+    p.consume(
+        TokenType::RCurly,
+        "Expected right curly bracket to close struct fields",
+    )?;
+
+    Ok(p.new_node(start_pos, Ast::StructDef(name, fields)))
+}
+
+fn struct_init<'a>(
+    p: &mut Parser<'a>,
+    start_pos: Position,
+    name: String,
+) -> Result<AstNode, Notice> {
+    p.consume(
+        TokenType::LCurly,
+        "Expected left curly bracket to open struct initializer",
+    )?;
+
+    let mut fields: Vec<(String, AstNode)> = Vec::new();
+    while !p.check(TokenType::RCurly) {
+        p.consume(TokenType::Identifier, "Expected identifier for field name")?;
+        let field = identifier_name(p)?;
+        p.consume(TokenType::Colon, "Expected colon after field name")?;
+        fields.push((field, expression(p)?));
+
+        if !p.check(TokenType::Comma) {
+            break;
+        }
+        p.advance();
+    }
+
+    p.consume(
+        TokenType::RCurly,
+        "Expected right curly bracket to close struct initializer",
+    )?;
+
+    Ok(p.new_node(start_pos, Ast::StructInit(name, fields)))
+}
+
 fn unary<'a>(p: &mut Parser<'a>) -> Result<AstNode, Notice> {
     let start_pos = p.previous.pos;
     let op = p.previous.type_;
@@ -615,6 +840,7 @@ fn unary<'a>(p: &mut Parser<'a>) -> Result<AstNode, Notice> {
         Ast::Unary(
             match op {
                 TokenType::Minus => UnaryOperation::Negate,
+                TokenType::Plus => UnaryOperation::Plus,
                 TokenType::Bang => UnaryOperation::Not,
                 _ => return Err(p.make_error("Invalid unary operator")),
             },
@@ -628,6 +854,10 @@ fn binary<'a>(p: &mut Parser<'a>) -> Result<AstNode, Notice> {
     let left = p.prefix_node.clone();
     let op = p.previous.type_;
     let right = parse_precedence(p, get_rule(op).precedence)?;
+    // `a < b < c` would end up comparing a bool with a number, so it gets a clearer error here
+    if is_comparison(op) && (is_comparison_node(&left) || is_comparison_node(&right)) {
+        return Err(p.make_error("chained comparison is not allowed"));
+    }
     Ok(p.new_node(
         start_pos,
         Ast::Binary(
@@ -657,6 +887,49 @@ fn binary<'a>(p: &mut Parser<'a>) -> Result<AstNode, Notice> {
     ))
 }
 
+fn is_comparison(op: TokenType) -> bool {
+    matches!(
+        op,
+        TokenType::Less | TokenType::LessEqual | TokenType::Greater | TokenType::GreaterEqual
+    )
+}
+
+fn is_comparison_node(node: &AstNode) -> bool {
+    matches!(
+        node.node,
+        Ast::Binary(BinaryOperation::Less, ..)
+            | Ast::Binary(BinaryOperation::LessEqual, ..)
+            | Ast::Binary(BinaryOperation::Greater, ..)
+            | Ast::Binary(BinaryOperation::GreaterEqual, ..)
+    )
+}
+
+// desugars `x op= e` into `x = x op e`
+fn compound_assign<'a>(p: &mut Parser<'a>) -> Result<AstNode, Notice> {
+    let start_pos = p.previous.pos;
+    let left = p.prefix_node.clone();
+    let op = p.previous.type_;
+    let right = parse_precedence(p, get_rule(op).precedence)?;
+    let value = p.new_node(
+        start_pos,
+        Ast::Binary(
+            match op {
+                TokenType::PlusEqual => BinaryOperation::Add,
+                TokenType::MinusEqual => BinaryOperation::Subtract,
+                TokenType::StarEqual => BinaryOperation::Multiply,
+                TokenType::SlashEqual => BinaryOperation::Divide,
+                _ => return Err(p.make_error("Invalid compound assignment operator")),
+            },
+            Box::new(left.clone()),
+            Box::new(right),
+        ),
+    );
+    Ok(p.new_node(
+        start_pos,
+        Ast::Binary(BinaryOperation::Assign, Box::new(left), Box::new(value)),
+    ))
+}
+
 fn grouping_or_fn<'a>(p: &mut Parser<'a>) -> Result<AstNode, Notice> {
     let start_pos = p.previous.pos;
     let old_lex = p.lex.clone();
@@ -751,7 +1024,20 @@ fn block<'a>(p: &mut Parser<'a>) -> Result<AstNode, Notice> {
     let start_pos = p.previous.pos;
     let mut expr_vec: Vec<AstNode> = Vec::new();
     while !p.check(TokenType::RCurly) {
-        expr_vec.push(maybe_statement_else_expression(p)?);
+        if p.skip_empty_statement() {
+            continue;
+        }
+        match maybe_statement_else_expression(p) {
+            Ok(expr) => expr_vec.push(expr),
+            Err(e) => {
+                if p.check(TokenType::Eof) {
+                    return Err(e);
+                }
+                if p.panic_mode {
+                    p.synchronize();
+                }
+            }
+        }
     }
     p.consume(TokenType::RCurly, "Expected closing right curly bracket")?;
     Ok(p.new_node(start_pos, Ast::Block(expr_vec)))
@@ -787,6 +1073,27 @@ fn if_<'a>(p: &mut Parser<'a>) -> Result<AstNode, Notice> {
     ))
 }
 
+// `cond ? a : b` is shorthand for `if cond a else b`
+fn ternary<'a>(p: &mut Parser<'a>) -> Result<AstNode, Notice> {
+    let start_pos = p.previous.pos;
+    let cond = p.prefix_node.clone();
+    let if_expr = parse_precedence(p, Prec::Assignment)?;
+    p.consume(
+        TokenType::Colon,
+        "Expected colon between conditional expression branches",
+    )?;
+    let else_expr = parse_precedence(p, Prec::Assignment)?;
+    Ok(p.new_node(
+        start_pos,
+        Ast::IfElse(
+            Box::new(cond),
+            Box::new(if_expr),
+            Vec::new(),
+            Some(Box::new(else_expr)),
+        ),
+    ))
+}
+
 fn while_<'a>(p: &mut Parser<'a>) -> Result<AstNode, Notice> {
     let start_pos = p.previous.pos;
     let cond = expression(p)?;
@@ -794,6 +1101,41 @@ fn while_<'a>(p: &mut Parser<'a>) -> Result<AstNode, Notice> {
     Ok(p.new_node(start_pos, Ast::While(Box::new(cond), Box::new(body))))
 }
 
+fn for_<'a>(p: &mut Parser<'a>) -> Result<AstNode, Notice> {
+    let start_pos = p.previous.pos;
+    let init = expression(p)?;
+    p.consume(
+        TokenType::Semicolon,
+        "Expected semicolon after for loop initializer",
+    )?;
+    let cond = expression(p)?;
+    p.consume(
+        TokenType::Semicolon,
+        "Expected semicolon after for loop condition",
+    )?;
+    let step = expression(p)?;
+    let body = expression(p)?;
+    Ok(p.new_node(
+        start_pos,
+        Ast::For(
+            Box::new(init),
+            Box::new(cond),
+            Box::new(step),
+            Box::new(body),
+        ),
+    ))
+}
+
+fn break_<'a>(p: &mut Parser<'a>) -> Result<AstNode, Notice> {
+    let start_pos = p.previous.pos;
+    Ok(p.new_node(start_pos, Ast::Break))
+}
+
+fn continue_<'a>(p: &mut Parser<'a>) -> Result<AstNode, Notice> {
+    let start_pos = p.previous.pos;
+    Ok(p.new_node(start_pos, Ast::Continue))
+}
+
 fn let_<'a>(p: &mut Parser<'a>) -> Result<AstNode, Notice> {
     let start_pos = p.previous.pos;
     let mutable = if p.check(TokenType::KwMut) {
@@ -855,7 +1197,13 @@ fn import<'a>(p: &mut Parser<'a>) -> Result<AstNode, Notice> {
         }
     };
 
-    let result = Parser::parse(
+    if let Ok(path) = std::fs::canonicalize(&name) {
+        if p.importing.contains(&path) {
+            return Err(p.make_error_with_string(format!("Circular import of file {}", name)));
+        }
+    }
+
+    let result = Parser::parse_imported(
         if let Ok(s) = std::str::from_utf8(&mapped_file[..]) {
             &s
         } else {
@@ -864,6 +1212,7 @@ fn import<'a>(p: &mut Parser<'a>) -> Result<AstNode, Notice> {
             );
         },
         Some(&*name),
+        p.importing.clone(),
     );
 
     match result {
@@ -942,6 +1291,10 @@ fn call<'a>(p: &mut Parser<'a>) -> Result<AstNode, Notice> {
 
         while p.check(TokenType::Comma) {
             p.advance();
+            // a trailing comma is allowed so long argument lists can be split over lines
+            if p.check(TokenType::RParen) {
+                break;
+            }
             args.push(expression(p)?);
         }
     }
@@ -954,6 +1307,46 @@ fn call<'a>(p: &mut Parser<'a>) -> Result<AstNode, Notice> {
     Ok(p.new_node(start_pos, Ast::FnCall(Box::new(callee), args)))
 }
 
+fn array<'a>(p: &mut Parser<'a>) -> Result<AstNode, Notice> {
+    let start_pos = p.previous.pos;
+
+    let mut elems: Vec<AstNode> = Vec::new();
+    if !p.check(TokenType::RBracket) {
+        elems.push(expression(p)?);
+
+        while p.check(TokenType::Comma) {
+            p.advance();
+            if p.check(TokenType::RBracket) {
+                break;
+            }
+            elems.push(expression(p)?);
+        }
+    }
+
+    p.consume(
+        TokenType::RBracket,
+        "Expected right bracket to close array elements",
+    )?;
+
+    Ok(p.new_node(start_pos, Ast::Array(elems)))
+}
+
+fn index<'a>(p: &mut Parser<'a>) -> Result<AstNode, Notice> {
+    let start_pos = p.previous.pos;
+    let array = p.prefix_node.clone();
+    let index = expression(p)?;
+    p.consume(TokenType::RBracket, "Expected right bracket to close index")?;
+    Ok(p.new_node(start_pos, Ast::Index(Box::new(array), Box::new(index))))
+}
+
+fn field_access<'a>(p: &mut Parser<'a>) -> Result<AstNode, Notice> {
+    let start_pos = p.previous.pos;
+    let expr = p.prefix_node.clone();
+    p.consume(TokenType::Identifier, "Expected identifier for field name")?;
+    let field = identifier_name(p)?;
+    Ok(p.new_node(start_pos, Ast::FieldAccess(Box::new(expr), field)))
+}
+
 fn as_<'a>(p: &mut Parser<'a>) -> Result<AstNode, Notice> {
     let start_pos = p.previous.pos;
     let casted_node = p.prefix_node.clone();
@@ -966,3 +1359,25 @@ fn as_<'a>(p: &mut Parser<'a>) -> Result<AstNode, Notice> {
 
     Ok(p.new_node(start_pos, Ast::As(Box::new(casted_node), sig)))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_module_returns_graviton_errors() {
+        assert!(Parser::parse_module("let x = 1; x", None).is_ok());
+
+        // every error in the source is returned, not just the first
+        let errors = Parser::parse_module("let x = ;\nlet y = 1 +;", None).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(
+            &errors[1],
+            GravitonError::Parse {
+                pos: Some(Position { line: 2, .. }),
+                ..
+            }
+        ));
+        assert_eq!(errors[0].to_string(), "1:9: Expected prefix expression");
+    }
+}
@@ -19,6 +19,11 @@ pub enum TokenType {
     Star,
     Slash,
 
+    PlusEqual,
+    MinusEqual,
+    StarEqual,
+    SlashEqual,
+
     Bang,
     BangEqual,
     Equal,
@@ -29,6 +34,7 @@ pub enum TokenType {
     LessEqual,
 
     Colon,
+    Question,
     RArrow,
 
     Identifier,
@@ -51,6 +57,7 @@ pub enum TokenType {
     KwWhile,
     KwFor,
     KwBreak,
+    KwContinue,
 
     KwTrue,
     KwFalse,
@@ -6,10 +6,23 @@ pub extern crate graviton_frontend as frontend;
 pub extern crate colored;
 use colored::*;
 
+// past this many errors the rest are only counted, one mistake can cause a lot of follow up errors
+const MAX_REPORTED_ERRORS: usize = 20;
+
 pub fn report_notices<'a>(notices: &[core::Notice], source: Option<&'a str>) {
-    for n in notices {
+    let mut errors = 0;
+    for n in core::sort_notices(notices) {
+        if let core::NoticeLevel::Error | core::NoticeLevel::Critical = n.level {
+            errors += 1;
+            if errors > MAX_REPORTED_ERRORS {
+                continue;
+            }
+        }
         n.report(source);
     }
+    if errors > MAX_REPORTED_ERRORS {
+        eprintln!("... and {} more errors", errors - MAX_REPORTED_ERRORS);
+    }
 }
 
 pub fn parse_source<'a>(
@@ -89,3 +102,214 @@ pub fn compile_source<'a>(
         debug_level,
     )
 }
+
+#[allow(deprecated)]
+pub fn compile_bytecode<'a>(
+    source: &'a str,
+    filename: Option<&'a str>,
+    debug_level: i32,
+) -> Result<backend::vm::Bytecode, Vec<core::Notice>> {
+    let (module, _) = parse_source(source, filename, debug_level)?;
+    let pos = match module.expressions.first() {
+        Some(e) => e.pos,
+        None => core::Position::new(1, 1),
+    };
+    let node = ast::ast::AstNode {
+        node: ast::ast::Ast::Block(module.expressions),
+        pos,
+        type_sig: None,
+    };
+    match backend::vm::Bytecode::new(node) {
+        Ok(bc) => Ok(bc),
+        Err(e) => Err(vec![e.to_notice()]),
+    }
+}
+
+/// Parses and analyzes source that is run a piece at a time, like a line in the repl,
+/// `globals` has what the pieces before it defined and is given what this one defines
+pub fn parse_source_incremental(
+    source: &str,
+    globals: &mut ast::semantic::SemanticStdLib,
+    debug_level: i32,
+) -> Result<(ast::Module, Vec<core::Notice>), Vec<core::Notice>> {
+    let (mut module, parse_notices) = frontend::parser::Parser::parse(source, None)?;
+    match ast::semantic::SemanticAnalyzer::analyze_incremental(&mut module, globals) {
+        Ok(semantic_notices) => {
+            if debug_level >= 2 {
+                println!("{}: {:#?}", "Typed AST".cyan(), module);
+            }
+            Ok((
+                module,
+                parse_notices.into_iter().chain(semantic_notices).collect(),
+            ))
+        }
+        Err(e) => {
+            if debug_level >= 2 {
+                println!("{}: {:#?}", "Untyped AST".red(), module);
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Compiles source onto the end of bytecode that is run a piece at a time with
+/// `StackVm::run_from`, returns the index of the first new op or none if the source has no expressions
+#[allow(deprecated)]
+pub fn append_bytecode(
+    source: &str,
+    bc: &mut backend::vm::Bytecode,
+    globals: &mut ast::semantic::SemanticStdLib,
+    debug_level: i32,
+) -> Result<(Option<usize>, Vec<core::Notice>), Vec<core::Notice>> {
+    let (module, notices) = parse_source_incremental(source, globals, debug_level)?;
+    let pos = match module.expressions.first() {
+        Some(e) => e.pos,
+        None => return Ok((None, notices)),
+    };
+    let node = ast::ast::AstNode {
+        node: ast::ast::Ast::Block(module.expressions),
+        pos,
+        type_sig: None,
+    };
+    match bc.append_global(node) {
+        Ok(start_idx) => Ok((Some(start_idx), notices)),
+        Err(e) => Err(notices
+            .into_iter()
+            .chain(std::iter::once(e.to_notice()))
+            .collect()),
+    }
+}
+
+/// Parses, compiles and runs source on a fresh vm. It is analyzed like a line in the repl so
+/// it can evaluate to a value of any type
+#[allow(deprecated)]
+pub fn eval(source: &str) -> Result<backend::vm::Value, String> {
+    let mut bc = backend::vm::Bytecode::empty();
+    let mut globals = backend::vm::stdlib::get_stdlib_signatures();
+    let start_idx = match append_bytecode(source, &mut bc, &mut globals, 0) {
+        Ok((Some(start_idx), _)) => start_idx,
+        Ok((None, _)) => return Ok(backend::vm::Value::Nil),
+        Err(notices) => {
+            return Err(notices
+                .iter()
+                .filter(|n| {
+                    matches!(
+                        n.level,
+                        core::NoticeLevel::Error | core::NoticeLevel::Critical
+                    )
+                })
+                .map(|n| core::GravitonError::from(n).to_string())
+                .collect::<Vec<String>>()
+                .join("\n"))
+        }
+    };
+    let mut vm = backend::vm::StackVm::new();
+    vm.push_global_scope();
+    vm.run_from(&bc, start_idx, 0)
+        .map_err(|e| e.to_runtime_error().to_string())
+}
+
+#[cfg(test)]
+#[allow(deprecated)]
+mod tests {
+    use super::*;
+    use backend::vm::Value;
+
+    #[test]
+    fn eval_returns_values_of_any_type() {
+        assert!(matches!(eval("1 < 2"), Ok(Value::Bool(true))));
+        assert!(matches!(eval("1.5"), Ok(Value::Number(n)) if n == 1.5));
+        assert_eq!(eval("\"hi\"").unwrap().to_string(), "hi");
+
+        let array = eval("[1, 2 + 3]").unwrap();
+        assert!(matches!(array, Value::Array(_)));
+        assert_eq!(array.to_string(), "[1, 5]");
+    }
+
+    #[test]
+    fn eval_reports_errors() {
+        assert_eq!(
+            eval("let x = 1; x = 2; x").unwrap_err(),
+            "1:12: Variable x not mutable"
+        );
+        assert_eq!(
+            eval("let a = [1]; a[3]").unwrap_err(),
+            "1:15: Array index 3 is out of bounds for length 1"
+        );
+        assert!(matches!(eval(""), Ok(Value::Nil)));
+    }
+
+    fn analysis_errors(source: &str) -> Vec<(String, core::Position)> {
+        match parse_source(source, None, 0) {
+            Ok(_) => vec![],
+            Err(notices) => notices.into_iter().map(|n| (n.msg, n.pos)).collect(),
+        }
+    }
+
+    #[test]
+    fn only_numbers_can_be_ordered() {
+        // a module itself can't evaluate to a Bool
+        assert!(analysis_errors("let a = 1 < 2; let b = 1.5 >= 2.5;").is_empty());
+        assert_eq!(
+            analysis_errors("let c = true < false;"),
+            vec![
+                (
+                    "Left comparison operand must be a number to be ordered; found Bool"
+                        .to_string(),
+                    core::Position::new(1, 9)
+                ),
+                (
+                    "Right comparison operand must be a number to be ordered; found Bool"
+                        .to_string(),
+                    core::Position::new(1, 16)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn returns_match_the_declared_type() {
+        assert!(analysis_errors("let f = (x: I32) -> I32 { return x; }; f(1)").is_empty());
+        assert!(analysis_errors("let f = (x: I32) -> I32 { let y = x; y }; f(1)").is_empty());
+        assert_eq!(
+            analysis_errors("let f = (x: I32) -> I32 { return true; }; f(1)"),
+            vec![
+                (
+                    "Return type does not match the function's return type; expected I32 but got Bool"
+                        .to_string(),
+                    core::Position::new(1, 34)
+                ),
+                (
+                    "Return types not the same; expected I32 but got Bool".to_string(),
+                    core::Position::new(1, 25)
+                ),
+            ]
+        );
+        // the tail of the body counts as a return too
+        assert_eq!(
+            analysis_errors("let f = (x: I32) -> I32 { let y = x; true }; f(1)"),
+            vec![(
+                "Return types not the same; expected I32 but got Bool".to_string(),
+                core::Position::new(1, 25)
+            )]
+        );
+        assert_eq!(
+            analysis_errors("let f = (x: I32) -> I32 { let y = x; }; f(1)"),
+            vec![(
+                "Function must return a value of type I32".to_string(),
+                core::Position::new(1, 25)
+            )]
+        );
+    }
+
+    #[test]
+    fn programs_can_have_several_statements() {
+        let source = "let x = 2;\nlet y = x * 3;\nx + y";
+        let (module, _) = parse_source(source, None, 0).unwrap();
+        assert_eq!(module.expressions.len(), 3);
+
+        let bc = compile_bytecode(source, None, 0).unwrap();
+        let result = backend::vm::StackVm::new().run(bc, 0).unwrap();
+        assert!(matches!(result, Value::Number(n) if n == 8.0));
+    }
+}
@@ -40,12 +40,18 @@ fn main() {
         .help("Input file to process")
         .index(1);
 
+    let no_color_arg = Arg::with_name("No Color")
+        .help("Disables colored output, setting NO_COLOR does the same")
+        .long("no-color")
+        .global(true);
+
     let args = App::new("grav")
         .version(VERSION)
         .author(AUTHOR)
         .about(DESCRIPTION)
         .arg(input_arg.clone())
         .arg(debug_arg.clone())
+        .arg(no_color_arg)
         .arg(
             Arg::with_name("Emit")
                 .help("Emits the specified format [ast, wasm, none]")
@@ -84,6 +90,8 @@ fn main() {
         )
         .get_matches();
 
+    grav::core::init_colors(args.is_present("No Color"));
+
     if let Some(repl_args) = args.subcommand_matches("repl") {
         let debug_level = match repl_args.value_of("Debug Level").unwrap_or("0") {
             "0" => 0,
@@ -1,8 +1,21 @@
 use super::*;
+use grav::ast::semantic::SemanticStdLib;
+#[allow(deprecated)]
+use grav::backend::vm::{stdlib, Bytecode, NumericMode, StackVm};
 use std::io::{BufRead, Write};
 
+#[allow(deprecated)]
 pub fn repl(debug_level_in: i32) -> Result<(), String> {
     let mut debug_level = debug_level_in;
+    let mut use_vm = false;
+    let mut optimize = false;
+    let mut wrap = false;
+
+    // kept for the whole session so definitions from one line are visible on the next
+    let mut vm = StackVm::new();
+    vm.push_global_scope();
+    let mut bc = Bytecode::empty();
+    let mut globals = stdlib::get_stdlib_signatures();
 
     let mut source = String::new();
 
@@ -10,11 +23,21 @@ pub fn repl(debug_level_in: i32) -> Result<(), String> {
         source.clear();
         print!("> ");
         std::io::stdout().flush().unwrap();
-        std::io::stdin()
+        let read = std::io::stdin()
             .lock()
             .read_line(&mut source)
             .expect("Error reading input");
 
+        // end of input, e.g. ctrl-d or the end of a piped script
+        if read == 0 {
+            println!();
+            return Ok(());
+        }
+
+        if source.trim().is_empty() {
+            continue 'repl;
+        }
+
         if !source.is_empty() && source.starts_with(':') {
             if source.len() > 1 {
                 let args: Vec<&str> = source[1..].split_ascii_whitespace().collect();
@@ -33,6 +56,72 @@ pub fn repl(debug_level_in: i32) -> Result<(), String> {
                             debug_level = 0;
                         }
                     }
+                    "vm" => {
+                        use_vm = !use_vm;
+                        println!("VM backend {}", if use_vm { "enabled" } else { "disabled" });
+                    }
+                    "wrap" => {
+                        wrap = !wrap;
+                        vm.set_numeric_mode(if wrap {
+                            NumericMode::IntegerWrap
+                        } else {
+                            NumericMode::Float
+                        });
+                        println!(
+                            "Integer wrapping {}",
+                            if wrap { "enabled" } else { "disabled" }
+                        );
+                    }
+                    "opt" => {
+                        optimize = !optimize;
+                        bc.set_fold_constants(optimize);
+                        println!(
+                            "Bytecode optimization {}",
+                            if optimize { "enabled" } else { "disabled" }
+                        );
+                    }
+                    // the session bytecode only ever holds code that compiled, failed lines are rolled back
+                    "dump" => {
+                        if args.len() < 2 {
+                            eprintln!("{}: Expected a file to dump bytecode to", "Error".red());
+                            continue 'repl;
+                        }
+                        if bc.ops().is_empty() {
+                            eprintln!("{}: No bytecode has been compiled yet", "Error".red());
+                            continue 'repl;
+                        }
+                        match bc.to_bytes().and_then(|bytes| {
+                            std::fs::write(args[1], bytes).map_err(|e| e.to_string())
+                        }) {
+                            Ok(_) => println!("Bytecode written to {}", args[1]),
+                            Err(e) => eprintln!("{}: {}: {}", "Error".red(), args[1], e),
+                        }
+                    }
+                    // runs a file in the current session so its definitions can be used from the prompt
+                    "load" => {
+                        if args.len() < 2 {
+                            eprintln!("{}: Expected a file to load", "Error".red());
+                            continue 'repl;
+                        }
+                        if !use_vm {
+                            eprintln!(
+                                "{}: Loading a file requires the VM backend, enable it with :vm",
+                                "Error".red()
+                            );
+                            continue 'repl;
+                        }
+                        match std::fs::read_to_string(args[1]) {
+                            Ok(file_source) => run_vm(
+                                &mut vm,
+                                &mut bc,
+                                &mut globals,
+                                &file_source,
+                                debug_level,
+                                optimize,
+                            ),
+                            Err(e) => eprintln!("{}: {}: {}", "Error".red(), args[1], e),
+                        }
+                    }
                     s => println!("Invalid command {}", s),
                 }
             } else {
@@ -41,6 +130,18 @@ pub fn repl(debug_level_in: i32) -> Result<(), String> {
             continue 'repl;
         }
 
+        if use_vm {
+            run_vm(
+                &mut vm,
+                &mut bc,
+                &mut globals,
+                &source,
+                debug_level,
+                optimize,
+            );
+            continue 'repl;
+        }
+
         let obj = match grav::compile_source(&source, None, debug_level) {
             Ok(o) => o,
             Err(e) => {
@@ -133,3 +234,60 @@ pub fn repl(debug_level_in: i32) -> Result<(), String> {
         };
     }
 }
+
+#[allow(deprecated)]
+fn run_vm(
+    vm: &mut StackVm,
+    bc: &mut Bytecode,
+    globals: &mut SemanticStdLib,
+    source: &str,
+    debug_level: i32,
+    optimize: bool,
+) {
+    let start_idx = match grav::append_bytecode(source, bc, globals, debug_level) {
+        Ok((Some(start_idx), notices)) => {
+            grav::report_notices(&notices, Some(source));
+            start_idx
+        }
+        Ok((None, notices)) => {
+            grav::report_notices(&notices, Some(source));
+            return;
+        }
+        Err(e) => {
+            grav::report_notices(&e, Some(source));
+            return;
+        }
+    };
+
+    // only the newly added code is optimized, functions from earlier lines still point into the old code
+    if optimize {
+        let removed = bc.optimize_from(start_idx);
+        if debug_level >= 1 {
+            println!("{}: removed {} ops", "Optimized".cyan(), removed);
+        }
+    }
+
+    // code that can never run is usually left behind by a return or break, but can be a codegen bug
+    if debug_level >= 2 {
+        let unreachable = bc.unreachable_ops_from(start_idx);
+        if !unreachable.is_empty() {
+            println!("{}: {:?}", "Unreachable ops".yellow(), unreachable);
+        }
+    }
+
+    // numbers are shown the way rust formats an f64 so a whole number has no decimal point and
+    // nan and infinity show up as `NaN`, `inf` and `-inf`, debug levels show the value's variant instead
+    match vm.run_from(bc, start_idx, debug_level) {
+        Ok(v) if debug_level >= 1 => println!("{}: {:?}", "Result".cyan(), v),
+        Ok(v) => println!("{}", v),
+        Err(e) => grav::report_notices(&[e.to_notice()], Some(source)),
+    }
+
+    // a run should only leave its result behind, anything still on the stack was leaked by codegen
+    if debug_level >= 2 && !vm.stack().is_empty() {
+        println!("{}: {:?}", "Leftover stack".yellow(), vm.stack());
+    }
+
+    // an error can stop a run part way through so anything it left behind is cleared
+    vm.reset(true);
+}
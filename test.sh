@@ -107,6 +107,765 @@ then
   FAILED=1
 fi
 
+cargo run run test/16.grav
+if [ $? -ne 0 ]
+then
+  echo "Failed test 16"
+  FAILED=1
+fi
+
+cargo run run test/17.grav
+if [ $? -ne 0 ]
+then
+  echo "Failed test 17"
+  FAILED=1
+fi
+
+# 16.grav and 17.grav print the same sum, 1 to 7 without 3
+printf ':vm\nlet mut i = 0; let mut sum = 0; while i < 10 { i = i + 1; if i == 3 { continue; }; if i == 8 { break; }; sum = sum + i; }; sum\n:exit\n' | cargo run repl | grep -qx "> 25"
+if [ $? -eq 1 ]
+then
+  echo "Failed while loop sum test"
+  FAILED=1
+fi
+
+printf ':vm\nlet mut sum = 0; for let mut i = 0; i < 10; i = i + 1 { if i == 3 { continue; }; if i == 8 { break; }; sum = sum + i; }; sum\n:exit\n' | cargo run repl | grep -qx "> 25"
+if [ $? -eq 1 ]
+then
+  echo "Failed for loop sum test"
+  FAILED=1
+fi
+
+printf ':vm\nfor let mut i = 0; i < 3; i = i + 1 {};\ni\n:exit\n' | cargo run repl 2>&1 | grep -q "Variable i not found in scope"
+if [ $? -eq 1 ]
+then
+  echo "Failed for loop variable scope test"
+  FAILED=1
+fi
+
+cargo run run test/18.grav
+if [ $? -ne 0 ]
+then
+  echo "Failed test 18"
+  FAILED=1
+fi
+
+printf ':vm\nlet mut x = 10;\nx += 5\nx -= 3\nx *= 2\nx /= 4\n:exit\n' | cargo run repl | tr '\n' ' ' | grep -q "> 15 > 12 > 24 > 6 "
+if [ $? -eq 1 ]
+then
+  echo "Failed compound assignment operators test"
+  FAILED=1
+fi
+
+cargo run run test/19.grav
+if [ $? -ne 0 ]
+then
+  echo "Failed test 19"
+  FAILED=1
+fi
+
+[ "$(printf ':vm\ntrue ? 1 : 2\nlet x = 4;\nx < 3 ? 1 : x == 4 ? 2 + 1 : 4\n:exit\n' | cargo run repl | grep -xE "> [0-9]+" | tr '\n' ' ')" = "> 1 > 3 " ]
+if [ $? -eq 1 ]
+then
+  echo "Failed conditional operator test"
+  FAILED=1
+fi
+
+# + binds tighter than ?, so these are false ? 1 : (2 + 10) and (1 + 1 == 2) ? 3 : 4
+printf ':vm\nfalse ? 1 : 2 + 10\n1 + 1 == 2 ? 3 : 4\n:exit\n' | cargo run repl | tr '\n' ' ' | grep -q "> 12 > 3 "
+if [ $? -eq 1 ]
+then
+  echo "Failed conditional operator precedence test"
+  FAILED=1
+fi
+
+cargo run run test/20.grav
+if [ $? -ne 0 ]
+then
+  echo "Failed test 20"
+  FAILED=1
+fi
+
+printf ':vm\nlet x = 41;\nx + 1\n:exit\n' | cargo run repl | grep -q "42"
+if [ $? -eq 1 ]
+then
+  echo "Failed repl test"
+  FAILED=1
+fi
+
+printf ':vm\nlet x = 5;\nlet f = (a: I32) -> I32 { { { return a; }; }; 7 };\nf(1) + x\n:exit\n' | cargo run repl | grep -q "6"
+if [ $? -eq 1 ]
+then
+  echo "Failed nested return test"
+  FAILED=1
+fi
+
+printf ':vm\nlet x = 1;\nx = 2;\n:exit\n' | cargo run repl 2>&1 | grep -q "not mutable"
+if [ $? -eq 1 ]
+then
+  echo "Failed immutable assignment test"
+  FAILED=1
+fi
+
+printf ':vm\nlet mut x = 1;\nx = 2;\nx\n:exit\n' | cargo run repl | grep -q "2"
+if [ $? -eq 1 ]
+then
+  echo "Failed mutable assignment test"
+  FAILED=1
+fi
+
+printf ':vm\n{}\n{ let x = 1; }\n:exit\n' | cargo run repl | grep -c "nil" | grep -q "2"
+if [ $? -eq 1 ]
+then
+  echo "Failed empty block test"
+  FAILED=1
+fi
+
+printf ':vm\nlet f = (n: I32) -> I32 { if n > 2 { n; }; n + 1 };\n1 + f(1)\n:exit\n' | cargo run repl | grep -q "3"
+if [ $? -eq 1 ]
+then
+  echo "Failed if without else test"
+  FAILED=1
+fi
+
+printf ':vm\nlet y = { let x = 2; x + 1 };\ny\n:exit\n' | cargo run repl | grep -q "3"
+if [ $? -eq 1 ]
+then
+  echo "Failed block value test"
+  FAILED=1
+fi
+
+printf ':vm\n+5\n:exit\n' | cargo run repl | grep -q "5"
+if [ $? -eq 1 ]
+then
+  echo "Failed unary plus test"
+  FAILED=1
+fi
+
+printf ':vm\n+true\n:exit\n' | cargo run repl 2>&1 | grep -q "Unary plus only supports"
+if [ $? -eq 1 ]
+then
+  echo "Failed unary plus type test"
+  FAILED=1
+fi
+
+printf ':vm\nlet f = (x: I32) -> I32 { return x * 7; let y = 5; y };\nf(6)\n:exit\n' | cargo run repl | grep -q "42"
+if [ $? -eq 1 ]
+then
+  echo "Failed unreachable code test"
+  FAILED=1
+fi
+
+printf ':vm\n:opt\n:debug 1\n5; 6; 7\n:exit\n' | cargo run repl | grep -q "removed 4 ops"
+if [ $? -eq 1 ]
+then
+  echo "Failed bytecode optimization test"
+  FAILED=1
+fi
+
+printf ':vm\n:opt\nlet mut i = 0;\nwhile i < 10 { 1; i = i + 1; };\ni\n:exit\n' | cargo run repl | grep -q "10"
+if [ $? -eq 1 ]
+then
+  echo "Failed optimized loop test"
+  FAILED=1
+fi
+
+printf ':vm\n:opt\n:debug 1\n1 + 2 * 3\n:exit\n' | cargo run repl | grep -qF "Load 0 (Number(7.0))"
+if [ $? -eq 1 ]
+then
+  echo "Failed constant folding test"
+  FAILED=1
+fi
+
+printf ':vm\nif 1 > 2 { 1; }\n:exit\n' | cargo run repl | grep -q "nil"
+if [ $? -eq 1 ]
+then
+  echo "Failed if false branch test"
+  FAILED=1
+fi
+
+printf ':vm\n1 @ 2\n:exit\n' | cargo run repl 2>&1 | grep -q "unexpected character '@'"
+if [ $? -eq 1 ]
+then
+  echo "Failed unexpected character test"
+  FAILED=1
+fi
+
+printf ':vm\n/* é */ @\n:exit\n' | cargo run repl 2>&1 | grep -q "Line: 1, Col: 9"
+if [ $? -eq 1 ]
+then
+  echo "Failed unexpected character position test"
+  FAILED=1
+fi
+
+printf ':vm\nlet café = 1 @\n:exit\n' | cargo run repl 2>&1 | grep -q "Line: 1, Col: 14"
+if [ $? -eq 1 ]
+then
+  echo "Failed accented identifier position test"
+  FAILED=1
+fi
+
+printf ':vm\n"x\\"y\\\\z"\n:exit\n' | cargo run repl | grep -q 'x"y\\z'
+if [ $? -eq 1 ]
+then
+  echo "Failed string quote and backslash escape test"
+  FAILED=1
+fi
+
+printf ':vm\n"a\\tb"\n:exit\n' | cargo run repl | grep -q "$(printf 'a\tb')"
+if [ $? -eq 1 ]
+then
+  echo "Failed string tab escape test"
+  FAILED=1
+fi
+
+printf ':vm\n"a\\nb"\n:exit\n' | cargo run repl | grep -q "^b$"
+if [ $? -eq 1 ]
+then
+  echo "Failed string newline escape test"
+  FAILED=1
+fi
+
+printf ':vm\n"\\u{48}i"\n:exit\n' | cargo run repl | grep -q "Hi"
+if [ $? -eq 1 ]
+then
+  echo "Failed string unicode escape test"
+  FAILED=1
+fi
+
+printf ':vm\n"\\q"\n:exit\n' | cargo run repl 2>&1 | grep -q "invalid escape sequence '\\\\q'"
+if [ $? -eq 1 ]
+then
+  echo "Failed invalid escape test"
+  FAILED=1
+fi
+
+printf ':vm\n"abc\n:exit\n' | cargo run repl 2>&1 | grep -q "Unterminated string"
+if [ $? -eq 1 ]
+then
+  echo "Failed unterminated string test"
+  FAILED=1
+fi
+
+printf ':vm\nlet fact = (n: I32) -> I32 if n < 2 { 1 } else { n * fact(n - 1) };\nfact(5)\n:exit\n' | cargo run repl | grep -q "120"
+if [ $? -eq 1 ]
+then
+  echo "Failed recursive factorial test"
+  FAILED=1
+fi
+
+printf ':vm\nlet f = (n: I32) -> I32 f(n + 1);\nf(0)\n:exit\n' | cargo run repl 2>&1 | grep -q "maximum recursion depth exceeded"
+if [ $? -eq 1 ]
+then
+  echo "Failed recursion limit test"
+  FAILED=1
+fi
+
+printf ':vm\n3.9 as I32\n:exit\n' | cargo run repl | grep -q "> 3$"
+if [ $? -eq 1 ]
+then
+  echo "Failed float to integer cast test"
+  FAILED=1
+fi
+
+printf ':vm\n257 as U8\n:exit\n' | cargo run repl | grep -q "> 1$"
+if [ $? -eq 1 ]
+then
+  echo "Failed integer wrapping cast test"
+  FAILED=1
+fi
+
+printf ':vm\n:wrap\n(255 as U8) + (1 as U8)\n:exit\n' | cargo run repl | grep -q "> 0$"
+if [ $? -eq 1 ]
+then
+  echo "Failed integer wrapping mode test"
+  FAILED=1
+fi
+
+printf ':vm\n1 + 1; 2 + 2\n:exit\n' | cargo run repl | grep -q "> 4$"
+if [ $? -eq 1 ]
+then
+  echo "Failed statement sequence test"
+  FAILED=1
+fi
+
+printf ':vm\nlet a = { let x = 4; x * 2;; x + 1 }\na\n:exit\n' | cargo run repl | grep -q "> 5$"
+if [ $? -eq 1 ]
+then
+  echo "Failed block statement sequence test"
+  FAILED=1
+fi
+
+printf ':vm\n{ 1; 2; }\n:exit\n' | cargo run repl | grep -q "> nil$"
+if [ $? -eq 1 ]
+then
+  echo "Failed block trailing statement test"
+  FAILED=1
+fi
+
+printf ':vm\nassert(1 < 2)\n:exit\n' | cargo run repl | grep -q "> nil$"
+if [ $? -eq 1 ]
+then
+  echo "Failed passing assert test"
+  FAILED=1
+fi
+
+printf ':vm\nassert(2 < 1)\n:exit\n' | cargo run repl 2>&1 | grep -q "assertion failed"
+if [ $? -eq 1 ]
+then
+  echo "Failed failing assert test"
+  FAILED=1
+fi
+
+printf ':vm\n1 < 2 < 3\n:exit\n' | cargo run repl 2>&1 | grep -q "chained comparison is not allowed"
+if [ $? -eq 1 ]
+then
+  echo "Failed chained comparison test"
+  FAILED=1
+fi
+
+printf ':vm\nlet mut b = false;\nif b = true { 1 } else { 2 }\n:exit\n' | cargo run repl 2>&1 | grep -q "assignment used as condition"
+if [ $? -eq 1 ]
+then
+  echo "Failed assignment as condition test"
+  FAILED=1
+fi
+
+printf ':vm\n:debug 1\n42\n:exit\n' | cargo run repl | grep -q "Result: Number(42.0)"
+if [ $? -eq 1 ]
+then
+  echo "Failed debug result format test"
+  FAILED=1
+fi
+
+printf ':vm\nlet x = 3;\n:dump grav_repl_dump.gbc\n:exit\n' | cargo run repl | grep -q "Bytecode written" && head -c 4 grav_repl_dump.gbc | grep -q "GRBC"
+if [ $? -eq 1 ]
+then
+  echo "Failed bytecode dump test"
+  FAILED=1
+fi
+rm -f grav_repl_dump.gbc
+
+printf 'let sq = (n: I32) -> I32 {\n    n * n\n};\n' > grav_repl_load.grav
+printf ':vm\n:load grav_repl_load.grav\nsq(7)\n:exit\n' | cargo run repl | grep -q "> 49$"
+if [ $? -eq 1 ]
+then
+  echo "Failed load file test"
+  FAILED=1
+fi
+rm -f grav_repl_load.grav
+
+printf ':vm\n:load grav_missing.grav\n:exit\n' | cargo run repl 2>&1 | grep -q "grav_missing.grav: No such file"
+if [ $? -eq 1 ]
+then
+  echo "Failed load missing file test"
+  FAILED=1
+fi
+
+# no :exit so the repl has to stop by itself once it reaches the end of its input
+printf ':vm\n\n   \n1 + 2\n' | cargo run repl | grep -q "> 3$"
+if [ $? -eq 1 ]
+then
+  echo "Failed blank line and end of input test"
+  FAILED=1
+fi
+
+printf ':vm\n  1 + true\n:exit\n' | cargo run repl 2>&1 | grep -q "Line: 1, Col: 5"
+if [ $? -eq 1 ]
+then
+  echo "Failed binary position test"
+  FAILED=1
+fi
+
+printf ':vm\n:debug 2\nlet x = 1; x + 2;\n:exit\n' | cargo run repl | grep -q "\\[1,12\\]: \\[Nil\\]: Statement("
+if [ $? -eq 1 ]
+then
+  echo "Failed statement position test"
+  FAILED=1
+fi
+
+printf ':vm\nlet f = () -> I32 { 5 };\nf()\n:exit\n' | cargo run repl | grep -q "> 5$"
+if [ $? -eq 1 ]
+then
+  echo "Failed empty argument list test"
+  FAILED=1
+fi
+
+printf ':vm\nlet g = (a: I32, b: I32) -> I32 { a - b };\ng(7, 2,)\n:exit\n' | cargo run repl | grep -q "> 5$"
+if [ $? -eq 1 ]
+then
+  echo "Failed trailing comma argument list test"
+  FAILED=1
+fi
+
+printf ':vm\nlet f = () -> I32 { 5 };\nf(,)\n:exit\n' | cargo run repl 2>&1 | grep -q "Expected prefix expression"
+if [ $? -eq 1 ]
+then
+  echo "Failed lone comma argument list test"
+  FAILED=1
+fi
+
+printf ':vm\nlet x: I32 = 1;\nx\n:exit\n' | cargo run repl | grep -q "> 1$"
+if [ $? -eq 1 ]
+then
+  echo "Failed annotated let test"
+  FAILED=1
+fi
+
+printf ':vm\nlet y = 2;\ny\n:exit\n' | cargo run repl | grep -q "> 2$"
+if [ $? -eq 1 ]
+then
+  echo "Failed unannotated let test"
+  FAILED=1
+fi
+
+printf ':vm\nlet z: Foo = 3;\n:exit\n' | cargo run repl 2>&1 | grep -q "expected Foo but got I32"
+if [ $? -eq 1 ]
+then
+  echo "Failed custom type let test"
+  FAILED=1
+fi
+
+printf ':vm\n!4\n:exit\n' | cargo run repl 2>&1 | grep -q "Unary not expression must evaluate to Bool"
+if [ $? -eq 1 ]
+then
+  echo "Failed not type error test"
+  FAILED=1
+fi
+
+printf ':vm\nlet mut x: I32 = 1; x = x + 2; !(x < 1)\n:exit\n' | cargo run repl | grep -q "> true$"
+if [ $? -eq 1 ]
+then
+  echo "Failed type checked program test"
+  FAILED=1
+fi
+
+printf ':vm\n:debug 2\n1\n:exit\n' | cargo run repl | grep -q "\\[I32\\]: Integer("
+if [ $? -eq 1 ]
+then
+  echo "Failed integer literal test"
+  FAILED=1
+fi
+
+printf ':vm\n:debug 2\n1.0\n:exit\n' | cargo run repl | grep -q "\\[F32\\]: Float("
+if [ $? -eq 1 ]
+then
+  echo "Failed float literal test"
+  FAILED=1
+fi
+
+printf ':vm\nlet c: I32 = 1e3;\n:exit\n' | cargo run repl 2>&1 | grep -q "expected I32 but got F32"
+if [ $? -eq 1 ]
+then
+  echo "Failed exponent literal test"
+  FAILED=1
+fi
+
+printf ':vm\n{ let x = 1; 2 }\n:exit\n' | cargo run repl 2>&1 | grep -q "unused variable 'x'"
+if [ $? -eq 1 ]
+then
+  echo "Failed unused variable test"
+  FAILED=1
+fi
+
+printf ':vm\n{ let x = 1; x }\n:exit\n' | cargo run repl 2>&1 | grep -q "unused variable"
+if [ $? -eq 0 ]
+then
+  echo "Failed used variable test"
+  FAILED=1
+fi
+
+printf ':vm\n{ let _x = 1; 2 }\n:exit\n' | cargo run repl 2>&1 | grep -q "unused variable"
+if [ $? -eq 0 ]
+then
+  echo "Failed underscore variable test"
+  FAILED=1
+fi
+
+printf ':vm\nlet y = y + 1;\n:exit\n' | cargo run repl 2>&1 | grep -q "cannot use 'y' in its own initializer"
+if [ $? -eq 1 ]
+then
+  echo "Failed self-referencing initializer test"
+  FAILED=1
+fi
+
+printf ':vm\n{ let a = 1; let b = { let a = a + 10; { let a = a * 2; a } }; b * 100 + a }\n:exit\n' | cargo run repl | grep -q "2201"
+if [ $? -eq 1 ]
+then
+  echo "Failed nested shadowing test"
+  FAILED=1
+fi
+
+printf ':vm\nlet x = 1;\n{ let x = 2; x }\n:exit\n' | cargo run repl | grep -qx "> 2"
+if [ $? -eq 1 ]
+then
+  echo "Failed redefining an outer variable in a block test"
+  FAILED=1
+fi
+
+# the trace prints the stack before each op, only the block's value may be left when returning
+printf ':vm\n:debug 3\n1 + { let a = 2; let mut b: I32; let c = 3; a + c }\n:exit\n' | cargo run repl | grep -B1 "^Return" | grep -qx "\[Number(6.0)\]"
+if [ $? -eq 1 ]
+then
+  echo "Failed stack balance after let test"
+  FAILED=1
+fi
+
+printf ':vm\n[1, 2, 3]\n:exit\n' | cargo run repl | grep -qx "> \[1, 2, 3\]"
+if [ $? -eq 1 ]
+then
+  echo "Failed array literal test"
+  FAILED=1
+fi
+
+printf ':vm\n{ let a = [4, 5, 6]; a[1] }\n:exit\n' | cargo run repl | grep -qx "> 5"
+if [ $? -eq 1 ]
+then
+  echo "Failed array read test"
+  FAILED=1
+fi
+
+printf ':vm\n{ let a = [4, 5, 6]; a[2] = 9; a }\n:exit\n' | cargo run repl | grep -qx "> \[4, 5, 9\]"
+if [ $? -eq 1 ]
+then
+  echo "Failed array write test"
+  FAILED=1
+fi
+
+printf ':vm\n{ let a = [4, 5, 6]; a[3] }\n:exit\n' | cargo run repl 2>&1 | grep -q "out of bounds for length 3"
+if [ $? -eq 1 ]
+then
+  echo "Failed array out of bounds test"
+  FAILED=1
+fi
+
+printf ':vm\n[1, 2][3 / 2]\n:exit\n' | cargo run repl 2>&1 | grep -q "Array index must be an integer"
+if [ $? -eq 1 ]
+then
+  echo "Failed non-integer array index test"
+  FAILED=1
+fi
+
+printf ':vm\n[1, 2][-1]\n:exit\n' | cargo run repl 2>&1 | grep -q "Array index -1 is out of bounds"
+if [ $? -eq 1 ]
+then
+  echo "Failed negative array index test"
+  FAILED=1
+fi
+
+printf ':vm\n{ let a = [1, 2]; a[-1] = 5 }\n:exit\n' | cargo run repl 2>&1 | grep -q "Array index -1 is out of bounds"
+if [ $? -eq 1 ]
+then
+  echo "Failed negative array index assignment test"
+  FAILED=1
+fi
+
+printf ':vm\n{ let a = [1, 2]; a[3 / 2] = 5 }\n:exit\n' | cargo run repl 2>&1 | grep -q "Array index must be an integer; got 1.5"
+if [ $? -eq 1 ]
+then
+  echo "Failed fractional array index assignment test"
+  FAILED=1
+fi
+
+printf ':vm\n{ let a = [1, 2]; a[2] = 5 }\n:exit\n' | cargo run repl 2>&1 | grep -q "Array index 2 is out of bounds for length 2"
+if [ $? -eq 1 ]
+then
+  echo "Failed array assignment out of bounds test"
+  FAILED=1
+fi
+
+printf ':vm\n[1, 2][1.5]\n:exit\n' | cargo run repl 2>&1 | grep -q "Array index must be an integer"
+if [ $? -eq 1 ]
+then
+  echo "Failed float array index test"
+  FAILED=1
+fi
+
+# 21.grav and 22.grav import each other
+cargo run run test/21.grav 2>&1 | grep -q "Circular import of file test/21.grav"
+if [ $? -eq 1 ]
+then
+  echo "Failed circular import test"
+  FAILED=1
+fi
+
+printf ':vm\nstruct Point { x: I32, y: I32 }\nlet p = Point { x: 1, y: 2 };\np\n:exit\n' | cargo run repl | grep -qx "> Point { x: 1, y: 2 }"
+if [ $? -eq 1 ]
+then
+  echo "Failed struct construction test"
+  FAILED=1
+fi
+
+printf ':vm\nstruct Point { x: I32, y: I32 }\nlet p = Point { x: 1, y: 2 };\np.x + p.y * 10\n:exit\n' | cargo run repl | grep -qx "> 21"
+if [ $? -eq 1 ]
+then
+  echo "Failed struct field read test"
+  FAILED=1
+fi
+
+printf ':vm\nstruct Point { x: I32, y: I32 }\nlet p = Point { x: 1, y: 2 };\np.y = 5;\np.y\n:exit\n' | cargo run repl | grep -qx "> 5"
+if [ $? -eq 1 ]
+then
+  echo "Failed struct field write test"
+  FAILED=1
+fi
+
+printf ':vm\nlet n = 3;\nn.x\n:exit\n' | cargo run repl 2>&1 | grep -q "Cannot access field x of type I32"
+if [ $? -eq 1 ]
+then
+  echo "Failed field access on a non-struct test"
+  FAILED=1
+fi
+
+[ "$(cargo run test/23.grav -e none 2>&1 | grep -o "unused variable '[a-z]'" | tr -d '\n')" = "unused variable 'c'unused variable 'a'unused variable 'b'" ]
+if [ $? -eq 1 ]
+then
+  echo "Failed warnings in source order test"
+  FAILED=1
+fi
+
+cargo run test/23.grav -e none 2>&1 | grep -qF "[test/23.grav:3:5]"
+if [ $? -eq 1 ]
+then
+  echo "Failed file position format test"
+  FAILED=1
+fi
+
+printf ':vm\nlet a: Bool = b;\n:exit\n' | cargo run repl 2>&1 | grep "Error" | head -1 | grep -q "do not match"
+if [ $? -eq 1 ]
+then
+  echo "Failed errors sorted by position test"
+  FAILED=1
+fi
+
+[ "$(printf ':vm\nlet f = (x: Foo) -> Foo x;\n:exit\n' | cargo run repl 2>&1 | grep -c "Type Foo is not defined")" = "1" ]
+if [ $? -eq 1 ]
+then
+  echo "Failed duplicate errors dropped test"
+  FAILED=1
+fi
+
+printf ':vm\nq+q+q+q+q+q+q+q+q+q+q+q+q+q+q+q+q+q+q+q+q+q+q+q+q\n:exit\n' | cargo run repl 2>&1 | grep -qx "\.\.\. and [0-9]* more errors"
+if [ $? -eq 1 ]
+then
+  echo "Failed error count cap test"
+  FAILED=1
+fi
+
+printf ':vm\nlet a = b;\n:exit\n' | cargo run repl 2>&1 | grep -qx "	~~~~~~~~^~"
+if [ $? -eq 1 ]
+then
+  echo "Failed error caret position test"
+  FAILED=1
+fi
+
+printf ':vm\n\tlet a = b;\n:exit\n' | cargo run repl 2>&1 | grep -qx "	~~~~~~~~~~~~^~"
+if [ $? -eq 1 ]
+then
+  echo "Failed error caret after tab test"
+  FAILED=1
+fi
+
+printf ':vm\nlet a = b;\n:exit\n' | CARGO_TERM_COLOR=never CLICOLOR_FORCE=1 cargo run -- repl --no-color 2>&1 | grep -q "$(printf '\033')"
+if [ $? -eq 0 ]
+then
+  echo "Failed no color flag test"
+  FAILED=1
+fi
+
+printf ':vm\nlet a = b;\n:exit\n' | CARGO_TERM_COLOR=never CLICOLOR_FORCE=1 NO_COLOR=1 cargo run repl 2>&1 | grep -q "$(printf '\033')"
+if [ $? -eq 0 ]
+then
+  echo "Failed NO_COLOR test"
+  FAILED=1
+fi
+
+printf ':vm\nlet mut x = 1;\n(x += 2) * 10\nx\n:exit\n' | cargo run repl | tr '\n' ' ' | grep -q "> 30 > 3"
+if [ $? -eq 1 ]
+then
+  echo "Failed compound assignment value test"
+  FAILED=1
+fi
+
+printf ':vm\n:debug 3\n1 + { let mut a = 1; a = 2; a += 3; a }\n:exit\n' | cargo run repl | grep -B1 "^Return" | grep -qx "\[Number(6.0)\]"
+if [ $? -eq 1 ]
+then
+  echo "Failed assignment statement stack balance test"
+  FAILED=1
+fi
+
+printf ':vm\n:debug 2\nlet mut i = 0;\nwhile true { i = 1 + { break; 2 }; }\n:exit\n' | cargo run repl | grep -q "Leftover stack"
+if [ $? -eq 0 ]
+then
+  echo "Failed break leaves no operands test"
+  FAILED=1
+fi
+
+printf ':vm\n:debug 2\n{ let a = 1; a + 2 }\n:exit\n' | cargo run repl | grep -q "Leftover stack"
+if [ $? -eq 0 ]
+then
+  echo "Failed no leftover stack test"
+  FAILED=1
+fi
+
+printf ':vm\nnums("abc");\n:exit\n' | cargo run repl 2>&1 | grep -q "Function: 'nums' returned an error"
+if [ $? -eq 1 ]
+then
+  echo "Failed variable name in runtime error test"
+  FAILED=1
+fi
+
+printf ':vm\n:debug 1\nlet a = 1;\na;\n:exit\n' | cargo run repl | grep -q "GetVar [0-9]* (a)"
+if [ $? -eq 1 ]
+then
+  echo "Failed variable name in disassembly test"
+  FAILED=1
+fi
+
+printf ':vm\nlet mut x;\nx = 5;\nx\n:exit\n' | cargo run repl | grep -qx "> 5"
+if [ $? -eq 1 ]
+then
+  echo "Failed uninitialized mutable variable test"
+  FAILED=1
+fi
+
+printf ':vm\nlet x;\n:exit\n' | cargo run repl 2>&1 | grep -q "Immutable variable x must be given a value"
+if [ $? -eq 1 ]
+then
+  echo "Failed uninitialized immutable variable test"
+  FAILED=1
+fi
+
+# the false branch starts after the jump over it, both branches meet at the return
+[ "$(printf ':vm\n:debug 1\nif 1 < 2 { 10 } else { 20 }\n:exit\n' | cargo run repl | grep -cxE "0004 JumpFalse -> 0010|0009 Jump -> 0014|0014 Return")" = "3" ]
+if [ $? -eq 1 ]
+then
+  echo "Failed resolved jump targets test"
+  FAILED=1
+fi
+
+# nothing jumps past a break so the rest of the loop body can't run
+printf ':vm\n:debug 2\nwhile true { break; 5; };\n:exit\n' | cargo run repl | grep -q "Unreachable ops: \["
+if [ $? -eq 1 ]
+then
+  echo "Failed unreachable ops test"
+  FAILED=1
+fi
+
+printf ':vm\n:debug 2\n1 + 2\n:exit\n' | cargo run repl | grep -q "Unreachable ops"
+if [ $? -eq 0 ]
+then
+  echo "Failed no unreachable ops test"
+  FAILED=1
+fi
+
+printf ':vm\n1 / 0\n:exit\n' | cargo run repl 2>&1 | grep -q "VM Error: Division by zero"
+if [ $? -eq 1 ]
+then
+  echo "Failed division by zero test"
+  FAILED=1
+fi
+
 echo "14" | cargo run run examples/fib.grav
 if [ $? -eq 1 ]
 then